@@ -0,0 +1,158 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+
+use crate::persist::json::JsonPersist;
+use super::classifier::{Classifier, Priors};
+
+#[derive(Serialize, Deserialize)]
+pub struct BernoulliNaiveBayes {
+	/* Naive Bayes over the presence of vocabulary tokens in a document, regardless of how often
+	they occur. Unlike the multinomial model, vocabulary tokens absent from a document count as
+	evidence too, through log(1 - P(token | label)). */
+	document_counts: HashMap<String, usize>,
+	// Number of documents of a label containing each token
+	presence_counts: HashMap<String, HashMap<String, usize>>,
+	vocabulary: BTreeSet<String>,
+	// Additive smoothing of presence probabilities, 1.0 is Laplace smoothing
+	#[serde(default = "default_alpha")]
+	alpha: f64,
+	// Fitted, uniform or user supplied class priors
+	#[serde(flatten)]
+	priors: Priors,
+}
+
+fn default_alpha() -> f64 {
+	return 1.0;
+}
+
+impl JsonPersist for BernoulliNaiveBayes {}
+
+impl Default for BernoulliNaiveBayes {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl BernoulliNaiveBayes {
+	pub fn new() -> Self {
+		return Self {
+			document_counts: HashMap::new(),
+			presence_counts: HashMap::new(),
+			vocabulary: BTreeSet::new(),
+			alpha: default_alpha(),
+			priors: Priors::default(),
+		};
+	}
+
+	pub fn with_alpha(mut self, alpha: f64) -> Self {
+		/* Sets the additive smoothing of presence probabilities, 1.0 by default */
+		assert!(alpha >= 0.0, "Smoothing alpha must not be negative");
+		self.alpha = alpha;
+		return self;
+	}
+
+	pub fn with_priors(mut self, priors: Priors) -> Self {
+		/* Fitted on the training label frequencies by default */
+		self.priors = priors;
+		return self;
+	}
+
+	pub fn train(&mut self, label: String, token_vec: Vec<&str>) {
+		*self.document_counts.entry(label.clone()).or_insert(0) += 1;
+		let presence = self.presence_counts.entry(label).or_default();
+		let tokens: HashSet<&str> = token_vec.into_iter().collect();
+		for token in tokens {
+			*presence.entry(token.to_string()).or_insert(0) += 1;
+			self.vocabulary.insert(token.to_string());
+		}
+	}
+
+	pub fn bulk_train(&mut self, documents: Vec<(String, Vec<&str>)>) {
+		for (label, words) in documents {
+			self.train(label, words);
+		}
+	}
+
+	fn log_likelihood(&self, label: &str, tokens: &HashSet<&str>) -> f64 {
+		/* Sum over the vocabulary of log P(present | label) or log P(absent | label).
+		Tokens outside the vocabulary are skipped. */
+		let documents = *self.document_counts.get(label).unwrap_or(&0) as f64;
+		let presence = self.presence_counts.get(label);
+		let mut log_likelihood = 0.0;
+		for token in &self.vocabulary {
+			let count = presence.and_then(|p| p.get(token)).copied().unwrap_or(0) as f64;
+			let probability = (count + self.alpha) / (documents + 2.0 * self.alpha);
+			log_likelihood += if tokens.contains(token.as_str()) { probability.ln() } else { (1.0 - probability).ln() };
+		}
+		return log_likelihood;
+	}
+}
+
+impl Classifier for BernoulliNaiveBayes {
+	type Sample<'a> = &'a [&'a str];
+
+	fn labels(&self) -> Vec<String> {
+		return self.document_counts.keys().cloned().collect();
+	}
+
+	fn classification_table(&self, token_vec: &[&str]) -> HashMap<String, f64> {
+		let tokens: HashSet<&str> = token_vec.iter().copied().collect();
+		return self.document_counts.keys().map(|label| {
+			let score = self.priors.log_prior(&self.document_counts, label) + self.log_likelihood(label, &tokens);
+			(label.clone(), score)
+		}).collect();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn spam_filter() -> BernoulliNaiveBayes {
+		let mut nb = BernoulliNaiveBayes::new();
+		nb.bulk_train(vec![
+			("spam".to_string(), vec!["buy", "cheap", "cheap", "meds"]),
+			("spam".to_string(), vec!["cheap", "pills"]),
+			("ham".to_string(), vec!["lunch", "tonight"]),
+			("ham".to_string(), vec!["party", "tonight", "cheap"]),
+		]);
+		return nb;
+	}
+
+	#[test]
+	fn test_presence_likelihoods() {
+		let nb = spam_filter();
+		// Vocabulary: buy, cheap, lunch, meds, party, pills, tonight
+		let table = nb.classification_table(&["cheap", "cheap"]);
+		// Repeats do not count, and every absent vocabulary token does
+		assert_eq!(table, nb.classification_table(&["cheap"]));
+		let spam = 0.5f64.ln() + 0.75f64.ln() + 3.0 * 0.5f64.ln() + 3.0 * 0.75f64.ln();
+		assert!((table["spam"] - spam).abs() < 1e-12);
+
+		assert_eq!(nb.classify(&["cheap", "meds"]), Some("spam".to_string()));
+		assert_eq!(nb.classify(&["lunch"]), Some("ham".to_string()));
+		// A missing "tonight", present in every ham document, is evidence against ham
+		assert_eq!(nb.classify(&["cheap"]), Some("spam".to_string()));
+		assert_eq!(nb.classify(&["cheap", "tonight"]), Some("ham".to_string()));
+		let proba = nb.predict_proba(&["cheap"]);
+		assert!((proba.values().sum::<f64>() - 1.0).abs() < 1e-12);
+		assert!((proba["spam"] - 0.75).abs() < 1e-12);
+		assert_eq!(nb.classify_with_threshold(&["cheap"], 0.8), None);
+
+		let ham_only = spam_filter().with_priors(Priors::given(HashMap::from([("spam".to_string(), 0.0), ("ham".to_string(), 1.0)])));
+		assert_eq!(ham_only.classify(&["cheap", "meds", "buy"]), Some("ham".to_string()));
+		let neither = spam_filter().with_priors(Priors::given(HashMap::from([("other".to_string(), 1.0)])));
+		assert_eq!(neither.classify(&["cheap"]), None);
+	}
+
+	#[test]
+	fn test_persistence() {
+		let nb = spam_filter().with_alpha(0.5);
+		let path = "test_bernoulli_nb.json";
+		nb.save_to_file(path, false).unwrap();
+		let loaded = BernoulliNaiveBayes::load_from_file(path).unwrap();
+		std::fs::remove_file(path).unwrap();
+		assert_eq!(loaded.classification_table(&["pills"]), nb.classification_table(&["pills"]));
+		assert_eq!(loaded.alpha, 0.5);
+	}
+}
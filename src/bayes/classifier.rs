@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+pub trait Classifier {
+	/* Probabilistic classifier over labels learned in training.
+	Implementors provide the joint log score log P(label) + log P(sample | label) of every
+	label, classification and normalized posteriors follow from it. */
+
+	// What a sample is made of: tokens for text models, numbers for continuous features
+	type Sample<'a>: Copy;
+
+	fn labels(&self) -> Vec<String>;
+
+	fn classification_table(&self, sample: Self::Sample<'_>) -> HashMap<String, f64>;
+
+	fn classify(&self, sample: Self::Sample<'_>) -> Option<String> {
+		/* The label with the highest score, ties going to the first label in alphabetical order.
+		None when no label is possible: the model has no labels, or every score is -inf, as when
+		user supplied priors give every trained label a probability of 0. NaN scores never win. */
+		let mut best_label: Option<String> = None;
+		let mut best_score = f64::NEG_INFINITY;
+		for (label, score) in self.classification_table(sample) {
+			if score > best_score || (score == best_score && best_label.as_ref().is_some_and(|b| label < *b)) {
+				best_score = score;
+				best_label = Some(label);
+			}
+		}
+		return best_label;
+	}
+
+	fn predict_log_proba(&self, sample: Self::Sample<'_>) -> HashMap<String, f64> {
+		/* Log posterior probability of every label, the scores normalized with log-sum-exp */
+		let table = self.classification_table(sample);
+		let normalizer = log_sum_exp(table.values().copied());
+		return table.into_iter().map(|(label, score)| {
+			let log_proba = if normalizer.is_finite() { score - normalizer } else { f64::NEG_INFINITY };
+			(label, log_proba)
+		}).collect();
+	}
+
+	fn predict_proba(&self, sample: Self::Sample<'_>) -> HashMap<String, f64> {
+		/* Posterior probability of every label, summing to 1 */
+		return self.predict_log_proba(sample).into_iter().map(|(label, p)| (label, p.exp())).collect();
+	}
+
+	fn classify_with_threshold(&self, sample: Self::Sample<'_>, threshold: f64) -> Option<String> {
+		/* The most probable label if its posterior probability reaches threshold, None otherwise */
+		let label = self.classify(sample)?;
+		let probability = self.predict_proba(sample).get(&label).copied().unwrap_or(0.0);
+		if probability < threshold {
+			return None;
+		}
+		return Some(label);
+	}
+}
+
+pub(crate) fn log_sum_exp(values: impl Iterator<Item = f64> + Clone) -> f64 {
+	/* ln(sum(exp(values))) without overflowing or underflowing exp() */
+	let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+	if !max.is_finite() {
+		return max;
+	}
+	return max + values.map(|v| (v - max).exp()).sum::<f64>().ln();
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Priors {
+	/* How a model weighs labels before seeing a sample. Models embed it with #[serde(flatten)],
+	so its fields persist alongside theirs. */
+	// Whether priors follow the class frequencies of the training samples or are uniform
+	#[serde(default = "default_fit_prior")]
+	pub fit_prior: bool,
+	// User supplied class probabilities, overriding fit_prior
+	#[serde(default)]
+	pub class_priors: Option<HashMap<String, f64>>,
+}
+
+fn default_fit_prior() -> bool {
+	return true;
+}
+
+impl Default for Priors {
+	fn default() -> Self {
+		return Self { fit_prior: default_fit_prior(), class_priors: None };
+	}
+}
+
+impl Priors {
+	pub fn uniform() -> Self {
+		return Self { fit_prior: false, ..Self::default() };
+	}
+
+	pub fn given(priors: HashMap<String, f64>) -> Self {
+		/* The given class probabilities, normalized to sum to 1. Trained labels missing from
+		priors get a prior of 0 and are never predicted. */
+		return Self { class_priors: Some(priors), ..Self::default() };
+	}
+
+	pub fn log_prior(&self, counts: &HashMap<String, usize>, label: &str) -> f64 {
+		/* log P(label) from the user supplied priors, from the training counts of every label,
+		or uniform when fit_prior is false */
+		if let Some(priors) = &self.class_priors {
+			let total: f64 = priors.values().sum();
+			return match priors.get(label) {
+				Some(p) if total > 0.0 => (p / total).ln(),
+				_ => f64::NEG_INFINITY,
+			};
+		}
+		if !self.fit_prior {
+			return -(counts.len() as f64).ln();
+		}
+		let samples: usize = counts.values().sum();
+		let count = *counts.get(label).unwrap_or(&0);
+		return (count as f64 / samples as f64).ln();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct Fixed(HashMap<String, f64>);
+
+	impl Classifier for Fixed {
+		type Sample<'a> = ();
+
+		fn labels(&self) -> Vec<String> {
+			return self.0.keys().cloned().collect();
+		}
+
+		fn classification_table(&self, _sample: ()) -> HashMap<String, f64> {
+			return self.0.clone();
+		}
+	}
+
+	fn fixed(scores: &[(&str, f64)]) -> Fixed {
+		return Fixed(scores.iter().map(|(label, score)| (label.to_string(), *score)).collect());
+	}
+
+	#[test]
+	fn test_classify() {
+		assert_eq!(fixed(&[("b", -1.0), ("a", -1.0), ("c", -2.0)]).classify(()), Some("a".to_string()));
+		assert_eq!(fixed(&[("a", f64::NAN), ("b", -5.0)]).classify(()), Some("b".to_string()));
+
+		// Labels exist but none is possible
+		let impossible = fixed(&[("a", f64::NEG_INFINITY), ("b", f64::NEG_INFINITY)]);
+		assert_eq!(impossible.labels().len(), 2);
+		assert_eq!(impossible.classify(()), None);
+		assert!(impossible.predict_proba(()).values().all(|p| *p == 0.0));
+		assert_eq!(impossible.classify_with_threshold((), 0.0), None);
+		assert_eq!(fixed(&[("a", f64::NAN)]).classify(()), None);
+		assert_eq!(fixed(&[]).classify(()), None);
+	}
+
+	#[test]
+	fn test_log_helpers() {
+		assert_eq!(log_sum_exp([1000.0, 1000.0].into_iter()), 1000.0 + 2f64.ln());
+		assert_eq!(log_sum_exp([f64::NEG_INFINITY].into_iter()), f64::NEG_INFINITY);
+
+		let counts = HashMap::from([("a".to_string(), 3), ("b".to_string(), 1)]);
+		assert!((Priors::default().log_prior(&counts, "a") - 0.75f64.ln()).abs() < 1e-12);
+		assert!((Priors::uniform().log_prior(&counts, "b") - 0.5f64.ln()).abs() < 1e-12);
+		let priors = Priors::given(HashMap::from([("a".to_string(), 2.0), ("b".to_string(), 6.0)]));
+		assert!((priors.log_prior(&counts, "b") - 0.75f64.ln()).abs() < 1e-12);
+		assert_eq!(priors.log_prior(&counts, "c"), f64::NEG_INFINITY);
+
+		// Persisted as the fields of the model embedding it, both optional
+		assert_eq!(serde_json::from_str::<Priors>("{}").unwrap(), Priors::default());
+	}
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+use crate::persist::json::JsonPersist;
+use super::classifier::{Classifier, Priors};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct FeatureStats {
+	// Running mean and sum of squared deviations of every feature (Welford's algorithm)
+	mean: Vec<f64>,
+	m2: Vec<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GaussianNaiveBayes {
+	/* Naive Bayes over continuous feature vectors, each feature following a normal distribution
+	per label. Means and variances are updated one sample at a time, so training can continue
+	after loading a saved model. */
+	train_counts: HashMap<String, usize>,
+	stats: HashMap<String, FeatureStats>,
+	// Fraction of the largest feature variance added to every variance, keeping constant features usable
+	#[serde(default = "default_var_smoothing")]
+	var_smoothing: f64,
+	// Fitted, uniform or user supplied class priors
+	#[serde(flatten)]
+	priors: Priors,
+}
+
+fn default_var_smoothing() -> f64 {
+	return 1e-9;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureCountError {
+	// A sample does not have as many features as the samples the model was trained on
+	pub expected: usize,
+	pub found: usize,
+}
+
+impl fmt::Display for FeatureCountError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Sample has {} features, the model expects {}", self.found, self.expected)
+	}
+}
+
+impl std::error::Error for FeatureCountError {}
+
+impl JsonPersist for GaussianNaiveBayes {}
+
+impl Default for GaussianNaiveBayes {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl GaussianNaiveBayes {
+	pub fn new() -> Self {
+		return Self {
+			train_counts: HashMap::new(),
+			stats: HashMap::new(),
+			var_smoothing: default_var_smoothing(),
+			priors: Priors::default(),
+		};
+	}
+
+	pub fn with_var_smoothing(mut self, var_smoothing: f64) -> Self {
+		/* Sets the fraction of the largest variance added to all variances, 1e-9 by default */
+		assert!(var_smoothing >= 0.0, "Variance smoothing must not be negative");
+		self.var_smoothing = var_smoothing;
+		return self;
+	}
+
+	pub fn with_priors(mut self, priors: Priors) -> Self {
+		/* Fitted on the training label frequencies by default */
+		self.priors = priors;
+		return self;
+	}
+
+	pub fn feature_count(&self) -> Option<usize> {
+		/* Length of the feature vectors, None before training */
+		return self.stats.values().next().map(|s| s.mean.len());
+	}
+
+	pub fn train(&mut self, label: String, features: Vec<f64>) -> Result<(), FeatureCountError> {
+		/* Fails without training when features do not match the length of earlier samples */
+		self.check_features(&features)?;
+		let count = self.train_counts.entry(label.clone()).or_insert(0);
+		*count += 1;
+		let n = *count as f64;
+		let stats = self.stats.entry(label).or_insert_with(|| FeatureStats {
+			mean: vec![0.0; features.len()],
+			m2: vec![0.0; features.len()],
+		});
+		for (i, x) in features.into_iter().enumerate() {
+			let delta = x - stats.mean[i];
+			stats.mean[i] += delta / n;
+			stats.m2[i] += delta * (x - stats.mean[i]);
+		}
+		return Ok(());
+	}
+
+	pub fn bulk_train(&mut self, samples: Vec<(String, Vec<f64>)>) -> Result<(), FeatureCountError> {
+		/* Trains on every sample, or on none of them if any has the wrong length */
+		let expected = self.feature_count().or(samples.first().map(|(_, f)| f.len()));
+		if let Some(expected) = expected {
+			if let Some((_, features)) = samples.iter().find(|(_, f)| f.len() != expected) {
+				return Err(FeatureCountError { expected, found: features.len() });
+			}
+		}
+		for (label, features) in samples {
+			self.train(label, features)?;
+		}
+		return Ok(());
+	}
+
+	fn check_features(&self, features: &[f64]) -> Result<(), FeatureCountError> {
+		if let Some(expected) = self.feature_count().filter(|e| *e != features.len()) {
+			return Err(FeatureCountError { expected, found: features.len() });
+		}
+		return Ok(());
+	}
+
+	pub fn mean(&self, label: &str) -> Option<&[f64]> {
+		return self.stats.get(label).map(|s| s.mean.as_slice());
+	}
+
+	pub fn variance(&self, label: &str) -> Option<Vec<f64>> {
+		/* Population variance of every feature, before smoothing */
+		let count = *self.train_counts.get(label)? as f64;
+		return self.stats.get(label).map(|s| s.m2.iter().map(|m2| m2 / count).collect());
+	}
+
+	fn epsilon(&self) -> f64 {
+		/* The variance added to every feature of every label */
+		let largest = self.train_counts.keys().filter_map(|l| self.variance(l)).flatten().fold(0.0, f64::max);
+		// Falls back to an absolute amount when every feature is constant
+		let largest = if largest > 0.0 { largest } else { 1.0 };
+		return self.var_smoothing * largest;
+	}
+
+	pub fn try_classification_table(&self, features: &[f64]) -> Result<HashMap<String, f64>, FeatureCountError> {
+		/* Classifier::classification_table(), failing on features of the wrong length */
+		self.check_features(features)?;
+		return Ok(self.classification_table(features));
+	}
+
+	pub fn try_classify(&self, features: &[f64]) -> Result<Option<String>, FeatureCountError> {
+		/* Classifier::classify(), failing on features of the wrong length instead of returning None */
+		self.check_features(features)?;
+		return Ok(self.classify(features));
+	}
+
+	pub fn try_predict_proba(&self, features: &[f64]) -> Result<HashMap<String, f64>, FeatureCountError> {
+		/* Classifier::predict_proba(), failing on features of the wrong length */
+		self.check_features(features)?;
+		return Ok(self.predict_proba(features));
+	}
+
+	fn log_likelihood(&self, label: &str, features: &[f64], epsilon: f64) -> f64 {
+		/* Sum of the log normal densities of every feature */
+		let (Some(stats), Some(variance)) = (self.stats.get(label), self.variance(label)) else {
+			return f64::NEG_INFINITY;
+		};
+		let mut log_likelihood = 0.0;
+		for ((x, mean), variance) in features.iter().zip(&stats.mean).zip(variance) {
+			// Keeps constant features finite even without smoothing
+			let variance = (variance + epsilon).max(f64::MIN_POSITIVE);
+			log_likelihood -= 0.5 * (2.0 * PI * variance).ln() + (x - mean).powi(2) / (2.0 * variance);
+		}
+		return log_likelihood;
+	}
+}
+
+impl Classifier for GaussianNaiveBayes {
+	type Sample<'a> = &'a [f64];
+
+	fn labels(&self) -> Vec<String> {
+		return self.train_counts.keys().cloned().collect();
+	}
+
+	fn classification_table(&self, features: &[f64]) -> HashMap<String, f64> {
+		/* Empty when features do not have the length of the training samples, so that such
+		samples are not classified. The try_* methods report them as errors instead. */
+		if self.check_features(features).is_err() {
+			return HashMap::new();
+		}
+		let epsilon = self.epsilon();
+		return self.train_counts.keys().map(|label| {
+			let score = self.priors.log_prior(&self.train_counts, label) + self.log_likelihood(label, features, epsilon);
+			(label.clone(), score)
+		}).collect();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn flowers() -> GaussianNaiveBayes {
+		let mut nb = GaussianNaiveBayes::new();
+		nb.bulk_train(vec![
+			("small".to_string(), vec![1.0, 0.2]),
+			("small".to_string(), vec![1.2, 0.4]),
+			("small".to_string(), vec![1.4, 0.3]),
+			("large".to_string(), vec![4.0, 1.8]),
+			("large".to_string(), vec![4.6, 2.2]),
+		]).unwrap();
+		return nb;
+	}
+
+	fn accuracy<'a, C: Classifier>(classifier: &C, samples: &[(C::Sample<'a>, &str)]) -> f64 {
+		let correct = samples.iter().filter(|(s, label)| classifier.classify(*s).as_deref() == Some(*label)).count();
+		return correct as f64 / samples.len() as f64;
+	}
+
+	#[test]
+	fn test_statistics() {
+		let nb = flowers();
+		assert_eq!(nb.feature_count(), Some(2));
+		let mean = nb.mean("small").unwrap();
+		assert!((mean[0] - 1.2).abs() < 1e-12 && (mean[1] - 0.3).abs() < 1e-12);
+		let variance = nb.variance("large").unwrap();
+		assert!((variance[0] - 0.09).abs() < 1e-12 && (variance[1] - 0.04).abs() < 1e-12);
+
+		let table = nb.with_var_smoothing(0.0).classification_table(&[4.0, 2.0]);
+		let density = |x: f64, mean: f64, variance: f64| -0.5 * (2.0 * PI * variance).ln() - (x - mean).powi(2) / (2.0 * variance);
+		let large = 0.4f64.ln() + density(4.0, 4.3, 0.09) + density(2.0, 2.0, 0.04);
+		assert!((table["large"] - large).abs() < 1e-9);
+	}
+
+	#[test]
+	fn test_classify() {
+		let nb = flowers();
+		let samples: Vec<(&[f64], &str)> = vec![(&[1.1, 0.3], "small"), (&[4.2, 2.0], "large"), (&[3.5, 1.5], "large")];
+		assert_eq!(accuracy(&nb, &samples), 1.0);
+
+		let proba = nb.predict_proba(&[1.3, 0.3]);
+		assert!((proba.values().sum::<f64>() - 1.0).abs() < 1e-12);
+		assert!(proba["small"] > 0.99);
+		assert_eq!(nb.classify_with_threshold(&[1.3, 0.3], 0.99), Some("small".to_string()));
+		// Between the clusters the posterior is less certain
+		let between = nb.predict_proba(&[2.2, 0.9]);
+		assert!(between["small"] < proba["small"]);
+
+		// A constant feature still gives finite scores thanks to the smoothing
+		let mut constant = GaussianNaiveBayes::new();
+		constant.bulk_train(vec![("a".to_string(), vec![1.0]), ("a".to_string(), vec![1.0]), ("b".to_string(), vec![2.0])]).unwrap();
+		assert!(constant.classification_table(&[1.0]).values().all(|s| s.is_finite()));
+		assert_eq!(constant.classify(&[1.1]), Some("a".to_string()));
+		// and never NaN without it, the label whose value it is wins
+		let unsmoothed = constant.with_var_smoothing(0.0);
+		assert!(unsmoothed.classification_table(&[1.0]).values().all(|s| !s.is_nan()));
+		assert_eq!(unsmoothed.classify(&[1.0]), Some("a".to_string()));
+		assert_eq!(unsmoothed.classify(&[2.0]), Some("b".to_string()));
+	}
+
+	#[test]
+	fn test_feature_count() {
+		let mut nb = flowers();
+		let error = FeatureCountError { expected: 2, found: 3 };
+		assert_eq!(nb.train("small".to_string(), vec![1.0, 0.2, 0.0]), Err(error.clone()));
+		let samples = vec![("small".to_string(), vec![1.1, 0.2]), ("large".to_string(), vec![4.0, 2.0, 1.0])];
+		assert_eq!(nb.bulk_train(samples), Err(error));
+		// Nothing was trained on
+		assert!((nb.mean("small").unwrap()[0] - 1.2).abs() < 1e-12);
+
+		let error = FeatureCountError { expected: 2, found: 1 };
+		assert_eq!(nb.try_classify(&[1.0]), Err(error.clone()));
+		assert_eq!(nb.try_classification_table(&[1.0]), Err(error.clone()));
+		assert_eq!(nb.try_predict_proba(&[1.0]), Err(error));
+		assert_eq!(nb.try_classify(&[1.1, 0.3]), Ok(Some("small".to_string())));
+		assert_eq!(nb.try_predict_proba(&[1.1, 0.3]), Ok(nb.predict_proba(&[1.1, 0.3])));
+		// The unchecked methods find no label
+		assert!(nb.classification_table(&[1.0]).is_empty());
+		assert_eq!(nb.classify(&[1.0]), None);
+		assert!(nb.predict_proba(&[1.0, 0.2, 0.0]).is_empty());
+		assert_eq!(nb.classify_with_threshold(&[1.0], 0.5), None);
+	}
+
+	#[test]
+	fn test_persistence() {
+		let nb = flowers().with_priors(Priors::uniform());
+		let path = "test_gaussian_nb.json";
+		nb.save_to_file(path, false).unwrap();
+		let mut loaded = GaussianNaiveBayes::load_from_file(path).unwrap();
+		std::fs::remove_file(path).unwrap();
+		assert_eq!(loaded.classification_table(&[2.0, 1.0]), nb.classification_table(&[2.0, 1.0]));
+
+		// Training resumes from the saved statistics
+		loaded.train("large".to_string(), vec![4.3, 2.0]).unwrap();
+		assert!((loaded.mean("large").unwrap()[0] - 4.3).abs() < 1e-12);
+	}
+}
@@ -1 +1,4 @@
-pub mod naive_bayes;
\ No newline at end of file
+pub mod classifier;
+pub mod naive_bayes;
+pub mod bernoulli;
+pub mod gaussian;
@@ -1,30 +1,62 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use serde::{Serialize, Deserialize};
 
 use crate::persist::json::JsonPersist;
+use super::classifier::{Classifier, Priors};
 
 #[derive(Serialize, Deserialize)]
 pub struct NaiveBayes {
 	train_counts: HashMap<String, usize>,
 	token_counts: HashMap<String, HashMap<String, usize>>,
 	total_counts: HashMap<String, usize>,
+	// Additive smoothing, 1.0 is Laplace smoothing and smaller values Lidstone smoothing
+	#[serde(default = "default_alpha")]
+	alpha: f64,
+	// Fitted, uniform or user supplied class priors
+	#[serde(flatten)]
+	priors: Priors,
+}
+
+fn default_alpha() -> f64 {
+	return 1.0;
 }
 
 impl JsonPersist for NaiveBayes {}
 
+impl Default for NaiveBayes {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl NaiveBayes {
 	pub fn new() -> Self {
 		NaiveBayes {
 			train_counts: HashMap::new(),
 			token_counts: HashMap::new(),
 			total_counts: HashMap::new(),
+			alpha: default_alpha(),
+			priors: Priors::default(),
 		}
 	}
 
+	pub fn with_alpha(mut self, alpha: f64) -> Self {
+		/* Sets the additive smoothing of token probabilities, 1.0 by default */
+		assert!(alpha >= 0.0, "Smoothing alpha must not be negative");
+		self.alpha = alpha;
+		return self;
+	}
+
+	pub fn with_priors(mut self, priors: Priors) -> Self {
+		/* Fitted on the training label frequencies by default */
+		self.priors = priors;
+		return self;
+	}
+
 	pub fn train(&mut self, label: String, token_vec: Vec<&str>) {
 		// Increment or insert label into class_counts and token_counts
 		*self.train_counts.entry(label.clone()).or_insert(0) += 1;
-		let word_map = self.token_counts.entry(label.clone()).or_insert_with(HashMap::new);
+		let word_map = self.token_counts.entry(label.clone()).or_default();
 		for token in token_vec {
 			// Increment or insert the token in word_map and update total_counts
 			*word_map.entry(token.to_string()).or_insert(0) += 1;
@@ -39,63 +71,69 @@ impl NaiveBayes {
 		}
 	}
 
-	fn log_prior(&self, label: &String) -> f64 {
-		/* Return the log_prior probability for a label */
-		let log_prior = (*self.train_counts.get(label).unwrap_or(&1) as f64).ln();
-		return log_prior;
+	fn log_prior(&self, label: &str) -> f64 {
+		/* Return log P(label) */
+		return self.priors.log_prior(&self.train_counts, label);
+	}
+
+	fn vocabulary(&self) -> HashSet<&str> {
+		/* Every token seen in training, whatever its class */
+		return self.token_counts.values().flat_map(|t| t.keys().map(|k| k.as_str())).collect();
+	}
+
+	fn log_likelihood(&self, label: &str, token_vec: &[&str], vocabulary: &HashSet<&str>) -> f64 {
+		/* Sum of log P(token | label) with additive smoothing over the vocabulary.
+		Tokens never seen in training carry no evidence for any class and are skipped. */
+		let total = *self.total_counts.get(label).unwrap_or(&0) as f64;
+		let denominator = total + self.alpha * vocabulary.len() as f64;
+		let mut log_likelihood = 0.0;
+		for token in token_vec {
+			if !vocabulary.contains(token) {
+				continue;
+			}
+			let word_freq = self.token_counts.get(label).and_then(|t| t.get(*token)).unwrap_or(&0);
+			log_likelihood += ((*word_freq as f64 + self.alpha) / denominator).ln();
+		}
+		return log_likelihood;
 	}
 
 	pub fn has_label(&self, label: &str) -> bool {
 		return self.train_counts.contains_key(label);
 	}
 
-	pub fn score(&self, label: &String, token_vec: &Vec<&str>) -> Option<f64> {
-		/* Returns the score for a label given a vecor of tokens */
+	pub fn score(&self, label: &str, token_vec: &Vec<&str>) -> Option<f64> {
+		/* Returns log P(label) + log P(tokens | label), the unnormalized log posterior of a label */
 		if !self.has_label(label) {return None;}
+		return Some(self.log_prior(label) + self.log_likelihood(label, token_vec, &self.vocabulary()));
+	}
 
-		let log_prior: f64 = self.log_prior(label);
-		let mut log_likelihood: f64 = 0.0;
+	pub fn classify(&self, token_vec: &[&str]) -> Option<String> {
+		/* Same as Classifier::classify(), callable without importing the trait */
+		return Classifier::classify(self, token_vec);
+	}
 
-		for token in token_vec {
-			let word_freq = self.token_counts.get(label).and_then(|t| t.get(*token)).unwrap_or(&0);
-			let total = self.total_counts.get(label).unwrap_or(&1);
-			let probability = (*word_freq as f64 + 1.0)  / (*total as f64 + 1.0);
-			log_likelihood += probability.ln();
-		}
-		return Some(log_prior + log_likelihood);
+	pub fn classification_table(&self, token_vec: &[&str]) -> HashMap<String, f64> {
+		/* Same as Classifier::classification_table(), callable without importing the trait */
+		return Classifier::classification_table(self, token_vec);
 	}
+}
 
-	pub fn classify(&self, token_vec: &Vec<&str>) -> Option<String> {
-		/* classifies a vector of string belonging to a label */
-		let mut best_label: Option<String> = None;
-		let mut best_score = f64::NEG_INFINITY;
+impl Classifier for NaiveBayes {
+	type Sample<'a> = &'a [&'a str];
 
-		for label in self.train_counts.keys() {
-			match self.score(label, &token_vec) {
-				Some(score) => { 
-					if score > best_score {
-						best_score = score;
-						best_label = Some(label.clone());
-					}
-				},
-				None => (),
-			}
-		}
-		return best_label;
+	fn labels(&self) -> Vec<String> {
+		return self.train_counts.keys().cloned().collect();
 	}
 
-	pub fn classification_table(&self, token_vec: &Vec<&str>) -> HashMap<String, f64> {
+	fn classification_table(&self, token_vec: &[&str]) -> HashMap<String, f64> {
 		/* Calculates the scores of all labels given the token_vec */
+		let vocabulary = self.vocabulary();
 		let mut table: HashMap<String, f64> = HashMap::new();
 		for label in self.train_counts.keys() {
-			match self.score(label, &token_vec) {
-				Some(score) => { table.insert(label.to_string(), score); },
-				None => (),
-			}
+			table.insert(label.to_string(), self.log_prior(label) + self.log_likelihood(label, token_vec, &vocabulary));
 		}
 		return table;
 	}
-
 }
 
 #[cfg(test)]
@@ -157,5 +195,82 @@ mod tests {
 		assert_eq!(nb.classify(&ambiguous_vector), Some("nospam".to_string()));
 	}
 
+	fn spam_filter(nb: NaiveBayes) -> NaiveBayes {
+		let mut nb = nb;
+		nb.bulk_train(vec![
+			("spam".to_string(), vec!["buy", "cheap", "meds"]),
+			("spam".to_string(), vec!["cheap", "pills"]),
+			("spam".to_string(), vec!["cheap", "offer"]),
+			("ham".to_string(), vec!["lunch", "offer", "tonight"]),
+		]);
+		return nb;
+	}
+
+	#[test]
+	fn test_priors() {
+		let tokens: Vec<&str> = vec![];
+		let nb = spam_filter(NaiveBayes::new());
+		let spam = nb.score("spam", &tokens).unwrap();
+		assert!((spam - 0.75f64.ln()).abs() < 1e-12);
+		assert!((nb.score("ham", &tokens).unwrap() - 0.25f64.ln()).abs() < 1e-12);
+
+		let uniform = spam_filter(NaiveBayes::new().with_priors(Priors::uniform()));
+		assert!((uniform.score("ham", &tokens).unwrap() - 0.5f64.ln()).abs() < 1e-12);
+
+		let priors = HashMap::from([("spam".to_string(), 1.0), ("ham".to_string(), 4.0)]);
+		let supplied = spam_filter(NaiveBayes::new().with_priors(Priors::given(priors)));
+		assert!((supplied.score("ham", &tokens).unwrap() - 0.8f64.ln()).abs() < 1e-12);
+		assert_eq!(supplied.classify(&vec!["offer"]), Some("ham".to_string()));
+		assert_eq!(nb.classify(&vec!["offer"]), Some("spam".to_string()));
+	}
+
+	#[test]
+	fn test_smoothing() {
+		// 7 spam tokens, 3 ham tokens and a vocabulary of 7 distinct tokens
+		let nb = spam_filter(NaiveBayes::new());
+		let score = nb.score("ham", &vec!["cheap", "tonight"]).unwrap();
+		let expected = 0.25f64.ln() + (1.0f64 / 10.0).ln() + (2.0f64 / 10.0).ln();
+		assert!((score - expected).abs() < 1e-12);
+		// Tokens outside the vocabulary are ignored
+		assert_eq!(nb.score("ham", &vec!["cheap", "tonight", "unknown"]), Some(score));
+
+		let lidstone = spam_filter(NaiveBayes::new().with_alpha(0.5));
+		let score = lidstone.score("spam", &vec!["cheap", "lunch"]).unwrap();
+		let expected = 0.75f64.ln() + (3.5f64 / 10.5).ln() + (0.5f64 / 10.5).ln();
+		assert!((score - expected).abs() < 1e-12);
+
+		// Models saved before smoothing was configurable load with Laplace smoothing
+		let json = r#"{"train_counts":{"a":1},"token_counts":{"a":{"x":1}},"total_counts":{"a":1}}"#;
+		let loaded: NaiveBayes = serde_json::from_str(json).unwrap();
+		assert_eq!(loaded.alpha, 1.0);
+		assert_eq!(loaded.priors, Priors::default());
+	}
 
-}
\ No newline at end of file
+	#[test]
+	fn test_probabilities() {
+		let nb = spam_filter(NaiveBayes::new());
+		let tokens = vec!["cheap", "meds", "offer"];
+		let table = nb.classification_table(&tokens);
+		let proba = nb.predict_proba(&tokens);
+		assert!((proba.values().sum::<f64>() - 1.0).abs() < 1e-12);
+		assert!(proba["spam"] > 0.85);
+		// The ratio of posteriors is the ratio of the unnormalized scores
+		assert!(((proba["spam"] / proba["ham"]).ln() - (table["spam"] - table["ham"])).abs() < 1e-9);
+		let log_proba = nb.predict_log_proba(&tokens);
+		assert!((log_proba["ham"].exp() - proba["ham"]).abs() < 1e-12);
+
+		// Long documents underflow exp() of the raw scores, not the normalized posteriors
+		let long: Vec<&str> = ["cheap", "lunch"].repeat(500);
+		let proba = nb.predict_proba(&long);
+		assert!(nb.classification_table(&long).values().all(|s| s.exp() == 0.0));
+		assert!((proba.values().sum::<f64>() - 1.0).abs() < 1e-12);
+
+		assert_eq!(nb.classify_with_threshold(&tokens, 0.85), Some("spam".to_string()));
+		let ambiguous = vec!["offer"];
+		let confidence = nb.predict_proba(&ambiguous)["spam"];
+		assert!(confidence < 0.85);
+		assert_eq!(nb.classify_with_threshold(&ambiguous, 0.85), None);
+		assert_eq!(nb.classify_with_threshold(&ambiguous, confidence), Some("spam".to_string()));
+		assert!(NaiveBayes::new().predict_proba(&tokens).is_empty());
+	}
+}
@@ -1,4 +1,4 @@
-/**
+/*!
  * Levenshtein Distance implementation
  * - distance(s1: &str, s2: &str)  => distance_unicode(...)
  * - distance_unicode(s1: &str, s2: &str) -> usize
@@ -1,6 +1,10 @@
+#![allow(clippy::needless_return)]
+#![cfg_attr(test, allow(clippy::needless_borrow, clippy::bool_assert_comparison, clippy::useless_vec))]
+
 pub mod utils;
 pub mod persist;
 pub mod levenshtein;
 pub mod bayes;
 pub mod markov;
-pub mod nnet;
\ No newline at end of file
+pub mod nnet;
+pub mod types;
\ No newline at end of file
@@ -30,7 +30,7 @@ impl MarkovChain {
 		}
 		for window in chars.windows(2) {
 			if let [current, next] = window {
-				self.chain.entry(*current).or_insert_with(Vec::new).push(*next);
+				self.chain.entry(*current).or_default().push(*next);
 			}
 		}
 	}
@@ -0,0 +1,204 @@
+use serde::{Serialize, Deserialize};
+
+use super::autodiff::{Graph, Var};
+use super::layer::Layer;
+
+use super::utils::{sigmoid, sigmoid_derivative, softmax};
+
+const GELU_COEFF: f64 = 0.044715;
+const SQRT_2_OVER_PI: f64 = 0.7978845608028654;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Activation {
+	#[default]
+	Sigmoid,
+	Tanh,
+	Relu,
+	LeakyRelu(f64),
+	Elu(f64),
+	Gelu,
+	Softplus,
+	Identity,
+	Softmax,
+}
+
+impl Activation {
+	pub fn forward(&self, z: &[f64]) -> Vec<f64> {
+		/* Returns the activations of a whole layer given its weighted inputs z */
+		match *self {
+			Activation::Softmax => softmax(z),
+			_ => z.iter().map(|x| self.apply(*x)).collect(),
+		}
+	}
+
+	pub fn backward(&self, z: &[f64], output: &[f64], grad: &[f64]) -> Vec<f64> {
+		/* Propagates the gradient w.r.t. the layer output back to its weighted inputs z.
+		output is the result of forward(z). */
+		match *self {
+			Activation::Softmax => {
+				let weighted: f64 = output.iter().zip(grad).map(|(s, g)| s * g).sum();
+				output.iter().zip(grad).map(|(s, g)| s * (g - weighted)).collect()
+			},
+			_ => z.iter().zip(grad).map(|(x, g)| g * self.derivative(*x)).collect(),
+		}
+	}
+
+	pub fn apply(&self, x: f64) -> f64 {
+		/* Returns the activation of the weighted input x.
+		Panics for Softmax which is only defined over a whole layer, see forward(). */
+		match *self {
+			Activation::Sigmoid => sigmoid(x),
+			Activation::Tanh => x.tanh(),
+			Activation::Relu => x.max(0.0),
+			Activation::LeakyRelu(alpha) => if x > 0.0 { x } else { alpha * x },
+			Activation::Elu(alpha) => if x > 0.0 { x } else { alpha * x.exp_m1() },
+			Activation::Gelu => 0.5 * x * (1.0 + gelu_inner(x).tanh()),
+			Activation::Softplus => x.max(0.0) + (-x.abs()).exp().ln_1p(),
+			Activation::Identity => x,
+			Activation::Softmax => panic!("Softmax is not an elementwise activation"),
+		}
+	}
+
+	pub fn derivative(&self, x: f64) -> f64 {
+		/* Returns the derivative of the activation with respect to the weighted input x.
+		Panics for Softmax, whose derivative is a jacobian, see backward(). */
+		match *self {
+			Activation::Sigmoid => sigmoid_derivative(x),
+			Activation::Tanh => 1.0 - x.tanh().powi(2),
+			Activation::Relu => if x > 0.0 { 1.0 } else { 0.0 },
+			Activation::LeakyRelu(alpha) => if x > 0.0 { 1.0 } else { alpha },
+			Activation::Elu(alpha) => if x > 0.0 { 1.0 } else { alpha * x.exp() },
+			Activation::Gelu => {
+				let t = gelu_inner(x).tanh();
+				let inner_derivative = SQRT_2_OVER_PI * (1.0 + 3.0 * GELU_COEFF * x * x);
+				0.5 * (1.0 + t) + 0.5 * x * (1.0 - t * t) * inner_derivative
+			},
+			Activation::Softplus => sigmoid(x),
+			Activation::Identity => 1.0,
+			Activation::Softmax => panic!("Softmax is not an elementwise activation"),
+		}
+	}
+}
+
+fn gelu_inner(x: f64) -> f64 {
+	/* Argument of the tanh in the GELU approximation */
+	return SQRT_2_OVER_PI * (x + GELU_COEFF * x.powi(3));
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ActivationLayer {
+	/* Applies an activation on its own, to the outputs of a layer without one */
+	pub size: usize,
+	pub activation: Activation,
+}
+
+impl ActivationLayer {
+	pub const NAME: &'static str = "Activation";
+
+	pub fn new(size: usize, activation: Activation) -> Self {
+		return Self { size, activation };
+	}
+}
+
+impl Layer for ActivationLayer {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.size;
+	}
+
+	fn output_size(&self) -> usize {
+		return self.size;
+	}
+
+	fn activation(&self) -> Option<Activation> {
+		return Some(self.activation);
+	}
+
+	fn build_graph(&self, graph: &mut Graph, input: Var, _parameters: &[Var]) -> Option<Var> {
+		return Some(graph.activation(input, self.activation));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::compare::approx_eq;
+
+	const ALL: [Activation; 8] = [
+		Activation::Sigmoid,
+		Activation::Tanh,
+		Activation::Relu,
+		Activation::LeakyRelu(0.01),
+		Activation::Elu(1.0),
+		Activation::Gelu,
+		Activation::Softplus,
+		Activation::Identity,
+	];
+
+	#[test]
+	fn test_apply() {
+		assert!(approx_eq(Activation::Sigmoid.apply(0.0), 0.5, 1e-12));
+		assert!(approx_eq(Activation::Relu.apply(-2.0), 0.0, 1e-12));
+		assert!(approx_eq(Activation::Relu.apply(2.0), 2.0, 1e-12));
+		assert!(approx_eq(Activation::LeakyRelu(0.1).apply(-2.0), -0.2, 1e-12));
+		assert!(approx_eq(Activation::Elu(1.0).apply(-1.0), (-1.0f64).exp() - 1.0, 1e-12));
+		assert!(approx_eq(Activation::Gelu.apply(0.0), 0.0, 1e-12));
+		assert!(approx_eq(Activation::Softplus.apply(0.0), 2.0f64.ln(), 1e-12));
+		assert!(approx_eq(Activation::Softplus.apply(1000.0), 1000.0, 1e-9));
+		assert!(approx_eq(Activation::Identity.apply(-3.5), -3.5, 1e-12));
+	}
+
+	#[test]
+	fn test_derivative_matches_finite_difference() {
+		let h = 1e-6;
+		for activation in ALL {
+			for x in [-2.5, -0.7, 0.3, 1.9] {
+				let numeric = (activation.apply(x + h) - activation.apply(x - h)) / (2.0 * h);
+				assert!(
+					approx_eq(activation.derivative(x), numeric, 1e-6),
+					"{:?} derivative at {}: {} != {}", activation, x, activation.derivative(x), numeric
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn test_softmax() {
+		let output = Activation::Softmax.forward(&[1.0, 2.0, 3.0]);
+		assert!(approx_eq(output.iter().sum::<f64>(), 1.0, 1e-12));
+		assert!(output[0] < output[1] && output[1] < output[2]);
+		// Large logits do not overflow
+		let output = Activation::Softmax.forward(&[1000.0, 1000.0]);
+		assert!(approx_eq(output[0], 0.5, 1e-12));
+	}
+
+	#[test]
+	fn test_softmax_backward() {
+		let h = 1e-6;
+		let z = [0.3, -1.2, 2.0];
+		let grad = [0.5, -1.0, 0.25];
+		let output = Activation::Softmax.forward(&z);
+		let analytic = Activation::Softmax.backward(&z, &output, &grad);
+		for i in 0..z.len() {
+			let mut plus = z;
+			let mut minus = z;
+			plus[i] += h;
+			minus[i] -= h;
+			let f = |z: &[f64]| -> f64 {
+				Activation::Softmax.forward(z).iter().zip(&grad).map(|(s, g)| s * g).sum()
+			};
+			let numeric = (f(&plus) - f(&minus)) / (2.0 * h);
+			assert!(approx_eq(analytic[i], numeric, 1e-6), "{} != {}", analytic[i], numeric);
+		}
+	}
+
+	#[test]
+	fn test_serialize() {
+		let json = serde_json::to_string(&Activation::LeakyRelu(0.2)).unwrap();
+		let activation: Activation = serde_json::from_str(&json).unwrap();
+		assert_eq!(activation, Activation::LeakyRelu(0.2));
+	}
+}
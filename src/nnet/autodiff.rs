@@ -0,0 +1,461 @@
+/*
+Reverse-mode automatic differentiation.
+A Graph records every operation applied to its values, which are matrices (a scalar is 1x1).
+backward() walks the recorded operations in reverse and returns the gradient of the output
+w.r.t. every value of the graph. Layers that describe their forward pass as a graph through
+Layer::build_graph() get their backward pass from here.
+*/
+
+use super::activation::Activation;
+use super::matrix::Matrix;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Var(usize);
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+	Leaf,
+	Add(Var, Var),
+	Sub(Var, Var),
+	Mul(Var, Var),
+	Div(Var, Var),
+	// Broadcasts a 1xD row vector over every row
+	AddRow(Var, Var),
+	MulRow(Var, Var),
+	// Broadcasts an Nx1 column vector over every column
+	SubCol(Var, Var),
+	MulCol(Var, Var),
+	DivCol(Var, Var),
+	Scale(Var, f64),
+	AddScalar(Var),
+	MatMul(Var, Var),
+	MatMulTransposed(Var, Var),
+	Reshape(Var),
+	Exp(Var),
+	Log(Var),
+	Sqrt(Var),
+	Square(Var),
+	Activation(Var, Activation),
+	Sum(Var),
+	Mean(Var),
+	SumRows(Var),
+	MeanRows(Var),
+	SumCols(Var),
+}
+
+struct Node {
+	value: Matrix,
+	op: Op,
+}
+
+pub struct Graph {
+	nodes: Vec<Node>,
+}
+
+pub struct Gradients {
+	grads: Vec<Option<Matrix>>,
+}
+
+impl Gradients {
+	pub fn get(&self, var: Var) -> Option<&Matrix> {
+		/* Gradient w.r.t. var, None when the output does not depend on it */
+		return self.grads[var.0].as_ref();
+	}
+}
+
+impl Graph {
+	pub fn new() -> Self {
+		return Self { nodes: Vec::new() };
+	}
+
+	pub fn leaf(&mut self, value: Matrix) -> Var {
+		/* An input or parameter of the computation */
+		return self.push(value, Op::Leaf);
+	}
+
+	pub fn scalar(&mut self, value: f64) -> Var {
+		return self.leaf(Matrix::row_vector(vec![value]));
+	}
+
+	pub fn value(&self, var: Var) -> &Matrix {
+		return &self.nodes[var.0].value;
+	}
+
+	pub fn scalar_value(&self, var: Var) -> f64 {
+		return self.value(var).data()[0];
+	}
+
+	pub fn add(&mut self, a: Var, b: Var) -> Var {
+		let value = self.value(a).zip_map(self.value(b), |x, y| x + y);
+		return self.push(value, Op::Add(a, b));
+	}
+
+	pub fn sub(&mut self, a: Var, b: Var) -> Var {
+		let value = self.value(a).zip_map(self.value(b), |x, y| x - y);
+		return self.push(value, Op::Sub(a, b));
+	}
+
+	pub fn mul(&mut self, a: Var, b: Var) -> Var {
+		/* Element-wise product */
+		let value = self.value(a).zip_map(self.value(b), |x, y| x * y);
+		return self.push(value, Op::Mul(a, b));
+	}
+
+	pub fn div(&mut self, a: Var, b: Var) -> Var {
+		let value = self.value(a).zip_map(self.value(b), |x, y| x / y);
+		return self.push(value, Op::Div(a, b));
+	}
+
+	pub fn add_row(&mut self, a: Var, row: Var) -> Var {
+		let value = broadcast_row(self.value(a), self.value(row), |x, y| x + y);
+		return self.push(value, Op::AddRow(a, row));
+	}
+
+	pub fn mul_row(&mut self, a: Var, row: Var) -> Var {
+		let value = broadcast_row(self.value(a), self.value(row), |x, y| x * y);
+		return self.push(value, Op::MulRow(a, row));
+	}
+
+	pub fn sub_col(&mut self, a: Var, col: Var) -> Var {
+		let value = broadcast_col(self.value(a), self.value(col), |x, y| x - y);
+		return self.push(value, Op::SubCol(a, col));
+	}
+
+	pub fn mul_col(&mut self, a: Var, col: Var) -> Var {
+		let value = broadcast_col(self.value(a), self.value(col), |x, y| x * y);
+		return self.push(value, Op::MulCol(a, col));
+	}
+
+	pub fn div_col(&mut self, a: Var, col: Var) -> Var {
+		let value = broadcast_col(self.value(a), self.value(col), |x, y| x / y);
+		return self.push(value, Op::DivCol(a, col));
+	}
+
+	pub fn scale(&mut self, a: Var, factor: f64) -> Var {
+		let value = self.value(a).map(|x| x * factor);
+		return self.push(value, Op::Scale(a, factor));
+	}
+
+	pub fn add_scalar(&mut self, a: Var, constant: f64) -> Var {
+		let value = self.value(a).map(|x| x + constant);
+		return self.push(value, Op::AddScalar(a));
+	}
+
+	pub fn matmul(&mut self, a: Var, b: Var) -> Var {
+		let value = self.value(a).matmul(self.value(b));
+		return self.push(value, Op::MatMul(a, b));
+	}
+
+	pub fn matmul_transposed(&mut self, a: Var, b: Var) -> Var {
+		/* a * b^T, e.g. a batch of inputs times a weight matrix with one row per neuron */
+		let value = self.value(a).matmul_transposed(self.value(b));
+		return self.push(value, Op::MatMulTransposed(a, b));
+	}
+
+	pub fn reshape(&mut self, a: Var, rows: usize, cols: usize) -> Var {
+		let value = self.value(a).clone().reshape(rows, cols);
+		return self.push(value, Op::Reshape(a));
+	}
+
+	pub fn exp(&mut self, a: Var) -> Var {
+		let value = self.value(a).map(f64::exp);
+		return self.push(value, Op::Exp(a));
+	}
+
+	pub fn log(&mut self, a: Var) -> Var {
+		let value = self.value(a).map(f64::ln);
+		return self.push(value, Op::Log(a));
+	}
+
+	pub fn sqrt(&mut self, a: Var) -> Var {
+		let value = self.value(a).map(f64::sqrt);
+		return self.push(value, Op::Sqrt(a));
+	}
+
+	pub fn square(&mut self, a: Var) -> Var {
+		let value = self.value(a).map(|x| x * x);
+		return self.push(value, Op::Square(a));
+	}
+
+	pub fn activation(&mut self, a: Var, activation: Activation) -> Var {
+		/* Applies an activation to every row */
+		let value = self.value(a).map_rows(|row| activation.forward(row));
+		return self.push(value, Op::Activation(a, activation));
+	}
+
+	pub fn sum(&mut self, a: Var) -> Var {
+		let value = self.value(a).data().iter().sum();
+		return self.push(Matrix::row_vector(vec![value]), Op::Sum(a));
+	}
+
+	pub fn mean(&mut self, a: Var) -> Var {
+		let m = self.value(a);
+		let value = m.data().iter().sum::<f64>() / m.data().len().max(1) as f64;
+		return self.push(Matrix::row_vector(vec![value]), Op::Mean(a));
+	}
+
+	pub fn sum_rows(&mut self, a: Var) -> Var {
+		/* Sum of every row, as a column vector */
+		let sums = self.value(a).row_sums();
+		return self.push(Matrix::new(sums.len(), 1, sums), Op::SumRows(a));
+	}
+
+	pub fn mean_rows(&mut self, a: Var) -> Var {
+		let cols = self.value(a).cols().max(1) as f64;
+		let means: Vec<f64> = self.value(a).row_sums().iter().map(|s| s / cols).collect();
+		return self.push(Matrix::new(means.len(), 1, means), Op::MeanRows(a));
+	}
+
+	pub fn sum_cols(&mut self, a: Var) -> Var {
+		/* Sum of every column, as a row vector */
+		let sums = self.value(a).column_sums();
+		return self.push(Matrix::row_vector(sums), Op::SumCols(a));
+	}
+
+	pub fn backward(&self, output: Var) -> Gradients {
+		/* Gradients of the sum of output's values */
+		let value = self.value(output);
+		return self.backward_with(output, Matrix::new(value.rows(), value.cols(), vec![1.0; value.data().len()]));
+	}
+
+	pub fn backward_with(&self, output: Var, seed: Matrix) -> Gradients {
+		/* Gradients given the gradient of some downstream loss w.r.t. output */
+		return self.backward_seeded(vec![(output, seed)]);
+	}
+
+	pub fn backward_seeded(&self, seeds: Vec<(Var, Matrix)>) -> Gradients {
+		/* Gradients of a loss depending on several values, given its gradient w.r.t. each of them */
+		let mut grads: Vec<Option<Matrix>> = vec![None; self.nodes.len()];
+		let last = seeds.iter().map(|(var, _)| var.0).max().unwrap_or(0);
+		for (var, seed) in seeds {
+			accumulate(&mut grads, var, seed);
+		}
+		for idx in (0..=last.min(self.nodes.len().saturating_sub(1))).rev() {
+			let Some(g) = grads[idx].take() else { continue };
+			let node = &self.nodes[idx];
+			match node.op {
+				Op::Leaf => {},
+				Op::Add(a, b) => {
+					accumulate(&mut grads, b, g.clone());
+					accumulate(&mut grads, a, g.clone());
+				},
+				Op::Sub(a, b) => {
+					accumulate(&mut grads, b, g.map(|x| -x));
+					accumulate(&mut grads, a, g.clone());
+				},
+				Op::Mul(a, b) => {
+					accumulate(&mut grads, a, g.zip_map(self.value(b), |x, y| x * y));
+					accumulate(&mut grads, b, g.zip_map(self.value(a), |x, y| x * y));
+				},
+				Op::Div(a, b) => {
+					let (va, vb) = (self.value(a), self.value(b));
+					accumulate(&mut grads, a, g.zip_map(vb, |x, y| x / y));
+					let gb = g.zip_map(va, |x, y| x * y).zip_map(vb, |x, y| -x / (y * y));
+					accumulate(&mut grads, b, gb);
+				},
+				Op::AddRow(a, row) => {
+					accumulate(&mut grads, row, Matrix::row_vector(g.column_sums()));
+					accumulate(&mut grads, a, g.clone());
+				},
+				Op::MulRow(a, row) => {
+					let product = g.zip_map(self.value(a), |x, y| x * y);
+					accumulate(&mut grads, row, Matrix::row_vector(product.column_sums()));
+					accumulate(&mut grads, a, broadcast_row(&g, self.value(row), |x, y| x * y));
+				},
+				Op::SubCol(a, col) => {
+					let sums: Vec<f64> = g.row_sums().iter().map(|s| -s).collect();
+					accumulate(&mut grads, col, Matrix::new(sums.len(), 1, sums));
+					accumulate(&mut grads, a, g.clone());
+				},
+				Op::MulCol(a, col) => {
+					let sums = g.zip_map(self.value(a), |x, y| x * y).row_sums();
+					accumulate(&mut grads, col, Matrix::new(sums.len(), 1, sums));
+					accumulate(&mut grads, a, broadcast_col(&g, self.value(col), |x, y| x * y));
+				},
+				Op::DivCol(a, col) => {
+					// d(a/c)/dc = -a/c^2 = -output/c
+					let quotient = broadcast_col(&g.zip_map(&node.value, |x, y| x * y), self.value(col), |x, y| -x / y);
+					let sums = quotient.row_sums();
+					accumulate(&mut grads, col, Matrix::new(sums.len(), 1, sums));
+					accumulate(&mut grads, a, broadcast_col(&g, self.value(col), |x, y| x / y));
+				},
+				Op::Scale(a, factor) => accumulate(&mut grads, a, g.map(|x| x * factor)),
+				Op::AddScalar(a) => accumulate(&mut grads, a, g.clone()),
+				Op::MatMul(a, b) => {
+					accumulate(&mut grads, a, g.matmul_transposed(self.value(b)));
+					accumulate(&mut grads, b, self.value(a).transposed_matmul(&g));
+				},
+				Op::MatMulTransposed(a, b) => {
+					accumulate(&mut grads, a, g.matmul(self.value(b)));
+					accumulate(&mut grads, b, g.transposed_matmul(self.value(a)));
+				},
+				Op::Reshape(a) => {
+					let shape = self.value(a);
+					accumulate(&mut grads, a, g.clone().reshape(shape.rows(), shape.cols()));
+				},
+				Op::Exp(a) => accumulate(&mut grads, a, g.zip_map(&node.value, |x, y| x * y)),
+				Op::Log(a) => accumulate(&mut grads, a, g.zip_map(self.value(a), |x, y| x / y)),
+				Op::Sqrt(a) => accumulate(&mut grads, a, g.zip_map(&node.value, |x, y| x / (2.0 * y))),
+				Op::Square(a) => accumulate(&mut grads, a, g.zip_map(self.value(a), |x, y| 2.0 * x * y)),
+				Op::Activation(a, activation) => {
+					let z = self.value(a);
+					let rows: Vec<Vec<f64>> = (0..g.rows()).map(|r| {
+						activation.backward(z.row(r), node.value.row(r), g.row(r))
+					}).collect();
+					accumulate(&mut grads, a, Matrix::from_rows(&rows));
+				},
+				Op::Sum(a) => {
+					let shape = self.value(a);
+					accumulate(&mut grads, a, Matrix::new(shape.rows(), shape.cols(), vec![g.data()[0]; shape.data().len()]));
+				},
+				Op::Mean(a) => {
+					let shape = self.value(a);
+					let n = shape.data().len().max(1) as f64;
+					accumulate(&mut grads, a, Matrix::new(shape.rows(), shape.cols(), vec![g.data()[0] / n; shape.data().len()]));
+				},
+				Op::SumRows(a) | Op::MeanRows(a) => {
+					let shape = self.value(a);
+					let factor = if let Op::MeanRows(_) = node.op { 1.0 / shape.cols().max(1) as f64 } else { 1.0 };
+					let expanded = broadcast_col(&Matrix::zeros(shape.rows(), shape.cols()), &g, |_, y| y * factor);
+					accumulate(&mut grads, a, expanded);
+				},
+				Op::SumCols(a) => {
+					let shape = self.value(a);
+					accumulate(&mut grads, a, broadcast_row(&Matrix::zeros(shape.rows(), shape.cols()), &g, |_, y| y));
+				},
+			}
+			grads[idx] = Some(g);
+		}
+		return Gradients { grads };
+	}
+
+	fn push(&mut self, value: Matrix, op: Op) -> Var {
+		self.nodes.push(Node { value, op });
+		return Var(self.nodes.len() - 1);
+	}
+}
+
+impl Default for Graph {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn accumulate(grads: &mut [Option<Matrix>], var: Var, gradient: Matrix) {
+	match &mut grads[var.0] {
+		Some(g) => g.add_assign(&gradient),
+		slot => *slot = Some(gradient),
+	}
+}
+
+fn broadcast_row(a: &Matrix, row: &Matrix, f: impl Fn(f64, f64) -> f64) -> Matrix {
+	assert_eq!((row.rows(), row.cols()), (1, a.cols()), "Expected a 1x{} row vector", a.cols());
+	return a.map_rows(|r| r.iter().zip(row.data()).map(|(x, y)| f(*x, *y)).collect());
+}
+
+fn broadcast_col(a: &Matrix, col: &Matrix, f: impl Fn(f64, f64) -> f64) -> Matrix {
+	assert_eq!((col.rows(), col.cols()), (a.rows(), 1), "Expected a {}x1 column vector", a.rows());
+	let mut out = a.clone();
+	for r in 0..out.rows() {
+		let c = col.data()[r];
+		for x in out.row_mut(r) {
+			*x = f(*x, c);
+		}
+	}
+	return out;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::compare::approx_eq;
+
+	fn check(build: impl Fn(&mut Graph, &[Var]) -> Var, inputs: &[Matrix]) {
+		/* Compares backward() with central differences of the summed output */
+		let mut graph = Graph::new();
+		let vars: Vec<Var> = inputs.iter().map(|m| graph.leaf(m.clone())).collect();
+		let output = build(&mut graph, &vars);
+		let grads = graph.backward(output);
+		let eval = |inputs: &[Matrix]| {
+			let mut graph = Graph::new();
+			let vars: Vec<Var> = inputs.iter().map(|m| graph.leaf(m.clone())).collect();
+			let output = build(&mut graph, &vars);
+			return graph.value(output).data().iter().sum::<f64>();
+		};
+		let h = 1e-6;
+		for (i, input) in inputs.iter().enumerate() {
+			for k in 0..input.data().len() {
+				let (mut plus, mut minus) = (inputs.to_vec(), inputs.to_vec());
+				plus[i].data_mut()[k] += h;
+				minus[i].data_mut()[k] -= h;
+				let numeric = (eval(&plus) - eval(&minus)) / (2.0 * h);
+				let analytic = grads.get(vars[i]).map_or(0.0, |g| g.data()[k]);
+				assert!(approx_eq(analytic, numeric, 1e-5), "Input {} element {}: {} != {}", i, k, analytic, numeric);
+			}
+		}
+	}
+
+	fn a() -> Matrix {
+		return Matrix::from_rows(&[vec![0.5, -1.2, 2.0], vec![1.5, 0.3, -0.7]]);
+	}
+
+	fn b() -> Matrix {
+		return Matrix::from_rows(&[vec![1.1, 0.4, -0.6], vec![-2.0, 0.9, 1.3]]);
+	}
+
+	#[test]
+	fn test_scalars() {
+		// f(x, y) = x * y + exp(x), df/dx = y + exp(x), df/dy = x
+		let mut graph = Graph::new();
+		let x = graph.scalar(2.0);
+		let y = graph.scalar(3.0);
+		let xy = graph.mul(x, y);
+		let ex = graph.exp(x);
+		let f = graph.add(xy, ex);
+		assert!(approx_eq(graph.scalar_value(f), 6.0 + 2f64.exp(), 1e-12));
+		let grads = graph.backward(f);
+		assert!(approx_eq(grads.get(x).unwrap().data()[0], 3.0 + 2f64.exp(), 1e-12));
+		assert!(approx_eq(grads.get(y).unwrap().data()[0], 2.0, 1e-12));
+		let unused = graph.scalar(1.0);
+		assert!(graph.backward(f).get(unused).is_none());
+	}
+
+	#[test]
+	fn test_element_wise() {
+		check(|g, v| g.add(v[0], v[1]), &[a(), b()]);
+		check(|g, v| g.sub(v[0], v[1]), &[a(), b()]);
+		check(|g, v| g.mul(v[0], v[1]), &[a(), b()]);
+		check(|g, v| g.div(v[0], v[1]), &[a(), b()]);
+		check(|g, v| { let s = g.scale(v[0], -1.5); g.add_scalar(s, 2.0) }, &[a()]);
+		check(|g, v| g.exp(v[0]), &[a()]);
+		check(|g, v| { let s = g.square(v[0]); let s = g.add_scalar(s, 0.1); g.log(s) }, &[a()]);
+		check(|g, v| { let s = g.square(v[0]); let s = g.add_scalar(s, 0.1); g.sqrt(s) }, &[a()]);
+		// Reuse of a value accumulates its gradient
+		check(|g, v| g.mul(v[0], v[0]), &[a()]);
+	}
+
+	#[test]
+	fn test_broadcasts_and_reductions() {
+		let row = Matrix::row_vector(vec![0.2, -0.4, 1.5]);
+		let col = Matrix::new(2, 1, vec![0.7, -1.3]);
+		check(|g, v| g.add_row(v[0], v[1]), &[a(), row.clone()]);
+		check(|g, v| g.mul_row(v[0], v[1]), &[a(), row.clone()]);
+		check(|g, v| g.sub_col(v[0], v[1]), &[a(), col.clone()]);
+		check(|g, v| g.mul_col(v[0], v[1]), &[a(), col.clone()]);
+		check(|g, v| g.div_col(v[0], v[1]), &[a(), col.clone()]);
+		check(|g, v| { let s = g.square(v[0]); g.mean(s) }, &[a()]);
+		check(|g, v| { let s = g.square(v[0]); g.sum_rows(s) }, &[a()]);
+		check(|g, v| { let s = g.square(v[0]); g.mean_rows(s) }, &[a()]);
+		check(|g, v| { let s = g.square(v[0]); let s = g.sum_cols(s); g.sum(s) }, &[a()]);
+	}
+
+	#[test]
+	fn test_matrix_products_and_activations() {
+		check(|g, v| g.matmul_transposed(v[0], v[1]), &[a(), b()]);
+		check(|g, v| { let t = g.reshape(v[1], 3, 2); let p = g.matmul(v[0], t); g.square(p) }, &[a(), b()]);
+		let weights = Matrix::row_vector(vec![0.3, -1.0, 0.8]);
+		for activation in [Activation::Sigmoid, Activation::Tanh, Activation::Gelu, Activation::Softmax, Activation::Elu(0.5)] {
+			check(|g, v| { let out = g.activation(v[0], activation); g.mul_row(out, v[1]) }, &[a(), weights.clone()]);
+		}
+	}
+}
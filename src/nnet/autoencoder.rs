@@ -0,0 +1,265 @@
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::persist::json::JsonPersist;
+use super::activation::Activation;
+use super::dense::Dense;
+use super::error::NetworkError;
+use super::initializer::Initializer;
+use super::layer::Layer;
+use super::loss::LossFunction;
+use super::matrix::Matrix;
+use super::network::NeuralNetwork;
+use super::optimizer::OptimizerKind;
+use super::schedule::Schedule;
+use super::training::{Dataset, History};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "AutoencoderRecord")]
+pub struct Autoencoder {
+	/* A network trained to reproduce its inputs through a narrower code. Its first
+	encoder_layers layers form the encoder, the remaining ones the decoder, so both halves
+	train together through NeuralNetwork::fit(). Inputs it reconstructs poorly are unlike
+	the data it was trained on, which makes the reconstruction error an anomaly score. */
+	network: NeuralNetwork,
+	// Always at least one and fewer than the layers of the network
+	encoder_layers: usize,
+}
+
+#[derive(Deserialize)]
+struct AutoencoderRecord {
+	network: NeuralNetwork,
+	encoder_layers: usize,
+}
+
+impl TryFrom<AutoencoderRecord> for Autoencoder {
+	type Error = String;
+
+	fn try_from(record: AutoencoderRecord) -> Result<Self, String> {
+		let layers = record.network.layers.len();
+		if record.encoder_layers == 0 || record.encoder_layers >= layers {
+			return Err(format!("An autoencoder of {} layers cannot have {} encoder layers", layers, record.encoder_layers));
+		}
+		return Ok(Self { network: record.network, encoder_layers: record.encoder_layers });
+	}
+}
+
+impl JsonPersist for Autoencoder {}
+
+impl Autoencoder {
+	pub fn new(encoder: Vec<Box<dyn Layer>>, decoder: Vec<Box<dyn Layer>>) -> Result<Self, NetworkError> {
+		/* Joins an encoder and a decoder, the decoder must output as many values as the encoder takes */
+		if encoder.is_empty() || decoder.is_empty() {
+			return Err(NetworkError::EmptyNetwork);
+		}
+		let (input_size, output_size) = (encoder[0].input_size(), decoder[decoder.len() - 1].output_size());
+		if input_size != output_size {
+			return Err(NetworkError::TargetSize { expected: input_size, found: output_size });
+		}
+		let encoder_layers = encoder.len();
+		let layers = encoder.into_iter().chain(decoder).collect();
+		let network = NeuralNetwork::from_layers(layers)?.with_loss(LossFunction::MeanSquaredError);
+		return Ok(Self { network, encoder_layers });
+	}
+
+	pub fn dense(sizes: &[usize], activation: Activation, rng: &mut impl Rng) -> Self {
+		/* Symmetric dense autoencoder: sizes run from the input size down to the code size,
+		the decoder mirrors them back up to a linear output layer */
+		assert!(sizes.len() >= 2, "An autoencoder needs an input size and a code size");
+		let initializer = Initializer::XavierUniform;
+		let encoder: Vec<Box<dyn Layer>> = sizes.windows(2)
+			.map(|w| Box::new(Dense::new(w[1], w[0], activation, initializer, rng)) as Box<dyn Layer>)
+			.collect();
+		let reversed: Vec<usize> = sizes.iter().rev().copied().collect();
+		let decoder: Vec<Box<dyn Layer>> = reversed.windows(2).enumerate().map(|(i, w)| {
+			let activation = if i + 2 == reversed.len() { Activation::Identity } else { activation };
+			Box::new(Dense::new(w[1], w[0], activation, initializer, rng)) as Box<dyn Layer>
+		}).collect();
+		return Self::new(encoder, decoder).expect("Mirrored layers fit together");
+	}
+
+	pub fn with_learning_rate(mut self, learning_rate: f64) -> Self {
+		self.network = self.network.with_learning_rate(learning_rate);
+		return self;
+	}
+
+	pub fn with_optimizer(mut self, optimizer: impl Into<OptimizerKind>) -> Self {
+		self.network = self.network.with_optimizer(optimizer);
+		return self;
+	}
+
+	pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+		self.network = self.network.with_schedule(schedule);
+		return self;
+	}
+
+	pub fn network(&self) -> &NeuralNetwork {
+		/* The encoder and decoder layers as one network */
+		return &self.network;
+	}
+
+	pub fn encoder_layers(&self) -> usize {
+		return self.encoder_layers;
+	}
+
+	pub fn dataset(inputs: &[Vec<f64>]) -> Dataset {
+		/* Samples whose targets are their inputs */
+		return Dataset::new(inputs.iter().map(|x| (x.clone(), x.clone())).collect());
+	}
+
+	pub fn fit(&mut self, dataset: &Dataset, epochs: usize, batch_size: usize, shuffle: bool) -> Result<History, NetworkError> {
+		/* Trains both halves, see NeuralNetwork::fit(). Build the dataset with Autoencoder::dataset(). */
+		return self.network.fit(dataset, epochs, batch_size, shuffle);
+	}
+
+	pub fn input_size(&self) -> usize {
+		return self.network.input_size();
+	}
+
+	pub fn code_size(&self) -> usize {
+		return self.network.layers[self.encoder_layers - 1].output_size();
+	}
+
+	pub fn encode(&self, input: Vec<f64>) -> Result<Vec<f64>, NetworkError> {
+		let input = self.checked(input, self.input_size())?;
+		return Ok(self.run(input, 0..self.encoder_layers).into_data());
+	}
+
+	pub fn decode(&self, code: Vec<f64>) -> Result<Vec<f64>, NetworkError> {
+		let code = self.checked(code, self.code_size())?;
+		return Ok(self.run(code, self.encoder_layers..self.network.layers.len()).into_data());
+	}
+
+	pub fn reconstruct(&self, input: Vec<f64>) -> Result<Vec<f64>, NetworkError> {
+		return self.network.predict(input);
+	}
+
+	pub fn reconstruction_error(&self, input: Vec<f64>) -> Result<f64, NetworkError> {
+		/* Mean squared difference between an input and its reconstruction */
+		return Ok(self.scores(&[input])?[0]);
+	}
+
+	pub fn scores(&self, inputs: &[Vec<f64>]) -> Result<Vec<f64>, NetworkError> {
+		/* Reconstruction error of every input, computed in one batch */
+		if inputs.is_empty() {
+			return Ok(Vec::new());
+		}
+		let expected = self.input_size();
+		if let Some(input) = inputs.iter().find(|x| x.len() != expected) {
+			return Err(NetworkError::InputSize { expected, found: input.len() });
+		}
+		let batch = Matrix::from_rows(inputs);
+		let outputs = self.network.predict_batch(&batch)?;
+		return Ok((0..inputs.len()).map(|r| {
+			let squared: f64 = batch.row(r).iter().zip(outputs.row(r)).map(|(x, y)| (x - y).powi(2)).sum();
+			squared / self.input_size().max(1) as f64
+		}).collect());
+	}
+
+	pub fn threshold(&self, inputs: &[Vec<f64>], quantile: f64) -> Result<f64, NetworkError> {
+		/* The score below which a quantile of inputs fall, typically taken over normal records
+		with a quantile close to 1 */
+		let mut scores = self.scores(inputs)?;
+		if scores.is_empty() {
+			return Ok(0.0);
+		}
+		scores.sort_by(f64::total_cmp);
+		let index = (quantile.clamp(0.0, 1.0) * (scores.len() - 1) as f64).round() as usize;
+		return Ok(scores[index]);
+	}
+
+	pub fn flag_anomalies(&self, inputs: &[Vec<f64>], threshold: f64) -> Result<Vec<bool>, NetworkError> {
+		/* Whether every input scores above threshold */
+		return Ok(self.scores(inputs)?.into_iter().map(|s| s > threshold).collect());
+	}
+
+	fn checked(&self, values: Vec<f64>, expected: usize) -> Result<Matrix, NetworkError> {
+		if values.len() != expected {
+			return Err(NetworkError::InputSize { expected, found: values.len() });
+		}
+		return Ok(Matrix::row_vector(values));
+	}
+
+	fn run(&self, mut values: Matrix, layers: std::ops::Range<usize>) -> Matrix {
+		for layer in &self.network.layers[layers] {
+			values = layer.forward(&values);
+		}
+		return values;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use crate::nnet::optimizer::Adam;
+
+	fn telemetry(rng: &mut StdRng, count: usize) -> Vec<Vec<f64>> {
+		/* 6 features driven by 2 hidden factors, plus a little noise */
+		return (0..count).map(|_| {
+			let (a, b): (f64, f64) = (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+			let features = [a, b, a + b, a - b, 0.5 * a, -b];
+			features.iter().map(|f| f + rng.gen_range(-0.02..0.02)).collect()
+		}).collect();
+	}
+
+	#[test]
+	fn test_anomaly_scores() {
+		let mut rng = StdRng::seed_from_u64(21);
+		let normal = telemetry(&mut rng, 200);
+		let anomalies: Vec<Vec<f64>> = (0..20).map(|_| (0..6).map(|_| rng.gen_range(-1.5..1.5)).collect()).collect();
+
+		let mut autoencoder = Autoencoder::dense(&[6, 4, 2], Activation::Tanh, &mut rng)
+			.with_optimizer(Adam::default())
+			.with_learning_rate(0.01);
+		let history = autoencoder.fit(&Autoencoder::dataset(&normal).with_seed(22), 200, 16, true).unwrap();
+		assert!(history.last().unwrap().loss < history.epochs[0].loss / 10.0);
+
+		let code = autoencoder.encode(normal[0].clone()).unwrap();
+		assert_eq!(code.len(), 2);
+		let decoded = autoencoder.decode(code).unwrap();
+		assert_eq!(decoded, autoencoder.reconstruct(normal[0].clone()).unwrap());
+
+		let threshold = autoencoder.threshold(&normal, 0.99).unwrap();
+		let flagged = autoencoder.flag_anomalies(&anomalies, threshold).unwrap();
+		assert!(flagged.iter().filter(|f| **f).count() >= 18, "{:?}", flagged);
+		let false_alarms = autoencoder.flag_anomalies(&telemetry(&mut rng, 100), threshold).unwrap();
+		assert!(false_alarms.iter().filter(|f| **f).count() <= 5);
+		let score = autoencoder.reconstruction_error(anomalies[0].clone()).unwrap();
+		assert_eq!(score, autoencoder.scores(&anomalies).unwrap()[0]);
+	}
+
+	#[test]
+	fn test_shapes_and_persistence() {
+		let mut rng = StdRng::seed_from_u64(23);
+		let autoencoder = Autoencoder::dense(&[5, 3, 2], Activation::Relu, &mut rng);
+		assert_eq!(autoencoder.encoder_layers(), 2);
+		assert_eq!(autoencoder.network().layers.len(), 4);
+		assert_eq!(autoencoder.code_size(), 2);
+		assert_eq!(autoencoder.network().layers[3].activation(), Some(Activation::Identity));
+		assert_eq!(autoencoder.encode(vec![1.0; 4]), Err(NetworkError::InputSize { expected: 5, found: 4 }));
+		assert_eq!(autoencoder.decode(vec![1.0; 5]), Err(NetworkError::InputSize { expected: 2, found: 5 }));
+		let ragged = vec![vec![1.0; 5], vec![1.0; 3]];
+		assert_eq!(autoencoder.scores(&ragged), Err(NetworkError::InputSize { expected: 5, found: 3 }));
+
+		let encoder: Vec<Box<dyn Layer>> = vec![Box::new(Dense::new(2, 4, Activation::Tanh, Initializer::XavierUniform, &mut rng))];
+		let decoder: Vec<Box<dyn Layer>> = vec![Box::new(Dense::new(3, 2, Activation::Identity, Initializer::XavierUniform, &mut rng))];
+		assert_eq!(Autoencoder::new(encoder, decoder).err(), Some(NetworkError::TargetSize { expected: 4, found: 3 }));
+
+		let path = "test_autoencoder.json";
+		autoencoder.save_to_file(path, false).unwrap();
+		let loaded = Autoencoder::load_from_file(path).unwrap();
+		std::fs::remove_file(path).unwrap();
+		let input = vec![0.1, 0.2, 0.3, 0.4, 0.5];
+		assert_eq!(loaded.encode(input.clone()).unwrap(), autoencoder.encode(input.clone()).unwrap());
+		assert_eq!(loaded.encoder_layers(), 2);
+
+		// The encoder must leave at least one layer to the decoder
+		let json = serde_json::to_string(&autoencoder).unwrap();
+		for count in ["0", "4"] {
+			let invalid = json.replace("\"encoder_layers\":2", &format!("\"encoder_layers\":{}", count));
+			assert!(serde_json::from_str::<Autoencoder>(&invalid).is_err());
+		}
+	}
+}
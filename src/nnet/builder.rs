@@ -0,0 +1,189 @@
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use super::activation::Activation;
+use super::initializer::Initializer;
+use super::dense::Dense;
+use super::dropout::Dropout;
+use super::layer::Layer;
+use super::loss::LossFunction;
+use super::normalization::{BatchNorm, LayerNorm};
+use super::network::{NeuralNetwork, DEFAULT_LEARNING_RATE};
+use super::optimizer::OptimizerKind;
+use super::regularization::Regularization;
+use super::schedule::Schedule;
+
+#[derive(Clone, Copy)]
+enum Normalization {
+	Batch,
+	Layer,
+}
+
+pub struct NetworkBuilder {
+	sizes: Vec<usize>,
+	activations: Vec<Activation>,
+	regularizations: Vec<Regularization>,
+	dropouts: Vec<Option<f64>>,
+	normalizations: Vec<Option<Normalization>>,
+	initializer: Initializer,
+	seed: Option<u64>,
+	loss: LossFunction,
+	learning_rate: f64,
+	optimizer: OptimizerKind,
+	schedule: Schedule,
+	threads: usize,
+	clip_norm: Option<f64>,
+}
+
+impl NetworkBuilder {
+	pub fn new(sizes: &[usize]) -> Self {
+		/* Starts a network with sizes[0] inputs and one layer per following size,
+		all sigmoid by default */
+		return Self {
+			sizes: sizes.to_vec(),
+			activations: vec![Activation::Sigmoid; sizes.len().saturating_sub(1)],
+			regularizations: vec![Regularization::default(); sizes.len().saturating_sub(1)],
+			dropouts: vec![None; sizes.len().saturating_sub(1)],
+			normalizations: vec![None; sizes.len().saturating_sub(1)],
+			initializer: Initializer::default(),
+			seed: None,
+			loss: LossFunction::default(),
+			learning_rate: DEFAULT_LEARNING_RATE,
+			optimizer: OptimizerKind::default(),
+			schedule: Schedule::default(),
+			threads: 1,
+			clip_norm: None,
+		};
+	}
+
+	pub fn activation(mut self, activation: Activation) -> Self {
+		/* Sets the activation of every hidden layer */
+		let hidden = self.activations.len().saturating_sub(1);
+		for a in self.activations.iter_mut().take(hidden) {
+			*a = activation;
+		}
+		return self;
+	}
+
+	pub fn output_activation(mut self, activation: Activation) -> Self {
+		if let Some(a) = self.activations.last_mut() {
+			*a = activation;
+		}
+		return self;
+	}
+
+	pub fn activations(mut self, activations: &[Activation]) -> Self {
+		/* Sets activations[i] for the layer between sizes[i] and sizes[i+1] */
+		assert_eq!(activations.len(), self.activations.len(), "Expected one activation per layer");
+		self.activations = activations.to_vec();
+		return self;
+	}
+
+	pub fn regularization(mut self, regularization: Regularization) -> Self {
+		/* Regularizes the weights of every dense layer */
+		self.regularizations = vec![regularization; self.regularizations.len()];
+		return self;
+	}
+
+	pub fn layer_regularization(mut self, layer: usize, regularization: Regularization) -> Self {
+		/* Regularizes the weights of the layer between sizes[layer] and sizes[layer+1] */
+		assert!(layer < self.regularizations.len(), "No layer {}", layer);
+		self.regularizations[layer] = regularization;
+		return self;
+	}
+
+	pub fn dropout(mut self, after_layer: usize, rate: f64) -> Self {
+		/* Inserts a dropout layer on the outputs of the layer between sizes[after_layer] and sizes[after_layer+1] */
+		assert!(after_layer < self.dropouts.len(), "No layer {}", after_layer);
+		self.dropouts[after_layer] = Some(rate);
+		return self;
+	}
+
+	pub fn batch_norm(mut self, after_layer: usize) -> Self {
+		/* Inserts batch normalization on the outputs of the layer between sizes[after_layer] and sizes[after_layer+1] */
+		assert!(after_layer < self.normalizations.len(), "No layer {}", after_layer);
+		self.normalizations[after_layer] = Some(Normalization::Batch);
+		return self;
+	}
+
+	pub fn layer_norm(mut self, after_layer: usize) -> Self {
+		/* Inserts layer normalization on the outputs of the layer between sizes[after_layer] and sizes[after_layer+1] */
+		assert!(after_layer < self.normalizations.len(), "No layer {}", after_layer);
+		self.normalizations[after_layer] = Some(Normalization::Layer);
+		return self;
+	}
+
+	pub fn initializer(mut self, initializer: Initializer) -> Self {
+		self.initializer = initializer;
+		return self;
+	}
+
+	pub fn seed(mut self, seed: u64) -> Self {
+		/* Seeds the weight initialization, the same seed builds a bit-identical network */
+		self.seed = Some(seed);
+		return self;
+	}
+
+	pub fn loss(mut self, loss: LossFunction) -> Self {
+		self.loss = loss;
+		return self;
+	}
+
+	pub fn learning_rate(mut self, learning_rate: f64) -> Self {
+		self.learning_rate = learning_rate;
+		return self;
+	}
+
+	pub fn optimizer(mut self, optimizer: impl Into<OptimizerKind>) -> Self {
+		self.optimizer = optimizer.into();
+		return self;
+	}
+
+	pub fn schedule(mut self, schedule: Schedule) -> Self {
+		self.schedule = schedule;
+		return self;
+	}
+
+	pub fn threads(mut self, threads: usize) -> Self {
+		/* Worker threads sharing every training batch, see NeuralNetwork::train_batch() */
+		self.threads = threads.max(1);
+		return self;
+	}
+
+	pub fn clip_norm(mut self, max_norm: f64) -> Self {
+		/* Rescales the gradients of every update to an overall norm of at most max_norm */
+		self.clip_norm = Some(max_norm);
+		return self;
+	}
+
+	pub fn build(self) -> NeuralNetwork {
+		let mut rng = match self.seed {
+			Some(seed) => StdRng::seed_from_u64(seed),
+			None => StdRng::from_entropy(),
+		};
+		let initializer = self.initializer;
+		let mut layers: Vec<Box<dyn Layer>> = Vec::new();
+		for (i, w) in self.sizes.windows(2).enumerate() {
+			let dense = Dense::new(w[1], w[0], self.activations[i], initializer, &mut rng);
+			layers.push(Box::new(dense.with_regularization(self.regularizations[i])));
+			// Normalization comes before dropout so its statistics see every unit
+			match self.normalizations[i] {
+				Some(Normalization::Batch) => layers.push(Box::new(BatchNorm::new(w[1]))),
+				Some(Normalization::Layer) => layers.push(Box::new(LayerNorm::new(w[1]))),
+				None => {},
+			}
+			if let Some(rate) = self.dropouts[i] {
+				layers.push(Box::new(Dropout::new(w[1], rate)));
+			}
+		}
+		return NeuralNetwork {
+			layers,
+			loss: self.loss,
+			learning_rate: self.learning_rate,
+			optimizer: self.optimizer,
+			schedule: self.schedule,
+			threads: self.threads,
+			clip_norm: self.clip_norm,
+		};
+	}
+}
@@ -0,0 +1,284 @@
+/*
+Hooks into NeuralNetwork::fit_with_callbacks().
+A Callback is told when training, every epoch and every batch begin and end, and can stop
+training after any epoch. Built-in callbacks stop early once a monitored loss stops improving,
+save checkpoints through JsonPersist and log every epoch to a CSV file.
+*/
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::persist::json::JsonPersist;
+use super::error::NetworkError;
+use super::layer::Layer;
+use super::network::NeuralNetwork;
+use super::training::{EpochRecord, History};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+	Continue,
+	Stop,
+}
+
+pub trait Callback {
+	fn on_train_begin(&mut self, _network: &NeuralNetwork) -> Result<(), NetworkError> {
+		return Ok(());
+	}
+
+	fn on_epoch_begin(&mut self, _epoch: usize, _network: &NeuralNetwork) {}
+
+	fn on_batch_begin(&mut self, _epoch: usize, _batch: usize) {}
+
+	fn on_batch_end(&mut self, _epoch: usize, _batch: usize, _loss: f64) {}
+
+	fn on_epoch_end(&mut self, _record: &EpochRecord, _network: &NeuralNetwork) -> Result<Control, NetworkError> {
+		/* Called once the epoch's losses are known, training stops if any callback returns Stop */
+		return Ok(Control::Continue);
+	}
+
+	fn on_train_end(&mut self, _history: &History, _network: &mut NeuralNetwork) -> Result<(), NetworkError> {
+		return Ok(());
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Monitor {
+	Loss,
+	// Falls back to the training loss when the dataset has no validation samples
+	#[default]
+	ValidationLoss,
+}
+
+impl Monitor {
+	pub fn value(&self, record: &EpochRecord) -> f64 {
+		return match self {
+			Monitor::Loss => record.loss,
+			Monitor::ValidationLoss => record.validation_loss.unwrap_or(record.loss),
+		};
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct EarlyStopping {
+	/* Stops training once the monitored loss has not improved by more than min_delta for
+	patience epochs. With restore_best, the network ends training with the layers of its
+	best epoch rather than its last one. */
+	pub monitor: Monitor,
+	pub patience: usize,
+	pub min_delta: f64,
+	pub restore_best: bool,
+	best: f64,
+	best_epoch: Option<usize>,
+	best_layers: Option<Vec<Box<dyn Layer>>>,
+	wait: usize,
+	stopped_epoch: Option<usize>,
+}
+
+impl EarlyStopping {
+	pub fn new(patience: usize) -> Self {
+		return Self {
+			monitor: Monitor::default(),
+			patience,
+			min_delta: 0.0,
+			restore_best: false,
+			best: f64::INFINITY,
+			best_epoch: None,
+			best_layers: None,
+			wait: 0,
+			stopped_epoch: None,
+		};
+	}
+
+	pub fn monitor(mut self, monitor: Monitor) -> Self {
+		self.monitor = monitor;
+		return self;
+	}
+
+	pub fn min_delta(mut self, min_delta: f64) -> Self {
+		self.min_delta = min_delta;
+		return self;
+	}
+
+	pub fn restore_best(mut self) -> Self {
+		self.restore_best = true;
+		return self;
+	}
+
+	pub fn best_epoch(&self) -> Option<usize> {
+		return self.best_epoch;
+	}
+
+	pub fn stopped_epoch(&self) -> Option<usize> {
+		/* The epoch after which training was stopped, None if it ran to completion */
+		return self.stopped_epoch;
+	}
+}
+
+impl Callback for EarlyStopping {
+	fn on_train_begin(&mut self, _network: &NeuralNetwork) -> Result<(), NetworkError> {
+		/* Forgets any previous run, so a callback can be reused across fits */
+		*self = Self { best: f64::INFINITY, best_epoch: None, best_layers: None, wait: 0, stopped_epoch: None, ..self.clone() };
+		return Ok(());
+	}
+
+	fn on_epoch_end(&mut self, record: &EpochRecord, network: &NeuralNetwork) -> Result<Control, NetworkError> {
+		let value = self.monitor.value(record);
+		if value < self.best - self.min_delta {
+			self.best = value;
+			self.best_epoch = Some(record.epoch);
+			self.wait = 0;
+			if self.restore_best {
+				self.best_layers = Some(network.layers.clone());
+			}
+			return Ok(Control::Continue);
+		}
+		self.wait += 1;
+		if self.wait >= self.patience {
+			self.stopped_epoch = Some(record.epoch);
+			return Ok(Control::Stop);
+		}
+		return Ok(Control::Continue);
+	}
+
+	fn on_train_end(&mut self, _history: &History, network: &mut NeuralNetwork) -> Result<(), NetworkError> {
+		if let Some(layers) = self.best_layers.take() {
+			network.layers = layers;
+		}
+		return Ok(());
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct ModelCheckpoint {
+	/* Saves the network to path every `every` epochs, never when `every` is 0, or only when
+	the monitored loss improves with best_only. A "{epoch}" in path is replaced by the epoch number. */
+	pub path: String,
+	pub every: usize,
+	pub best_only: bool,
+	pub monitor: Monitor,
+	best: f64,
+}
+
+impl ModelCheckpoint {
+	pub fn new(path: &str) -> Self {
+		return Self { path: path.to_string(), every: 1, best_only: false, monitor: Monitor::default(), best: f64::INFINITY };
+	}
+
+	pub fn every(mut self, epochs: usize) -> Self {
+		self.every = epochs;
+		return self;
+	}
+
+	pub fn best_only(mut self, monitor: Monitor) -> Self {
+		self.best_only = true;
+		self.monitor = monitor;
+		return self;
+	}
+
+	pub fn path_for(&self, epoch: usize) -> String {
+		return self.path.replace("{epoch}", &epoch.to_string());
+	}
+}
+
+impl Callback for ModelCheckpoint {
+	fn on_train_begin(&mut self, _network: &NeuralNetwork) -> Result<(), NetworkError> {
+		self.best = f64::INFINITY;
+		return Ok(());
+	}
+
+	fn on_epoch_end(&mut self, record: &EpochRecord, network: &NeuralNetwork) -> Result<Control, NetworkError> {
+		let save = if self.best_only {
+			let value = self.monitor.value(record);
+			let improved = value < self.best;
+			self.best = self.best.min(value);
+			improved
+		} else {
+			(record.epoch + 1).is_multiple_of(self.every)
+		};
+		if save {
+			network.save_to_file(&self.path_for(record.epoch), false).map_err(|e| NetworkError::Io(e.to_string()))?;
+		}
+		return Ok(Control::Continue);
+	}
+}
+
+pub struct CsvLogger {
+	/* Writes the epoch, training loss, validation loss and learning rate of every epoch
+	to a CSV file, flushed after every row. Validation loss is left empty without validation data. */
+	pub path: String,
+	writer: Option<BufWriter<File>>,
+}
+
+impl CsvLogger {
+	pub fn new(path: &str) -> Self {
+		return Self { path: path.to_string(), writer: None };
+	}
+
+	fn write_line(&mut self, line: &str) -> Result<(), NetworkError> {
+		let Some(writer) = self.writer.as_mut() else {
+			return Ok(());
+		};
+		writeln!(writer, "{}", line).and_then(|_| writer.flush()).map_err(|e| NetworkError::Io(e.to_string()))?;
+		return Ok(());
+	}
+}
+
+impl Callback for CsvLogger {
+	fn on_train_begin(&mut self, _network: &NeuralNetwork) -> Result<(), NetworkError> {
+		let file = File::create(&self.path).map_err(|e| NetworkError::Io(e.to_string()))?;
+		self.writer = Some(BufWriter::new(file));
+		return self.write_line("epoch,loss,validation_loss,learning_rate");
+	}
+
+	fn on_epoch_end(&mut self, record: &EpochRecord, _network: &NeuralNetwork) -> Result<Control, NetworkError> {
+		let validation = record.validation_loss.map_or(String::new(), |v| v.to_string());
+		self.write_line(&format!("{},{},{},{}", record.epoch, record.loss, validation, record.learning_rate))?;
+		return Ok(Control::Continue);
+	}
+
+	fn on_train_end(&mut self, _history: &History, _network: &mut NeuralNetwork) -> Result<(), NetworkError> {
+		self.writer = None;
+		return Ok(());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn record(epoch: usize, loss: f64, validation_loss: Option<f64>) -> EpochRecord {
+		return EpochRecord { epoch, loss, validation_loss, learning_rate: 0.1 };
+	}
+
+	#[test]
+	fn test_early_stopping() {
+		let network = NeuralNetwork::new(&[1, 1]);
+		let mut stopping = EarlyStopping::new(2).min_delta(0.01);
+		stopping.on_train_begin(&network).unwrap();
+		let losses = [1.0, 0.5, 0.495, 0.6, 0.4];
+		let controls: Vec<Control> = losses.iter().enumerate()
+			.map(|(epoch, loss)| stopping.on_epoch_end(&record(epoch, 9.0, Some(*loss)), &network).unwrap())
+			.collect();
+		// 0.495 is not enough of an improvement over 0.5
+		assert_eq!(controls, vec![Control::Continue, Control::Continue, Control::Continue, Control::Stop, Control::Continue]);
+		assert_eq!(stopping.best_epoch(), Some(4));
+		assert_eq!(stopping.stopped_epoch(), Some(3));
+
+		assert_eq!(Monitor::ValidationLoss.value(&record(0, 2.0, None)), 2.0);
+		assert_eq!(Monitor::Loss.value(&record(0, 2.0, Some(1.0))), 2.0);
+	}
+
+	#[test]
+	fn test_checkpoint_paths() {
+		let checkpoint = ModelCheckpoint::new("model_{epoch}.json").every(5);
+		assert_eq!(checkpoint.path_for(12), "model_12.json");
+		assert_eq!(checkpoint.every, 5);
+
+		let network = NeuralNetwork::new(&[1, 1]);
+		let mut never = ModelCheckpoint::new("test_checkpoint_never_{epoch}.json").every(0);
+		for epoch in 0..3 {
+			never.on_epoch_end(&record(epoch, 1.0, None), &network).unwrap();
+			assert!(!std::path::Path::new(&never.path_for(epoch)).exists());
+		}
+	}
+}
@@ -0,0 +1,592 @@
+/*
+Convolution layers.
+Samples stay flat rows of the batch matrix, laid out channel-major: [channel][position] for
+1D inputs and [channel][row][column] for 2D inputs. A 1D convolution is a 2D one over a
+single row, so Conv1D wraps a Conv2D. Convolutions multiply im2col patch matrices with a
+weight matrix holding one row of in_channels * kernel values per output channel.
+*/
+
+use rand::{Rng, RngCore};
+use serde::{Serialize, Deserialize};
+
+use super::activation::Activation;
+use super::initializer::Initializer;
+use super::layer::Layer;
+use super::matrix::Matrix;
+use super::tape::LayerTrace;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Geometry {
+	pub channels: usize,
+	pub height: usize,
+	pub width: usize,
+	pub kernel: [usize; 2],
+	pub stride: [usize; 2],
+	pub padding: [usize; 2],
+	pub dilation: [usize; 2],
+}
+
+impl Geometry {
+	pub fn check(&self) -> Result<(), String> {
+		/* The reason the kernel cannot slide over the input, if any */
+		for (axis, size) in [self.height, self.width].into_iter().enumerate() {
+			if self.kernel[axis] == 0 {
+				return Err(format!("Kernel sizes must be at least 1, got {:?}", self.kernel));
+			}
+			if self.stride[axis] == 0 {
+				return Err(format!("Strides must be at least 1, got {:?}", self.stride));
+			}
+			if self.dilation[axis] == 0 {
+				return Err(format!("Dilations must be at least 1, got {:?}", self.dilation));
+			}
+			let (span, padded) = self.extent(size, axis);
+			if padded < span {
+				return Err(format!("A kernel spanning {} values does not fit an input of {} values padded by {} on each side", span, size, self.padding[axis]));
+			}
+		}
+		return Ok(());
+	}
+
+	pub fn validate(&self) {
+		/* Panics with the reason when the kernel cannot slide over the input */
+		if let Err(reason) = self.check() {
+			panic!("{}", reason);
+		}
+	}
+
+	pub fn output_dims(&self) -> (usize, usize) {
+		let dim = |size: usize, axis: usize| {
+			let (span, padded) = self.extent(size, axis);
+			(padded - span) / self.stride[axis] + 1
+		};
+		return (dim(self.height, 0), dim(self.width, 1));
+	}
+
+	fn extent(&self, size: usize, axis: usize) -> (usize, usize) {
+		/* Values covered by the dilated kernel and the padded input along an axis */
+		let span = self.dilation[axis] * (self.kernel[axis] - 1) + 1;
+		return (span, size + 2 * self.padding[axis]);
+	}
+
+	pub fn input_size(&self) -> usize {
+		return self.channels * self.height * self.width;
+	}
+
+	pub fn patch_size(&self) -> usize {
+		return self.channels * self.kernel[0] * self.kernel[1];
+	}
+
+	pub fn source(&self, oy: usize, ox: usize, ky: usize, kx: usize) -> Option<(usize, usize)> {
+		/* Input row and column read by kernel offset (ky, kx) at output (oy, ox), None in the padding */
+		let y = (oy * self.stride[0] + ky * self.dilation[0]) as isize - self.padding[0] as isize;
+		let x = (ox * self.stride[1] + kx * self.dilation[1]) as isize - self.padding[1] as isize;
+		if y < 0 || x < 0 || y as usize >= self.height || x as usize >= self.width {
+			return None;
+		}
+		return Some((y as usize, x as usize));
+	}
+
+	pub fn im2col(&self, sample: &[f64]) -> Matrix {
+		/* One row per output position holding the input patch it sees, zeros for padding */
+		let (oh, ow) = self.output_dims();
+		let mut cols = Matrix::zeros(oh * ow, self.patch_size());
+		for oy in 0..oh {
+			for ox in 0..ow {
+				let row = cols.row_mut(oy * ow + ox);
+				for c in 0..self.channels {
+					for ky in 0..self.kernel[0] {
+						for kx in 0..self.kernel[1] {
+							if let Some((y, x)) = self.source(oy, ox, ky, kx) {
+								row[(c * self.kernel[0] + ky) * self.kernel[1] + kx] = sample[(c * self.height + y) * self.width + x];
+							}
+						}
+					}
+				}
+			}
+		}
+		return cols;
+	}
+
+	pub fn col2im(&self, cols: &Matrix, sample: &mut [f64]) {
+		/* Adds patch gradients back onto the input positions they were read from */
+		let (oh, ow) = self.output_dims();
+		for oy in 0..oh {
+			for ox in 0..ow {
+				let row = cols.row(oy * ow + ox);
+				for c in 0..self.channels {
+					for ky in 0..self.kernel[0] {
+						for kx in 0..self.kernel[1] {
+							if let Some((y, x)) = self.source(oy, ox, ky, kx) {
+								sample[(c * self.height + y) * self.width + x] += row[(c * self.kernel[0] + ky) * self.kernel[1] + kx];
+							}
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+fn init_kernels(geometry: &Geometry, out_channels: usize, initializer: Initializer, rng: &mut impl Rng) -> (Matrix, Vec<f64>) {
+	let fan_in = geometry.patch_size();
+	let fan_out = out_channels * geometry.kernel[0] * geometry.kernel[1];
+	let weights = (0..out_channels * fan_in).map(|_| initializer.weight(fan_in, fan_out, rng)).collect();
+	let biases = (0..out_channels).map(|_| initializer.bias(rng)).collect();
+	return (Matrix::new(out_channels, fan_in, weights), biases);
+}
+
+fn convolve(geometry: &Geometry, weights: &Matrix, biases: &[f64], inputs: &Matrix) -> Matrix {
+	/* Weighted inputs of a batch, channel-major per sample */
+	let (oh, ow) = geometry.output_dims();
+	let mut z = Matrix::zeros(inputs.rows(), weights.rows() * oh * ow);
+	for r in 0..inputs.rows() {
+		let mut maps = geometry.im2col(inputs.row(r)).matmul_transposed(weights);
+		maps.add_row_vector(biases);
+		z.row_mut(r).copy_from_slice(maps.transpose().data());
+	}
+	return z;
+}
+
+fn convolve_backward(geometry: &Geometry, weights: &Matrix, inputs: &Matrix, deltas: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+	/* Adds the kernel and bias gradients, returns the gradient w.r.t. the inputs */
+	let (oh, ow) = geometry.output_dims();
+	let mut input_grad = Matrix::zeros(inputs.rows(), inputs.cols());
+	for r in 0..inputs.rows() {
+		let cols = geometry.im2col(inputs.row(r));
+		let maps = Matrix::new(weights.rows(), oh * ow, deltas.row(r).to_vec());
+		for (g, d) in gradient[0].iter_mut().zip(maps.matmul(&cols).data()) {
+			*g += d;
+		}
+		for (g, d) in gradient[1].iter_mut().zip(maps.row_sums()) {
+			*g += d;
+		}
+		let patch_grads = maps.transposed_matmul(weights);
+		geometry.col2im(&patch_grads, input_grad.row_mut(r));
+	}
+	return input_grad;
+}
+
+fn activation_deltas(activation: Activation, trace: &LayerTrace, grad: &Matrix) -> Matrix {
+	let z = &trace.cache[0];
+	let rows: Vec<Vec<f64>> = (0..grad.rows()).map(|r| activation.backward(z.row(r), trace.output.row(r), grad.row(r))).collect();
+	return Matrix::from_rows(&rows);
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "ConvRecord")]
+pub struct Conv2D {
+	in_channels: usize,
+	out_channels: usize,
+	// Input height and width
+	height: usize,
+	width: usize,
+	kernel: [usize; 2],
+	stride: [usize; 2],
+	padding: [usize; 2],
+	dilation: [usize; 2],
+	// One row per output channel of in_channels * kernel values
+	weights: Matrix,
+	biases: Vec<f64>,
+	pub activation: Activation,
+}
+
+#[derive(Deserialize)]
+struct ConvRecord {
+	in_channels: usize,
+	out_channels: usize,
+	height: usize,
+	width: usize,
+	kernel: [usize; 2],
+	stride: [usize; 2],
+	padding: [usize; 2],
+	dilation: [usize; 2],
+	weights: Matrix,
+	biases: Vec<f64>,
+	activation: Activation,
+}
+
+impl TryFrom<ConvRecord> for Conv2D {
+	type Error = String;
+
+	fn try_from(record: ConvRecord) -> Result<Self, String> {
+		let layer = Conv2D {
+			in_channels: record.in_channels, out_channels: record.out_channels,
+			height: record.height, width: record.width,
+			kernel: record.kernel, stride: record.stride, padding: record.padding, dilation: record.dilation,
+			weights: record.weights, biases: record.biases, activation: record.activation,
+		};
+		layer.geometry().check()?;
+		layer.check_parameters(&layer.weights, &layer.biases)?;
+		return Ok(layer);
+	}
+}
+
+impl Conv2D {
+	pub const NAME: &'static str = "Conv2D";
+
+	pub fn new(in_channels: usize, out_channels: usize, input: [usize; 2], kernel: [usize; 2], activation: Activation, initializer: Initializer, rng: &mut impl Rng) -> Self {
+		/* Stride 1, no padding and no dilation, see with_stride(), with_padding() and with_dilation() */
+		let mut layer = Self {
+			in_channels, out_channels,
+			height: input[0], width: input[1],
+			kernel, stride: [1, 1], padding: [0, 0], dilation: [1, 1],
+			weights: Matrix::zeros(0, 0), biases: Vec::new(), activation,
+		}.validated();
+		(layer.weights, layer.biases) = init_kernels(&layer.geometry(), out_channels, initializer, rng);
+		return layer;
+	}
+
+	pub fn with_stride(mut self, stride: [usize; 2]) -> Self {
+		self.stride = stride;
+		return self.validated();
+	}
+
+	pub fn with_padding(mut self, padding: [usize; 2]) -> Self {
+		self.padding = padding;
+		return self.validated();
+	}
+
+	pub fn with_dilation(mut self, dilation: [usize; 2]) -> Self {
+		self.dilation = dilation;
+		return self.validated();
+	}
+
+	pub fn with_weights(mut self, weights: Matrix, biases: Vec<f64>) -> Self {
+		/* Replaces the kernels, one row of in_channels * kernel values and one bias per output channel */
+		if let Err(reason) = self.check_parameters(&weights, &biases) {
+			panic!("{}", reason);
+		}
+		(self.weights, self.biases) = (weights, biases);
+		return self;
+	}
+
+	pub fn output_shape(&self) -> [usize; 3] {
+		/* Channels, height and width of the output */
+		let (oh, ow) = self.geometry().output_dims();
+		return [self.out_channels, oh, ow];
+	}
+
+	pub fn in_channels(&self) -> usize {
+		return self.in_channels;
+	}
+
+	pub fn out_channels(&self) -> usize {
+		return self.out_channels;
+	}
+
+	pub fn input_shape(&self) -> [usize; 2] {
+		/* Height and width of the input */
+		return [self.height, self.width];
+	}
+
+	pub fn kernel(&self) -> [usize; 2] {
+		return self.kernel;
+	}
+
+	pub fn stride(&self) -> [usize; 2] {
+		return self.stride;
+	}
+
+	pub fn padding(&self) -> [usize; 2] {
+		return self.padding;
+	}
+
+	pub fn dilation(&self) -> [usize; 2] {
+		return self.dilation;
+	}
+
+	pub fn weights(&self) -> &Matrix {
+		return &self.weights;
+	}
+
+	pub fn biases(&self) -> &[f64] {
+		return &self.biases;
+	}
+
+	fn geometry(&self) -> Geometry {
+		return Geometry {
+			channels: self.in_channels, height: self.height, width: self.width,
+			kernel: self.kernel, stride: self.stride, padding: self.padding, dilation: self.dilation,
+		};
+	}
+
+	fn validated(self) -> Self {
+		self.geometry().validate();
+		return self;
+	}
+
+	fn check_parameters(&self, weights: &Matrix, biases: &[f64]) -> Result<(), String> {
+		let patch_size = self.geometry().patch_size();
+		if weights.rows() != self.out_channels || weights.cols() != patch_size {
+			return Err(format!("Expected {}x{} kernel weights, got {}x{}", self.out_channels, patch_size, weights.rows(), weights.cols()));
+		}
+		if biases.len() != self.out_channels {
+			return Err(format!("Expected {} biases, got {}", self.out_channels, biases.len()));
+		}
+		return Ok(());
+	}
+}
+
+impl Layer for Conv2D {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.geometry().input_size();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.output_shape().iter().product();
+	}
+
+	fn activation(&self) -> Option<Activation> {
+		return Some(self.activation);
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		let z = convolve(&self.geometry(), &self.weights, &self.biases, inputs);
+		return z.map_rows(|row| self.activation.forward(row));
+	}
+
+	fn trace(&self, inputs: Matrix, _rng: &mut dyn RngCore) -> LayerTrace {
+		let z = convolve(&self.geometry(), &self.weights, &self.biases, &inputs);
+		let output = z.map_rows(|row| self.activation.forward(row));
+		return LayerTrace { input: inputs, output, cache: vec![z] };
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		let deltas = activation_deltas(self.activation, trace, grad);
+		return convolve_backward(&self.geometry(), &self.weights, &trace.input, &deltas, gradient);
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		return vec![self.weights.data(), &self.biases];
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return vec![self.weights.data_mut(), &mut self.biases];
+	}
+
+	fn weight_matrices(&self) -> Vec<(&'static str, &Matrix)> {
+		return vec![("weights", &self.weights)];
+	}
+
+	fn weight_matrices_mut(&mut self) -> Vec<(&'static str, &mut Matrix)> {
+		return vec![("weights", &mut self.weights)];
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "Conv2D", into = "Conv2D")]
+pub struct Conv1D {
+	/* A Conv2D over inputs of height 1 with kernels of height 1, persisted as that Conv2D */
+	conv: Conv2D,
+}
+
+impl TryFrom<Conv2D> for Conv1D {
+	type Error = String;
+
+	fn try_from(conv: Conv2D) -> Result<Self, String> {
+		if conv.height != 1 || conv.kernel[0] != 1 || conv.padding[0] != 0 {
+			return Err("A 1D convolution has inputs and kernels of height 1 and no vertical padding".to_string());
+		}
+		return Ok(Self { conv });
+	}
+}
+
+impl From<Conv1D> for Conv2D {
+	fn from(layer: Conv1D) -> Self {
+		return layer.conv;
+	}
+}
+
+impl Conv1D {
+	pub const NAME: &'static str = "Conv1D";
+
+	pub fn new(in_channels: usize, out_channels: usize, length: usize, kernel: usize, activation: Activation, initializer: Initializer, rng: &mut impl Rng) -> Self {
+		/* Stride 1, no padding and no dilation, see with_stride(), with_padding() and with_dilation() */
+		return Self { conv: Conv2D::new(in_channels, out_channels, [1, length], [1, kernel], activation, initializer, rng) };
+	}
+
+	pub fn with_stride(self, stride: usize) -> Self {
+		return Self { conv: self.conv.with_stride([1, stride]) };
+	}
+
+	pub fn with_padding(self, padding: usize) -> Self {
+		return Self { conv: self.conv.with_padding([0, padding]) };
+	}
+
+	pub fn with_dilation(self, dilation: usize) -> Self {
+		return Self { conv: self.conv.with_dilation([1, dilation]) };
+	}
+
+	pub fn with_weights(self, weights: Matrix, biases: Vec<f64>) -> Self {
+		/* Replaces the kernels, one row of in_channels * kernel values and one bias per output channel */
+		return Self { conv: self.conv.with_weights(weights, biases) };
+	}
+
+	pub fn output_shape(&self) -> [usize; 2] {
+		/* Channels and length of the output */
+		let [channels, _, length] = self.conv.output_shape();
+		return [channels, length];
+	}
+
+	pub fn in_channels(&self) -> usize {
+		return self.conv.in_channels;
+	}
+
+	pub fn out_channels(&self) -> usize {
+		return self.conv.out_channels;
+	}
+
+	pub fn length(&self) -> usize {
+		/* Length of the input */
+		return self.conv.width;
+	}
+
+	pub fn kernel(&self) -> usize {
+		return self.conv.kernel[1];
+	}
+
+	pub fn stride(&self) -> usize {
+		return self.conv.stride[1];
+	}
+
+	pub fn padding(&self) -> usize {
+		return self.conv.padding[1];
+	}
+
+	pub fn dilation(&self) -> usize {
+		return self.conv.dilation[1];
+	}
+
+	pub fn weights(&self) -> &Matrix {
+		return &self.conv.weights;
+	}
+
+	pub fn biases(&self) -> &[f64] {
+		return &self.conv.biases;
+	}
+}
+
+impl Layer for Conv1D {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.conv.input_size();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.conv.output_size();
+	}
+
+	fn activation(&self) -> Option<Activation> {
+		return self.conv.activation();
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		return self.conv.forward(inputs);
+	}
+
+	fn trace(&self, inputs: Matrix, rng: &mut dyn RngCore) -> LayerTrace {
+		return self.conv.trace(inputs, rng);
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		return self.conv.backward(trace, grad, gradient);
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		return self.conv.parameters();
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return self.conv.parameters_mut();
+	}
+
+	fn weight_matrices(&self) -> Vec<(&'static str, &Matrix)> {
+		return self.conv.weight_matrices();
+	}
+
+	fn weight_matrices_mut(&mut self) -> Vec<(&'static str, &mut Matrix)> {
+		return self.conv.weight_matrices_mut();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+
+	#[test]
+	fn test_output_shapes() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let conv = Conv2D::new(1, 4, [28, 28], [3, 3], Activation::Relu, Initializer::HeUniform, &mut rng);
+		assert_eq!(conv.output_shape(), [4, 26, 26]);
+		assert_eq!(conv.clone().with_padding([1, 1]).output_shape(), [4, 28, 28]);
+		assert_eq!(conv.clone().with_stride([2, 2]).output_shape(), [4, 13, 13]);
+		assert_eq!(conv.with_dilation([2, 2]).output_shape(), [4, 24, 24]);
+		let conv = Conv1D::new(2, 3, 10, 3, Activation::Tanh, Initializer::XavierUniform, &mut rng);
+		assert_eq!((conv.input_size(), conv.output_size()), (20, 24));
+		assert_eq!(conv.with_padding(2).with_stride(3).output_shape(), [3, 4]);
+	}
+
+	#[test]
+	fn test_convolution() {
+		// A single 2x2 kernel over a 3x3 image, compared with a hand computed cross-correlation
+		let mut rng = StdRng::seed_from_u64(0);
+		let conv = Conv2D::new(1, 1, [3, 3], [2, 2], Activation::Identity, Initializer::Zeros, &mut rng)
+			.with_weights(Matrix::new(1, 4, vec![1.0, 2.0, 3.0, 4.0]), vec![0.5]);
+		let image = Matrix::row_vector((1..=9).map(|x| x as f64).collect());
+		assert_eq!(conv.forward(&image).data(), &[37.5, 47.5, 67.5, 77.5]);
+		// Zero padding adds border positions that only see part of the image
+		let padded = conv.clone().with_padding([1, 1]).forward(&image);
+		assert_eq!(padded.data()[0], 4.0 * 1.0 + 0.5);
+
+		let conv = Conv1D::new(1, 1, 5, 2, Activation::Identity, Initializer::Zeros, &mut rng)
+			.with_dilation(2)
+			.with_weights(Matrix::new(1, 2, vec![1.0, -1.0]), vec![0.0]);
+		assert_eq!((conv.length(), conv.kernel(), conv.dilation()), (5, 2, 2));
+		assert_eq!(conv.forward(&Matrix::row_vector(vec![1.0, 2.0, 4.0, 8.0, 16.0])).data(), &[-3.0, -6.0, -12.0]);
+		let json = serde_json::to_string(&conv).unwrap();
+		assert_eq!(serde_json::from_str::<Conv1D>(&json).unwrap(), conv);
+	}
+
+	#[test]
+	fn test_deserialize_validation() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let conv = Conv2D::new(2, 3, [4, 4], [3, 3], Activation::Relu, Initializer::HeUniform, &mut rng);
+		let json = serde_json::to_string(&conv).unwrap();
+		assert_eq!(serde_json::from_str::<Conv2D>(&json).unwrap(), conv);
+		let error = serde_json::from_str::<Conv2D>(&json.replace("\"stride\":[1,1]", "\"stride\":[0,1]")).unwrap_err();
+		assert!(error.to_string().contains("Strides must be at least 1"));
+		let error = serde_json::from_str::<Conv2D>(&json.replace("\"in_channels\":2", "\"in_channels\":1")).unwrap_err();
+		assert!(error.to_string().contains("Expected 3x9 kernel weights, got 3x18"));
+		// A 2D convolution is not a 1D one
+		assert!(serde_json::from_str::<Conv1D>(&json).is_err());
+	}
+
+	#[test]
+	#[should_panic(expected = "Expected 2 biases, got 1")]
+	fn test_bias_count() {
+		let conv = Conv1D::new(1, 2, 5, 2, Activation::Identity, Initializer::Zeros, &mut StdRng::seed_from_u64(0));
+		conv.with_weights(Matrix::zeros(2, 2), vec![0.0]);
+	}
+
+	#[test]
+	#[should_panic(expected = "Strides must be at least 1")]
+	fn test_zero_stride() {
+		Conv1D::new(1, 1, 5, 2, Activation::Identity, Initializer::Zeros, &mut StdRng::seed_from_u64(0)).with_stride(0);
+	}
+
+	#[test]
+	#[should_panic(expected = "A kernel spanning 5 values does not fit an input of 4 values")]
+	fn test_oversized_kernel() {
+		let conv = Conv2D::new(1, 1, [4, 4], [3, 3], Activation::Identity, Initializer::Zeros, &mut StdRng::seed_from_u64(0));
+		conv.with_dilation([2, 1]);
+	}
+}
@@ -0,0 +1,190 @@
+use rand::{Rng, RngCore};
+
+use super::activation::Activation;
+use super::initializer::Initializer;
+use super::layer::Layer;
+use super::matrix::Matrix;
+use super::regularization::Regularization;
+use super::tape::LayerTrace;
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "DenseRecord")]
+pub struct Dense {
+	// One row of input weights per neuron
+	pub weights: Matrix,
+	pub biases: Vec<f64>,
+	pub activation: Activation,
+	#[serde(skip_serializing_if = "Regularization::is_none")]
+	pub regularization: Regularization,
+}
+
+impl Dense {
+	pub const NAME: &'static str = "Dense";
+
+	pub fn new(num_neurons: usize, input_size: usize, activation: Activation, initializer: Initializer, rng: &mut impl Rng) -> Self {
+		let (weights, biases) = init_dense_layer(input_size, num_neurons, initializer, rng);
+		return Self { weights, biases, activation, regularization: Regularization::default() };
+	}
+
+	pub fn with_regularization(mut self, regularization: Regularization) -> Self {
+		self.regularization = regularization;
+		return self;
+	}
+
+	pub fn deltas(&self, trace: &LayerTrace, grad: &Matrix) -> Matrix {
+		/* Turns the gradient w.r.t. the traced outputs into deltas w.r.t. the weighted inputs */
+		let z = &trace.cache[0];
+		let rows: Vec<Vec<f64>> = (0..grad.rows()).map(|r| {
+			self.activation.backward(z.row(r), trace.output.row(r), grad.row(r))
+		}).collect();
+		return Matrix::from_rows(&rows);
+	}
+
+	pub fn backward_deltas(&self, trace: &LayerTrace, deltas: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		/* Adds the parameter gradients of a traced batch given its deltas,
+		returns the gradient w.r.t. the layer inputs */
+		let weight_gradient = deltas.transposed_matmul(&trace.input);
+		for (g, d) in gradient[0].iter_mut().zip(weight_gradient.data()) {
+			*g += d;
+		}
+		for (g, d) in gradient[1].iter_mut().zip(deltas.column_sums()) {
+			*g += d;
+		}
+		return deltas.matmul(&self.weights);
+	}
+
+	fn weighted_inputs(&self, inputs: &Matrix) -> Matrix {
+		let mut z = inputs.matmul_transposed(&self.weights);
+		z.add_row_vector(&self.biases);
+		return z;
+	}
+}
+
+impl Layer for Dense {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.weights.cols();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.weights.rows();
+	}
+
+	fn activation(&self) -> Option<Activation> {
+		return Some(self.activation);
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		/* Activations for a batch of inputs, one sample per row */
+		let z = self.weighted_inputs(inputs);
+		return z.map_rows(|row| self.activation.forward(row));
+	}
+
+	fn trace(&self, inputs: Matrix, _rng: &mut dyn RngCore) -> LayerTrace {
+		/* Forward pass keeping the weighted inputs for backpropagation */
+		let z = self.weighted_inputs(&inputs);
+		let output = z.map_rows(|row| self.activation.forward(row));
+		return LayerTrace { input: inputs, output, cache: vec![z] };
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		return self.backward_deltas(trace, &self.deltas(trace, grad), gradient);
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		return vec![self.weights.data(), &self.biases];
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return vec![self.weights.data_mut(), &mut self.biases];
+	}
+
+	fn weight_matrices(&self) -> Vec<(&'static str, &Matrix)> {
+		return vec![("weights", &self.weights)];
+	}
+
+	fn weight_matrices_mut(&mut self) -> Vec<(&'static str, &mut Matrix)> {
+		return vec![("weights", &mut self.weights)];
+	}
+
+	fn penalty(&self) -> f64 {
+		return self.regularization.penalty(self.weights.data());
+	}
+
+	fn add_penalty_gradient(&self, gradient: &mut [Vec<f64>]) {
+		self.regularization.add_gradient(self.weights.data(), &mut gradient[0]);
+	}
+
+	fn apply_constraints(&mut self) {
+		for r in 0..self.weights.rows() {
+			self.regularization.constrain(self.weights.row_mut(r));
+		}
+	}
+}
+
+fn init_dense_layer(input_size: usize, output_size: usize, initializer: Initializer, rng: &mut impl Rng) -> (Matrix, Vec<f64>) {
+	/* Initializes the layer's weights and biases from an initializer, neuron by neuron */
+	let mut weights = Vec::with_capacity(input_size * output_size);
+	let mut biases = Vec::with_capacity(output_size);
+	for _ in 0..output_size {
+		weights.extend((0..input_size).map(|_| initializer.weight(input_size, output_size, rng)));
+		biases.push(initializer.bias(rng));
+	}
+	return (Matrix::new(output_size, input_size, weights), biases);
+}
+
+#[derive(Deserialize)]
+struct NeuronRecord {
+	weights: Vec<f64>,
+	bias: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DenseRecord {
+	/* Accepted layer layouts, the current one and the per-neuron one written by earlier versions */
+	Matrix {
+		weights: Matrix,
+		biases: Vec<f64>,
+		#[serde(default)]
+		activation: Activation,
+		#[serde(default)]
+		regularization: Regularization,
+	},
+	Neurons {
+		neurons: Vec<NeuronRecord>,
+		#[serde(default)]
+		activation: Activation,
+	},
+}
+
+impl TryFrom<DenseRecord> for Dense {
+	type Error = String;
+
+	fn try_from(record: DenseRecord) -> Result<Self, String> {
+		let layer = match record {
+			DenseRecord::Matrix { weights, biases, activation, regularization } => {
+				Dense { weights, biases, activation, regularization }
+			},
+			DenseRecord::Neurons { neurons, activation } => {
+				let input_size = neurons.first().map_or(0, |n| n.weights.len());
+				if neurons.iter().any(|n| n.weights.len() != input_size) {
+					return Err("Neurons of a layer differ in their number of weights".to_string());
+				}
+				let weights = neurons.iter().flat_map(|n| n.weights.iter().cloned()).collect();
+				let biases = neurons.iter().map(|n| n.bias).collect();
+				let weights = Matrix::new(neurons.len(), input_size, weights);
+				Dense { weights, biases, activation, regularization: Regularization::default() }
+			},
+		};
+		if layer.biases.len() != layer.weights.rows() {
+			return Err("Expected one bias per weight matrix row".to_string());
+		}
+		return Ok(layer);
+	}
+}
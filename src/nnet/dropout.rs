@@ -0,0 +1,83 @@
+use rand::{Rng, RngCore};
+use serde::{Serialize, Deserialize};
+
+use super::layer::Layer;
+use super::matrix::Matrix;
+use super::tape::LayerTrace;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Dropout {
+	/* Inverted dropout: during training every value is zeroed with probability rate and the
+	survivors are scaled by 1/(1-rate), so inference passes values through unchanged. */
+	pub size: usize,
+	pub rate: f64,
+}
+
+impl Dropout {
+	pub const NAME: &'static str = "Dropout";
+
+	pub fn new(size: usize, rate: f64) -> Self {
+		assert!((0.0..1.0).contains(&rate), "Dropout rate must be in [0, 1)");
+		return Self { size, rate };
+	}
+}
+
+impl Layer for Dropout {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.size;
+	}
+
+	fn output_size(&self) -> usize {
+		return self.size;
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		return inputs.clone();
+	}
+
+	fn trace(&self, inputs: Matrix, rng: &mut dyn RngCore) -> LayerTrace {
+		/* Samples a mask for the batch and keeps it for backpropagation */
+		let keep = 1.0 - self.rate;
+		let mask_data = (0..inputs.data().len())
+			.map(|_| if rng.gen::<f64>() < keep { 1.0 / keep } else { 0.0 })
+			.collect();
+		let mask = Matrix::new(inputs.rows(), inputs.cols(), mask_data);
+		let output_data = inputs.data().iter().zip(mask.data()).map(|(x, m)| x * m).collect();
+		let output = Matrix::new(inputs.rows(), inputs.cols(), output_data);
+		return LayerTrace { input: inputs, output, cache: vec![mask] };
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, _gradient: &mut [Vec<f64>]) -> Matrix {
+		let mask = &trace.cache[0];
+		let data = grad.data().iter().zip(mask.data()).map(|(g, m)| g * m).collect();
+		return Matrix::new(grad.rows(), grad.cols(), data);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+
+	#[test]
+	fn test_inverted_dropout() {
+		let dropout = Dropout::new(1000, 0.25);
+		let inputs = Matrix::new(1, 1000, vec![1.0; 1000]);
+		assert_eq!(dropout.forward(&inputs), inputs);
+
+		let mut rng = StdRng::seed_from_u64(0);
+		let trace = dropout.trace(inputs.clone(), &mut rng);
+		let dropped = trace.output.data().iter().filter(|x| **x == 0.0).count();
+		assert!((150..350).contains(&dropped), "Dropped {} of 1000", dropped);
+		// Surviving values are scaled so the expected activation is unchanged
+		assert!(trace.output.data().iter().all(|x| *x == 0.0 || (x - 1.0 / 0.75).abs() < 1e-12));
+
+		let grad = dropout.backward(&trace, &inputs, &mut []);
+		assert_eq!(grad, trace.output);
+	}
+}
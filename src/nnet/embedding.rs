@@ -0,0 +1,186 @@
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use super::error::NetworkError;
+use super::initializer::Initializer;
+use super::layer::Layer;
+use super::matrix::Matrix;
+use super::tape::LayerTrace;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Embedding {
+	/* Looks up a trainable vector for every token of a sample. Samples hold length token ids,
+	as produced by Vocabulary::ids(), and come out as their length vectors laid end to end. */
+	pub length: usize,
+	// One row of dimension values per token id
+	pub vectors: Matrix,
+}
+
+impl Embedding {
+	pub const NAME: &'static str = "Embedding";
+
+	pub fn new(vocabulary_size: usize, dimension: usize, length: usize, rng: &mut impl Rng) -> Self {
+		let initializer = Initializer::Uniform(0.05);
+		let data = (0..vocabulary_size * dimension).map(|_| initializer.weight(vocabulary_size, dimension, rng)).collect();
+		return Self { length, vectors: Matrix::new(vocabulary_size, dimension, data) };
+	}
+
+	pub fn from_vectors(vectors: Matrix, length: usize) -> Self {
+		/* Starts from pretrained vectors, one row per token id */
+		return Self { length, vectors };
+	}
+
+	pub fn vocabulary_size(&self) -> usize {
+		return self.vectors.rows();
+	}
+
+	pub fn dimension(&self) -> usize {
+		return self.vectors.cols();
+	}
+
+	pub fn vector(&self, id: usize) -> Option<&[f64]> {
+		if id >= self.vocabulary_size() {
+			return None;
+		}
+		return Some(self.vectors.row(id));
+	}
+
+	pub fn nearest(&self, id: usize, count: usize) -> Vec<(usize, f64)> {
+		/* The count token ids whose vectors are most similar to the one of id, itself excluded,
+		with their cosine similarity, most similar first */
+		let Some(vector) = self.vector(id) else {
+			return Vec::new();
+		};
+		let mut neighbours = self.nearest_to(vector, count + 1);
+		neighbours.retain(|(other, _)| *other != id);
+		neighbours.truncate(count);
+		return neighbours;
+	}
+
+	pub fn nearest_to(&self, vector: &[f64], count: usize) -> Vec<(usize, f64)> {
+		/* The count token ids whose vectors are most similar to vector by cosine similarity */
+		let mut similarities: Vec<(usize, f64)> = (0..self.vocabulary_size())
+			.map(|id| (id, cosine_similarity(vector, self.vectors.row(id))))
+			.collect();
+		similarities.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+		similarities.truncate(count);
+		return similarities;
+	}
+
+	pub fn token_id(&self, value: f64) -> Option<usize> {
+		/* The token id an input value stands for, None unless it is a whole number within the vocabulary */
+		if value < 0.0 || value.fract() != 0.0 || value >= self.vocabulary_size() as f64 {
+			return None;
+		}
+		return Some(value as usize);
+	}
+
+	fn id(&self, value: f64) -> usize {
+		/* Inputs reaching the layer through a network are checked by check_input() */
+		return self.token_id(value)
+			.unwrap_or_else(|| panic!("Token id {} is outside the vocabulary of {}", value, self.vocabulary_size()));
+	}
+}
+
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+	/* Cosine of the angle between a and b, 0 when either is the zero vector */
+	let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+	let norms = a.iter().map(|x| x * x).sum::<f64>().sqrt() * b.iter().map(|x| x * x).sum::<f64>().sqrt();
+	if norms == 0.0 {
+		return 0.0;
+	}
+	return dot / norms;
+}
+
+impl Layer for Embedding {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.length;
+	}
+
+	fn output_size(&self) -> usize {
+		return self.length * self.dimension();
+	}
+
+	fn check_input(&self, input: &[f64]) -> Result<(), NetworkError> {
+		if let Some(value) = input.iter().find(|v| self.token_id(**v).is_none()) {
+			return Err(NetworkError::InvalidToken { value: *value, vocabulary: self.vocabulary_size() });
+		}
+		return Ok(());
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		return inputs.map_rows(|ids| ids.iter().flat_map(|id| self.vectors.row(self.id(*id)).to_vec()).collect());
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		/* Scatters the gradient of every looked up vector into its row. Token ids are not
+		differentiable, so the gradient w.r.t. the inputs is zero. */
+		let dimension = self.dimension();
+		for r in 0..grad.rows() {
+			for (position, id) in trace.input.row(r).iter().enumerate() {
+				let row = self.id(*id) * dimension;
+				let g = &grad.row(r)[position * dimension..(position + 1) * dimension];
+				for (t, d) in gradient[0][row..row + dimension].iter_mut().zip(g) {
+					*t += d;
+				}
+			}
+		}
+		return Matrix::zeros(grad.rows(), self.length);
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		return vec![self.vectors.data()];
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return vec![self.vectors.data_mut()];
+	}
+
+	fn weight_matrices(&self) -> Vec<(&'static str, &Matrix)> {
+		return vec![("vectors", &self.vectors)];
+	}
+
+	fn weight_matrices_mut(&mut self) -> Vec<(&'static str, &mut Matrix)> {
+		return vec![("vectors", &mut self.vectors)];
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_lookup() {
+		let vectors = Matrix::from_rows(&[vec![1.0, 0.0], vec![0.0, 1.0], vec![2.0, 3.0]]);
+		let embedding = Embedding::from_vectors(vectors, 3);
+		assert_eq!(embedding.output_size(), 6);
+		let inputs = Matrix::from_rows(&[vec![2.0, 0.0, 2.0], vec![1.0, 1.0, 0.0]]);
+		let outputs = embedding.forward(&inputs);
+		assert_eq!(outputs.row(0), &[2.0, 3.0, 1.0, 0.0, 2.0, 3.0]);
+		assert_eq!(outputs.row(1), &[0.0, 1.0, 0.0, 1.0, 1.0, 0.0]);
+
+		// Repeated tokens accumulate the gradients of every position they appear at
+		let trace = LayerTrace { input: inputs, output: outputs, cache: vec![] };
+		let grad = Matrix::from_rows(&[vec![1.0, 1.0, 2.0, 2.0, 3.0, 3.0], vec![0.5, 0.5, 0.5, 0.5, 1.0, 1.0]]);
+		let mut gradient = embedding.zero_gradient();
+		let input_grad = embedding.backward(&trace, &grad, &mut gradient);
+		assert_eq!(gradient[0], vec![3.0, 3.0, 1.0, 1.0, 4.0, 4.0]);
+		assert_eq!(input_grad, Matrix::zeros(2, 3));
+	}
+
+	#[test]
+	fn test_nearest_neighbours() {
+		let vectors = Matrix::from_rows(&[vec![1.0, 0.1], vec![0.0, 1.0], vec![2.0, 0.0], vec![-1.0, 0.0], vec![0.0, 0.0]]);
+		let embedding = Embedding::from_vectors(vectors, 1);
+		let nearest = embedding.nearest(0, 2);
+		assert_eq!(nearest.iter().map(|n| n.0).collect::<Vec<_>>(), vec![2, 1]);
+		assert!((nearest[0].1 - 1.0 / 1.01f64.sqrt()).abs() < 1e-12);
+		assert_eq!(embedding.nearest_to(&[-3.0, 0.0], 1), vec![(3, 1.0)]);
+		assert_eq!(embedding.nearest(7, 2), vec![]);
+		assert_eq!(cosine_similarity(&[1.0, 1.0], &[0.0, 0.0]), 0.0);
+	}
+}
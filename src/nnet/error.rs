@@ -0,0 +1,46 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkError {
+	// An input vector does not match the width of the first layer
+	InputSize { expected: usize, found: usize },
+	// A target vector does not match the width of the output layer
+	TargetSize { expected: usize, found: usize },
+	// A layer does not accept the output width of the layer before it
+	LayerSize { layer: usize, expected: usize, found: usize },
+	// A sequence needs one target per step, or a single one for sequence-to-one models
+	TargetCount { expected: usize, found: usize },
+	EmptySequence,
+	EmptyNetwork,
+	// An input value is not a token id of the vocabulary of an Embedding layer
+	InvalidToken { value: f64, vocabulary: usize },
+	// A checkpoint or training log could not be written
+	Io(String),
+}
+
+impl fmt::Display for NetworkError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			NetworkError::InputSize { expected, found } => {
+				write!(f, "Input has {} values, the network expects {}", found, expected)
+			},
+			NetworkError::TargetSize { expected, found } => {
+				write!(f, "Target has {} values, the network outputs {}", found, expected)
+			},
+			NetworkError::LayerSize { layer, expected, found } => {
+				write!(f, "Layer {} takes {} values, the previous layer outputs {}", layer, found, expected)
+			},
+			NetworkError::TargetCount { expected, found } => {
+				write!(f, "Sequence has {} targets, expected {}", found, expected)
+			},
+			NetworkError::EmptySequence => write!(f, "The sequence has no steps"),
+			NetworkError::EmptyNetwork => write!(f, "The network has no layers"),
+			NetworkError::InvalidToken { value, vocabulary } => {
+				write!(f, "{} is not a token id of a vocabulary of {}", value, vocabulary)
+			},
+			NetworkError::Io(message) => write!(f, "I/O error: {}", message),
+		}
+	}
+}
+
+impl std::error::Error for NetworkError {}
@@ -0,0 +1,320 @@
+/*
+Numerical gradient checking.
+Compares the gradients computed by backpropagation with central finite differences
+(L(w + e) - L(w - e)) / 2e, one parameter at a time. Both sides see the same dropout
+masks and include the regularization penalty. Layers that mix the samples of a batch, such
+as batch normalization, are only exercised by gradient_check_batch().
+*/
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use super::error::NetworkError;
+use super::network::NeuralNetwork;
+use super::training::Sample;
+
+const SEED: u64 = 0;
+// Gradients smaller than this are compared absolutely, float noise would dominate their relative error
+const MIN_SCALE: f64 = 1e-7;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParameterCheck {
+	pub layer: usize,
+	// Index of the parameter tensor within the layer, e.g. 0 for dense weights and 1 for biases
+	pub tensor: usize,
+	pub index: usize,
+	pub analytic: f64,
+	pub numeric: f64,
+	pub relative_error: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GradientCheck {
+	pub parameters: Vec<ParameterCheck>,
+	pub max_relative_error: f64,
+	// Number of layers of the checked network
+	pub layers: usize,
+}
+
+impl GradientCheck {
+	pub fn worst(&self) -> Option<&ParameterCheck> {
+		/* The parameter with the largest relative error */
+		return self.parameters.iter().max_by(|a, b| a.relative_error.total_cmp(&b.relative_error));
+	}
+
+	pub fn layer_errors(&self) -> Vec<f64> {
+		/* Largest relative error of every layer, 0 for layers without parameters */
+		let mut errors = vec![0.0; self.layers];
+		for p in &self.parameters {
+			errors[p.layer] = f64::max(errors[p.layer], p.relative_error);
+		}
+		return errors;
+	}
+}
+
+pub fn relative_error(analytic: f64, numeric: f64) -> f64 {
+	/* |a - n| / max(|a|, |n|), with small magnitudes floored at MIN_SCALE */
+	return (analytic - numeric).abs() / analytic.abs().max(numeric.abs()).max(MIN_SCALE);
+}
+
+pub fn gradient_check(network: &NeuralNetwork, input: &[f64], target: &[f64], epsilon: f64) -> Result<GradientCheck, NetworkError> {
+	/* Checks the gradient of the network's training loss on one sample w.r.t. every parameter.
+	A batch of one sample has no batch statistics to normalize with, check batch normalization
+	with gradient_check_batch(). */
+	return gradient_check_batch(network, &[(input.to_vec(), target.to_vec())], epsilon);
+}
+
+pub fn gradient_check_batch(network: &NeuralNetwork, batch: &[Sample], epsilon: f64) -> Result<GradientCheck, NetworkError> {
+	/* Checks the gradient of the network's mean training loss over a batch w.r.t. every parameter */
+	for (input, target) in batch {
+		network.check_sample(input, target)?;
+	}
+	if batch.is_empty() {
+		return Ok(GradientCheck { parameters: Vec::new(), max_relative_error: 0.0, layers: network.layers.len() });
+	}
+	let (_, gradients) = network.batch_gradients(batch, &mut StdRng::seed_from_u64(SEED));
+	let mut scratch = network.clone();
+	let mut parameters = Vec::new();
+	for (layer, layer_gradients) in gradients.iter().enumerate() {
+		for (tensor, tensor_gradient) in layer_gradients.iter().enumerate() {
+			for (index, analytic) in tensor_gradient.iter().enumerate() {
+				let original = scratch.layers[layer].parameters()[tensor][index];
+				scratch.layers[layer].parameters_mut()[tensor][index] = original + epsilon;
+				let plus = scratch.batch_loss(batch, &mut StdRng::seed_from_u64(SEED));
+				scratch.layers[layer].parameters_mut()[tensor][index] = original - epsilon;
+				let minus = scratch.batch_loss(batch, &mut StdRng::seed_from_u64(SEED));
+				scratch.layers[layer].parameters_mut()[tensor][index] = original;
+
+				let numeric = (plus - minus) / (2.0 * epsilon);
+				let relative_error = relative_error(*analytic, numeric);
+				parameters.push(ParameterCheck { layer, tensor, index, analytic: *analytic, numeric, relative_error });
+			}
+		}
+	}
+	let max_relative_error = parameters.iter().map(|p| p.relative_error).fold(0.0, f64::max);
+	return Ok(GradientCheck { parameters, max_relative_error, layers: network.layers.len() });
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::nnet::activation::{Activation, ActivationLayer};
+	use crate::nnet::autodiff::{Graph, Var};
+	use crate::nnet::conv::{Conv1D, Conv2D};
+	use crate::nnet::dense::Dense;
+	use crate::nnet::pooling::{Pool1D, Pool2D};
+	use crate::nnet::reshape::Reshape;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use crate::nnet::initializer::Initializer;
+	use crate::nnet::loss::LossFunction;
+	use crate::nnet::layer::Layer;
+	use crate::nnet::matrix::Matrix;
+	use crate::nnet::regularization::Regularization;
+	use crate::nnet::tape::LayerTrace;
+
+	const EPSILON: f64 = 1e-5;
+	const TOLERANCE: f64 = 1e-5;
+
+	fn assert_gradients(network: &NeuralNetwork, input: &[f64], target: &[f64]) {
+		let check = gradient_check(network, input, target, EPSILON).unwrap();
+		assert!(!check.parameters.is_empty());
+		assert!(check.max_relative_error < TOLERANCE, "Worst parameter: {:?}", check.worst());
+	}
+
+	#[test]
+	fn test_activations() {
+		let activations = [
+			Activation::Sigmoid, Activation::Tanh, Activation::Relu, Activation::LeakyRelu(0.1),
+			Activation::Elu(1.0), Activation::Gelu, Activation::Softplus, Activation::Identity,
+		];
+		for activation in activations {
+			let nn = NeuralNetwork::builder(&[3, 5, 2])
+				.activation(activation)
+				.output_activation(activation)
+				.initializer(Initializer::XavierNormal)
+				.seed(1)
+				.build();
+			assert_gradients(&nn, &[0.3, -0.8, 1.2], &[0.2, 0.9]);
+		}
+		let nn = NeuralNetwork::builder(&[3, 4, 3]).output_activation(Activation::Softmax).seed(2).build();
+		assert_gradients(&nn, &[0.3, -0.8, 1.2], &[0.2, 0.5, 0.3]);
+	}
+
+	#[test]
+	fn test_losses() {
+		let cases = [
+			(LossFunction::MeanSquaredError, Activation::Identity),
+			(LossFunction::Huber(0.5), Activation::Identity),
+			(LossFunction::BinaryCrossEntropy, Activation::Sigmoid),
+			(LossFunction::CategoricalCrossEntropy, Activation::Softmax),
+		];
+		for (loss, output) in cases {
+			let nn = NeuralNetwork::builder(&[2, 4, 3]).activation(Activation::Tanh).output_activation(output).loss(loss).seed(3).build();
+			assert_gradients(&nn, &[0.7, -0.4], &[1.0, 0.0, 0.0]);
+		}
+	}
+
+	#[test]
+	fn test_layers() {
+		let nn = NeuralNetwork::builder(&[4, 6, 5, 2])
+			.activation(Activation::Tanh)
+			.initializer(Initializer::HeNormal)
+			.layer_norm(0)
+			.dropout(1, 0.3)
+			.regularization(Regularization { l1: 0.0, l2: 0.01, max_norm: None })
+			.seed(4)
+			.build();
+		assert_gradients(&nn, &[0.5, -1.0, 0.25, 2.0], &[0.3, 0.6]);
+	}
+
+	#[test]
+	fn test_batch_normalization() {
+		let nn = NeuralNetwork::builder(&[3, 4, 2])
+			.activation(Activation::Tanh)
+			.batch_norm(0)
+			.seed(8)
+			.build();
+		let batch: Vec<Sample> = vec![
+			(vec![0.5, -1.0, 0.25], vec![0.3, 0.6]),
+			(vec![-0.2, 0.4, 1.5], vec![0.9, 0.1]),
+			(vec![1.1, 0.3, -0.7], vec![0.2, 0.4]),
+		];
+		let check = gradient_check_batch(&nn, &batch, EPSILON).unwrap();
+		assert!(check.max_relative_error < TOLERANCE, "Worst parameter: {:?}", check.worst());
+		// BatchNorm parameters are checked too, through the batch statistics
+		assert!(check.parameters.iter().any(|p| p.layer == 1));
+		assert_eq!(gradient_check_batch(&nn, &[], EPSILON).unwrap().parameters, vec![]);
+	}
+
+	#[test]
+	fn test_convolution_layers() {
+		let mut rng = StdRng::seed_from_u64(7);
+		let conv = Conv2D::new(2, 3, [5, 5], [3, 3], Activation::Tanh, Initializer::XavierUniform, &mut rng)
+			.with_padding([1, 1])
+			.with_stride([2, 1])
+			.with_dilation([1, 2]);
+		let [channels, height, width] = conv.output_shape();
+		let layers: Vec<Box<dyn Layer>> = vec![
+			Box::new(conv),
+			Box::new(Pool2D::average(channels, [height, width], [1, 2])),
+			Box::new(Reshape::flatten(&[channels, height, width / 2])),
+			Box::new(Dense::new(2, channels * height * (width / 2), Activation::Identity, Initializer::XavierUniform, &mut rng)),
+		];
+		let nn = NeuralNetwork::from_layers(layers).unwrap();
+		let input: Vec<f64> = (0..50).map(|i| (i as f64 * 0.37).sin()).collect();
+		assert_gradients(&nn, &input, &[0.5, -0.5]);
+
+		let conv = Conv1D::new(2, 2, 8, 3, Activation::Sigmoid, Initializer::XavierNormal, &mut rng).with_padding(1).with_dilation(2);
+		let [channels, length] = conv.output_shape();
+		let layers: Vec<Box<dyn Layer>> = vec![
+			Box::new(conv),
+			Box::new(Pool1D::max(channels, length, 2)),
+			Box::new(Dense::new(1, channels * length / 2, Activation::Identity, Initializer::XavierUniform, &mut rng)),
+		];
+		let nn = NeuralNetwork::from_layers(layers).unwrap();
+		let input: Vec<f64> = (0..16).map(|i| (i as f64 * 0.91).cos()).collect();
+		assert_gradients(&nn, &input, &[0.3]);
+	}
+
+	#[derive(serde::Serialize, Clone)]
+	struct Gated {
+		// size x size matrix, flattened
+		weights: Vec<f64>,
+		bias: Vec<f64>,
+	}
+
+	impl Layer for Gated {
+		fn name(&self) -> &'static str {
+			return "Gated";
+		}
+
+		fn input_size(&self) -> usize {
+			return self.bias.len();
+		}
+
+		fn output_size(&self) -> usize {
+			return self.bias.len();
+		}
+
+		fn build_graph(&self, graph: &mut Graph, input: Var, parameters: &[Var]) -> Option<Var> {
+			// x * sigmoid(x W^T) + b
+			let size = self.bias.len();
+			let weights = graph.reshape(parameters[0], size, size);
+			let z = graph.matmul_transposed(input, weights);
+			let gate = graph.activation(z, Activation::Sigmoid);
+			let gated = graph.mul(input, gate);
+			return Some(graph.add_row(gated, parameters[1]));
+		}
+
+		fn parameters(&self) -> Vec<&[f64]> {
+			return vec![&self.weights, &self.bias];
+		}
+
+		fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+			return vec![&mut self.weights, &mut self.bias];
+		}
+	}
+
+	#[test]
+	fn test_graph_layers() {
+		// Layers described by build_graph() are differentiated by the autodiff graph
+		let mut nn = NeuralNetwork::builder(&[3, 2]).activation(Activation::Tanh).seed(6).build();
+		nn.layers.insert(0, Box::new(Gated { weights: vec![0.2, -0.5, 0.9, 0.1, 0.4, -0.3, -0.8, 0.6, 0.05], bias: vec![0.1, 0.0, -0.1] }));
+		nn.layers.push(Box::new(ActivationLayer::new(2, Activation::Softmax)));
+		nn.loss = LossFunction::CategoricalCrossEntropy;
+		assert_gradients(&nn, &[0.5, -1.5, 0.8], &[0.0, 1.0]);
+	}
+
+	#[derive(serde::Serialize, Clone)]
+	struct BrokenBias {
+		bias: Vec<f64>,
+	}
+
+	impl Layer for BrokenBias {
+		fn name(&self) -> &'static str {
+			return "BrokenBias";
+		}
+
+		fn input_size(&self) -> usize {
+			return self.bias.len();
+		}
+
+		fn output_size(&self) -> usize {
+			return self.bias.len();
+		}
+
+		fn forward(&self, inputs: &Matrix) -> Matrix {
+			return inputs.map_rows(|row| row.iter().zip(&self.bias).map(|(x, b)| x + b).collect());
+		}
+
+		fn backward(&self, _trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+			// Doubles the bias gradient
+			for (g, d) in gradient[0].iter_mut().zip(grad.column_sums()) {
+				*g += 2.0 * d;
+			}
+			return grad.clone();
+		}
+
+		fn parameters(&self) -> Vec<&[f64]> {
+			return vec![&self.bias];
+		}
+
+		fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+			return vec![&mut self.bias];
+		}
+	}
+
+	#[test]
+	fn test_detects_wrong_gradients() {
+		let mut nn = NeuralNetwork::builder(&[2, 3]).seed(5).build();
+		nn.layers.push(Box::new(BrokenBias { bias: vec![0.1, 0.2, 0.3] }));
+		let check = gradient_check(&nn, &[0.4, 0.6], &[1.0, 0.0, 1.0], EPSILON).unwrap();
+		assert_eq!(check.worst().unwrap().layer, 1);
+		assert!(check.max_relative_error > 0.4);
+		assert_eq!(check.layer_errors().len(), 2);
+		assert!(check.layer_errors()[0] < TOLERANCE);
+
+		assert_eq!(gradient_check(&nn, &[1.0], &[0.0, 1.0, 0.0], EPSILON).err(), Some(NetworkError::InputSize { expected: 2, found: 1 }));
+	}
+}
@@ -0,0 +1,96 @@
+use std::f64::consts::PI;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Initializer {
+	// Weights and biases drawn from -limit..limit, the historic NeuralNetwork::new behavior
+	Uniform(f64),
+	XavierUniform,
+	XavierNormal,
+	HeUniform,
+	HeNormal,
+	LecunUniform,
+	LecunNormal,
+	Zeros,
+	Constant(f64),
+}
+
+impl Default for Initializer {
+	fn default() -> Self {
+		return Initializer::Uniform(1.0);
+	}
+}
+
+impl Initializer {
+	pub fn weight(&self, fan_in: usize, fan_out: usize, rng: &mut impl Rng) -> f64 {
+		/* Draws one weight of a layer with fan_in inputs and fan_out outputs */
+		let fan_in = fan_in.max(1) as f64;
+		let fan_out = fan_out.max(1) as f64;
+		match *self {
+			Initializer::Uniform(limit) => uniform(limit, rng),
+			Initializer::XavierUniform => uniform((6.0 / (fan_in + fan_out)).sqrt(), rng),
+			Initializer::XavierNormal => normal((2.0 / (fan_in + fan_out)).sqrt(), rng),
+			Initializer::HeUniform => uniform((6.0 / fan_in).sqrt(), rng),
+			Initializer::HeNormal => normal((2.0 / fan_in).sqrt(), rng),
+			Initializer::LecunUniform => uniform((3.0 / fan_in).sqrt(), rng),
+			Initializer::LecunNormal => normal((1.0 / fan_in).sqrt(), rng),
+			Initializer::Zeros => 0.0,
+			Initializer::Constant(c) => c,
+		}
+	}
+
+	pub fn bias(&self, rng: &mut impl Rng) -> f64 {
+		/* Draws one bias. Variance-scaling initializers start biases at zero. */
+		match *self {
+			Initializer::Uniform(limit) => uniform(limit, rng),
+			Initializer::Constant(c) => c,
+			_ => 0.0,
+		}
+	}
+}
+
+fn uniform(limit: f64, rng: &mut impl Rng) -> f64 {
+	if limit <= 0.0 {
+		return 0.0;
+	}
+	return rng.gen_range(-limit..limit);
+}
+
+fn normal(std_dev: f64, rng: &mut impl Rng) -> f64 {
+	/* Box-Muller transform of two uniform samples */
+	let u1: f64 = 1.0 - rng.gen::<f64>();
+	let u2: f64 = rng.gen();
+	return std_dev * (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+
+	#[test]
+	fn test_uniform_limits() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let limit = (6.0f64 / 30.0).sqrt();
+		for _ in 0..1000 {
+			let w = Initializer::XavierUniform.weight(10, 20, &mut rng);
+			assert!(w.abs() <= limit);
+		}
+		assert_eq!(Initializer::XavierUniform.bias(&mut rng), 0.0);
+		assert_eq!(Initializer::Constant(0.3).weight(10, 20, &mut rng), 0.3);
+		assert_eq!(Initializer::Zeros.weight(10, 20, &mut rng), 0.0);
+	}
+
+	#[test]
+	fn test_normal_variance() {
+		let mut rng = StdRng::seed_from_u64(1);
+		let n = 20000;
+		let samples: Vec<f64> = (0..n).map(|_| Initializer::HeNormal.weight(50, 10, &mut rng)).collect();
+		let mean = samples.iter().sum::<f64>() / n as f64;
+		let variance = samples.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / n as f64;
+		assert!(mean.abs() < 0.01, "Mean {}", mean);
+		assert!((variance - 2.0 / 50.0).abs() < 0.003, "Variance {}", variance);
+	}
+}
@@ -1,30 +1,260 @@
-use super::neuron::Neuron;
+/*
+Layers of a NeuralNetwork.
+A network is a stack of Box<dyn Layer>, persisted as JSON objects tagged with the layer's
+"type". Built-in layers are known by their tag, custom layers must be registered with
+register_layer() before a network containing them is loaded.
+*/
 
-use serde::{Serialize, Deserialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
 
-#[derive(Serialize, Deserialize)]
-pub struct Layer {
-	pub neurons: Vec<Neuron>,
-	last_output: Vec<f64>,
+use rand::RngCore;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{DeserializeOwned, Error as DeError};
+use serde::ser::Error as SerError;
+use serde_json::Value;
+
+use super::activation::{Activation, ActivationLayer};
+use super::autodiff::{Graph, Var};
+use super::conv::{Conv1D, Conv2D};
+use super::dense::Dense;
+use super::dropout::Dropout;
+use super::embedding::Embedding;
+use super::error::NetworkError;
+use super::matrix::Matrix;
+use super::normalization::{BatchNorm, LayerNorm};
+use super::pooling::{Pool1D, Pool2D};
+use super::recurrent::Recurrent;
+use super::reshape::Reshape;
+use super::tape::LayerTrace;
+use super::time_distributed::TimeDistributed;
+
+pub trait Layer: LayerObject + Send + Sync {
+	// Tag written to the "type" field when the layer is persisted
+	fn name(&self) -> &'static str;
+
+	fn input_size(&self) -> usize;
+
+	fn output_size(&self) -> usize;
+
+	fn output_size_for(&self, input_size: usize) -> Option<usize> {
+		/* Number of values output for a sample of input_size values, None if the layer cannot
+		take it. Layers over sequences accept any number of steps. */
+		if input_size != self.input_size() {
+			return None;
+		}
+		return Some(self.output_size());
+	}
+
+	fn activation(&self) -> Option<Activation> {
+		return None;
+	}
+
+	fn check_input(&self, _input: &[f64]) -> Result<(), NetworkError> {
+		/* Rejects input values the layer cannot handle, called on every sample given to the
+		first layer of a network before the forward pass. Sizes are checked by the network. */
+		return Ok(());
+	}
+
+	fn build_graph(&self, _graph: &mut Graph, _input: Var, _parameters: &[Var]) -> Option<Var> {
+		/* Describes the forward pass as autodiff operations on the input batch and on every
+		parameter tensor as a 1xN row vector. Layers doing so get forward() and backward() for free. */
+		return None;
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		let mut graph = Graph::new();
+		let (_, _, output) = self.graph_pass(&mut graph, inputs.clone());
+		return graph.value(output).clone();
+	}
+
+	fn trace(&self, inputs: Matrix, _rng: &mut dyn RngCore) -> LayerTrace {
+		/* Training pass over a batch, recording what backward() needs */
+		let output = self.forward(&inputs);
+		return LayerTrace { input: inputs, output, cache: Vec::new() };
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		/* Adds the parameter gradients for the gradient w.r.t. the traced outputs,
+		returns the gradient w.r.t. the traced inputs. Differentiates build_graph() by default. */
+		let mut graph = Graph::new();
+		let (input, parameters, output) = self.graph_pass(&mut graph, trace.input.clone());
+		let grads = graph.backward_with(output, grad.clone());
+		for (tensor, var) in gradient.iter_mut().zip(&parameters) {
+			if let Some(g) = grads.get(*var) {
+				for (t, d) in tensor.iter_mut().zip(g.data()) {
+					*t += d;
+				}
+			}
+		}
+		return match grads.get(input) {
+			Some(g) => g.clone(),
+			None => Matrix::zeros(grad.rows(), trace.input.cols()),
+		};
+	}
+
+	fn graph_pass(&self, graph: &mut Graph, inputs: Matrix) -> (Var, Vec<Var>, Var) {
+		/* Records build_graph() on a batch, returns the input, parameter and output variables */
+		let input = graph.leaf(inputs);
+		let parameters: Vec<Var> = self.parameters().iter().map(|p| graph.leaf(Matrix::row_vector(p.to_vec()))).collect();
+		let output = self.build_graph(graph, input, &parameters)
+			.unwrap_or_else(|| panic!("Layer {} implements neither forward() nor build_graph()", self.name()));
+		return (input, parameters, output);
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		/* Trainable tensors, each one optimizer parameter group */
+		return Vec::new();
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return Vec::new();
+	}
+
+	fn weight_matrices(&self) -> Vec<(&'static str, &Matrix)> {
+		/* Weight matrices an inference copy may store at reduced precision, by the name of the
+		field they are persisted in. Biases and other small tensors are left out. */
+		return Vec::new();
+	}
+
+	fn weight_matrices_mut(&mut self) -> Vec<(&'static str, &mut Matrix)> {
+		return Vec::new();
+	}
+
+	fn parameter_count(&self) -> usize {
+		return self.parameters().iter().map(|p| p.len()).sum();
+	}
+
+	fn zero_gradient(&self) -> Vec<Vec<f64>> {
+		return self.parameters().iter().map(|p| vec![0.0; p.len()]).collect();
+	}
+
+	fn penalty(&self) -> f64 {
+		/* Regularization term added to the training loss */
+		return 0.0;
+	}
+
+	fn add_penalty_gradient(&self, _gradient: &mut [Vec<f64>]) {}
+
+	fn apply_constraints(&mut self) {}
+
+	fn update_statistics(&mut self, _traces: &[&LayerTrace]) {
+		/* Folds the statistics of a training batch into the layer's running state, once per
+		step. A batch split across threads comes as one trace per shard. */
+	}
+}
+
+pub trait LayerObject {
+	/* Object safe cloning, serialization and downcasting, implemented for every
+	Layer that is Serialize + Clone */
+	fn clone_box(&self) -> Box<dyn Layer>;
+	fn to_value(&self) -> serde_json::Result<Value>;
+	fn as_any(&self) -> &dyn Any;
+	fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Layer + Serialize + Clone + 'static> LayerObject for T {
+	fn clone_box(&self) -> Box<dyn Layer> {
+		return Box::new(self.clone());
+	}
+
+	fn to_value(&self) -> serde_json::Result<Value> {
+		return serde_json::to_value(self);
+	}
+
+	fn as_any(&self) -> &dyn Any {
+		return self;
+	}
+
+	fn as_any_mut(&mut self) -> &mut dyn Any {
+		return self;
+	}
+}
+
+impl dyn Layer {
+	pub fn downcast_ref<T: Layer + 'static>(&self) -> Option<&T> {
+		return self.as_any().downcast_ref::<T>();
+	}
+
+	pub fn downcast_mut<T: Layer + 'static>(&mut self) -> Option<&mut T> {
+		return self.as_any_mut().downcast_mut::<T>();
+	}
+
+	pub fn as_dense(&self) -> Option<&Dense> {
+		return self.downcast_ref::<Dense>();
+	}
 }
 
-impl Layer {
-	pub fn new(num_neurons: usize, input_size: usize, mut init_fn: impl FnMut() -> f64) -> Self {
-		let neurons = init_dense_layer(input_size, num_neurons, &mut init_fn);
-		return Self { neurons, last_output: vec![] };
+impl Clone for Box<dyn Layer> {
+	fn clone(&self) -> Self {
+		return self.clone_box();
 	}
+}
 
-	pub fn forward(&mut self, inputs: &[f64]) -> Vec<f64> {
-		self.last_output = self.neurons.iter_mut().map(|n| n.activate(inputs)).collect();
-		return self.last_output.clone();
+impl fmt::Debug for dyn Layer {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}({} -> {})", self.name(), self.input_size(), self.output_size())
 	}
 }
 
-fn init_dense_layer(input_size: usize, output_size: usize, mut init_fn: impl FnMut() -> f64) -> Vec<Neuron> {
-	/* Initializes the layer's neurons from a init_function */
-	(0..output_size).map(|_| {
-		let weights = (0..input_size).map(|_| init_fn()).collect();
-		let bias = init_fn();
-		Neuron::from_parts(weights, bias)
-	}).collect()
-}
\ No newline at end of file
+impl Serialize for dyn Layer {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut value = self.to_value().map_err(S::Error::custom)?;
+		match value.as_object_mut() {
+			Some(fields) => fields.insert("type".to_string(), Value::from(self.name())),
+			None => return Err(S::Error::custom(format!("Layer {} does not serialize to an object", self.name()))),
+		};
+		return value.serialize(serializer);
+	}
+}
+
+impl<'de> Deserialize<'de> for Box<dyn Layer> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let mut value = Value::deserialize(deserializer)?;
+		let tag = match value.as_object_mut().and_then(|fields| fields.remove("type")) {
+			Some(Value::String(tag)) => tag,
+			Some(_) => return Err(D::Error::custom("Layer type must be a string")),
+			// Untagged layers were written before the network could hold anything but dense layers
+			None => Dense::NAME.to_string(),
+		};
+		let deserialize = registry().read().unwrap().get(&tag).copied();
+		return match deserialize {
+			Some(deserialize) => deserialize(value).map_err(D::Error::custom),
+			None => Err(D::Error::custom(format!("Unknown layer type {}, custom layers must be registered", tag))),
+		};
+	}
+}
+
+type LayerDeserializer = fn(Value) -> serde_json::Result<Box<dyn Layer>>;
+
+fn deserialize_layer<T: Layer + DeserializeOwned + 'static>(value: Value) -> serde_json::Result<Box<dyn Layer>> {
+	return Ok(Box::new(serde_json::from_value::<T>(value)?));
+}
+
+fn registry() -> &'static RwLock<HashMap<String, LayerDeserializer>> {
+	static REGISTRY: OnceLock<RwLock<HashMap<String, LayerDeserializer>>> = OnceLock::new();
+	return REGISTRY.get_or_init(|| {
+		let mut layers: HashMap<String, LayerDeserializer> = HashMap::new();
+		layers.insert(Dense::NAME.to_string(), deserialize_layer::<Dense>);
+		layers.insert(Dropout::NAME.to_string(), deserialize_layer::<Dropout>);
+		layers.insert(BatchNorm::NAME.to_string(), deserialize_layer::<BatchNorm>);
+		layers.insert(LayerNorm::NAME.to_string(), deserialize_layer::<LayerNorm>);
+		layers.insert(ActivationLayer::NAME.to_string(), deserialize_layer::<ActivationLayer>);
+		layers.insert(Reshape::NAME.to_string(), deserialize_layer::<Reshape>);
+		layers.insert(Conv1D::NAME.to_string(), deserialize_layer::<Conv1D>);
+		layers.insert(Conv2D::NAME.to_string(), deserialize_layer::<Conv2D>);
+		layers.insert(Pool1D::NAME.to_string(), deserialize_layer::<Pool1D>);
+		layers.insert(Pool2D::NAME.to_string(), deserialize_layer::<Pool2D>);
+		layers.insert(Embedding::NAME.to_string(), deserialize_layer::<Embedding>);
+		layers.insert(Recurrent::NAME.to_string(), deserialize_layer::<Recurrent>);
+		layers.insert(TimeDistributed::NAME.to_string(), deserialize_layer::<TimeDistributed>);
+		RwLock::new(layers)
+	});
+}
+
+pub fn register_layer<T: Layer + DeserializeOwned + 'static>(name: &str) {
+	/* Makes a custom layer loadable, name must be what its Layer::name() returns */
+	registry().write().unwrap().insert(name.to_string(), deserialize_layer::<T>);
+}
@@ -0,0 +1,174 @@
+use serde::{Serialize, Deserialize};
+
+use super::activation::Activation;
+
+// Keeps ln() finite when a predicted probability underflows to 0 or 1
+const EPSILON: f64 = 1e-12;
+
+pub trait Loss {
+	/* Objective minimized by training, comparing a network output against its target */
+
+	fn value(&self, output: &[f64], target: &[f64]) -> f64;
+
+	fn gradient(&self, output: &[f64], target: &[f64]) -> Vec<f64>;
+
+	fn output_delta(&self, _activation: Activation, _output: &[f64], _target: &[f64]) -> Option<Vec<f64>> {
+		/* Shortcut for the gradient w.r.t. the weighted inputs of the output layer,
+		for loss/activation pairs whose combined derivative simplifies. None by default. */
+		return None;
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum LossFunction {
+	#[default]
+	MeanSquaredError,
+	MeanAbsoluteError,
+	// Quadratic within delta of the target, linear beyond
+	Huber(f64),
+	BinaryCrossEntropy,
+	CategoricalCrossEntropy,
+	// Expects targets of -1.0 or 1.0
+	Hinge,
+}
+
+impl Loss for LossFunction {
+	fn value(&self, output: &[f64], target: &[f64]) -> f64 {
+		/* Returns the loss of the network output given the target */
+		let n = output.len().max(1) as f64;
+		match *self {
+			LossFunction::MeanSquaredError => {
+				output.iter().zip(target).map(|(o, t)| (o - t).powi(2)).sum::<f64>() / n
+			},
+			LossFunction::MeanAbsoluteError => {
+				output.iter().zip(target).map(|(o, t)| (o - t).abs()).sum::<f64>() / n
+			},
+			LossFunction::Huber(delta) => {
+				output.iter().zip(target).map(|(o, t)| {
+					let e = (o - t).abs();
+					if e <= delta { 0.5 * e * e } else { delta * (e - 0.5 * delta) }
+				}).sum::<f64>() / n
+			},
+			LossFunction::BinaryCrossEntropy => {
+				-output.iter().zip(target).map(|(o, t)| {
+					let o = o.clamp(EPSILON, 1.0 - EPSILON);
+					t * o.ln() + (1.0 - t) * (1.0 - o).ln()
+				}).sum::<f64>() / n
+			},
+			LossFunction::CategoricalCrossEntropy => {
+				-output.iter().zip(target).map(|(o, t)| t * o.max(EPSILON).ln()).sum::<f64>()
+			},
+			LossFunction::Hinge => {
+				output.iter().zip(target).map(|(o, t)| (1.0 - t * o).max(0.0)).sum::<f64>() / n
+			},
+		}
+	}
+
+	fn gradient(&self, output: &[f64], target: &[f64]) -> Vec<f64> {
+		/* Returns the derivative of the loss with respect to each output */
+		let n = output.len().max(1) as f64;
+		match *self {
+			LossFunction::MeanSquaredError => {
+				output.iter().zip(target).map(|(o, t)| 2.0 * (o - t) / n).collect()
+			},
+			LossFunction::MeanAbsoluteError => {
+				output.iter().zip(target).map(|(o, t)| (o - t).signum() / n).collect()
+			},
+			LossFunction::Huber(delta) => {
+				output.iter().zip(target).map(|(o, t)| (o - t).clamp(-delta, delta) / n).collect()
+			},
+			LossFunction::BinaryCrossEntropy => {
+				output.iter().zip(target).map(|(o, t)| {
+					let o = o.clamp(EPSILON, 1.0 - EPSILON);
+					(o - t) / (o * (1.0 - o)) / n
+				}).collect()
+			},
+			LossFunction::CategoricalCrossEntropy => {
+				output.iter().zip(target).map(|(o, t)| -t / o.max(EPSILON)).collect()
+			},
+			LossFunction::Hinge => {
+				output.iter().zip(target).map(|(o, t)| if t * o < 1.0 { -t / n } else { 0.0 }).collect()
+			},
+		}
+	}
+
+	fn output_delta(&self, activation: Activation, output: &[f64], target: &[f64]) -> Option<Vec<f64>> {
+		/* The gradient w.r.t. the weighted inputs of an output layer using activation.
+		For sigmoid + binary and softmax + categorical cross-entropy the jacobian cancels out,
+		leaving (output - target), which avoids dividing by vanishing probabilities.
+		Returns None for all other pairings, which go through Activation::backward. */
+		let n = output.len().max(1) as f64;
+		match (*self, activation) {
+			(LossFunction::BinaryCrossEntropy, Activation::Sigmoid) => {
+				Some(output.iter().zip(target).map(|(o, t)| (o - t) / n).collect())
+			},
+			(LossFunction::CategoricalCrossEntropy, Activation::Softmax) => {
+				Some(output.iter().zip(target).map(|(o, t)| o - t).collect())
+			},
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::compare::approx_eq;
+
+	#[test]
+	fn test_value() {
+		assert!(approx_eq(LossFunction::MeanSquaredError.value(&[1.0, 0.0], &[0.0, 0.0]), 0.5, 1e-12));
+		let bce = LossFunction::BinaryCrossEntropy.value(&[0.5], &[1.0]);
+		assert!(approx_eq(bce, 2.0f64.ln(), 1e-12));
+		let cce = LossFunction::CategoricalCrossEntropy.value(&[0.25, 0.75], &[0.0, 1.0]);
+		assert!(approx_eq(cce, -(0.75f64).ln(), 1e-12));
+		// Saturated predictions stay finite
+		assert!(LossFunction::CategoricalCrossEntropy.value(&[1.0, 0.0], &[0.0, 1.0]).is_finite());
+		assert!(LossFunction::BinaryCrossEntropy.value(&[1.0], &[0.0]).is_finite());
+		assert!(approx_eq(LossFunction::MeanAbsoluteError.value(&[1.0, -1.0], &[0.0, 0.0]), 1.0, 1e-12));
+		assert!(approx_eq(LossFunction::Huber(1.0).value(&[0.5], &[0.0]), 0.125, 1e-12));
+		assert!(approx_eq(LossFunction::Huber(1.0).value(&[3.0], &[0.0]), 2.5, 1e-12));
+		assert!(approx_eq(LossFunction::Hinge.value(&[0.5, 2.0], &[1.0, 1.0]), 0.25, 1e-12));
+	}
+
+	#[test]
+	fn test_gradient_matches_finite_difference() {
+		let h = 1e-6;
+		let output = [0.3, 0.6, 0.1];
+		let target = [0.0, 1.0, 0.0];
+		let losses = [
+			LossFunction::MeanSquaredError,
+			LossFunction::MeanAbsoluteError,
+			LossFunction::Huber(0.25),
+			LossFunction::BinaryCrossEntropy,
+			LossFunction::CategoricalCrossEntropy,
+			LossFunction::Hinge,
+		];
+		for loss in losses {
+			let gradient = loss.gradient(&output, &target);
+			for i in 0..output.len() {
+				let mut plus = output;
+				let mut minus = output;
+				plus[i] += h;
+				minus[i] -= h;
+				let numeric = (loss.value(&plus, &target) - loss.value(&minus, &target)) / (2.0 * h);
+				assert!(approx_eq(gradient[i], numeric, 1e-6), "{:?}: {} != {}", loss, gradient[i], numeric);
+			}
+		}
+	}
+
+	#[test]
+	fn test_fused_output_delta() {
+		// The fused delta equals the chained loss gradient and activation jacobian
+		let z = [0.2, -0.4, 1.1];
+		let target = [0.0, 0.0, 1.0];
+		let output = Activation::Softmax.forward(&z);
+		let loss = LossFunction::CategoricalCrossEntropy;
+		let fused = loss.output_delta(Activation::Softmax, &output, &target).unwrap();
+		let chained = Activation::Softmax.backward(&z, &output, &loss.gradient(&output, &target));
+		for (f, c) in fused.iter().zip(&chained) {
+			assert!(approx_eq(*f, *c, 1e-9), "{} != {}", f, c);
+		}
+		assert_eq!(LossFunction::MeanSquaredError.output_delta(Activation::Sigmoid, &output, &target), None);
+	}
+}
@@ -0,0 +1,255 @@
+use std::ops::{Index, IndexMut};
+use serde::{Serialize, Deserialize};
+
+use super::utils::dot;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "MatrixRecord")]
+pub struct Matrix {
+	/* Dense row-major matrix of f64 */
+	rows: usize,
+	cols: usize,
+	data: Vec<f64>,
+}
+
+#[derive(Deserialize)]
+struct MatrixRecord {
+	rows: usize,
+	cols: usize,
+	data: Vec<f64>,
+}
+
+impl TryFrom<MatrixRecord> for Matrix {
+	type Error = String;
+
+	fn try_from(record: MatrixRecord) -> Result<Self, String> {
+		if record.data.len() != record.rows * record.cols {
+			return Err(format!("Matrix data of {} values does not match {}x{}", record.data.len(), record.rows, record.cols));
+		}
+		return Ok(Self { rows: record.rows, cols: record.cols, data: record.data });
+	}
+}
+
+impl Matrix {
+	pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Self {
+		assert_eq!(data.len(), rows * cols, "Matrix data does not match {}x{}", rows, cols);
+		return Self { rows, cols, data };
+	}
+
+	pub fn zeros(rows: usize, cols: usize) -> Self {
+		return Self { rows, cols, data: vec![0.0; rows * cols] };
+	}
+
+	pub fn from_rows(rows: &[Vec<f64>]) -> Self {
+		/* Stacks equally long vectors as the rows of a matrix */
+		let cols = rows.first().map_or(0, |r| r.len());
+		let mut data = Vec::with_capacity(rows.len() * cols);
+		for row in rows {
+			assert_eq!(row.len(), cols, "Rows differ in length");
+			data.extend_from_slice(row);
+		}
+		return Self { rows: rows.len(), cols, data };
+	}
+
+	pub fn row_vector(row: Vec<f64>) -> Self {
+		return Self { rows: 1, cols: row.len(), data: row };
+	}
+
+	pub fn rows(&self) -> usize {
+		return self.rows;
+	}
+
+	pub fn cols(&self) -> usize {
+		return self.cols;
+	}
+
+	pub fn data(&self) -> &[f64] {
+		return &self.data;
+	}
+
+	pub fn data_mut(&mut self) -> &mut [f64] {
+		return &mut self.data;
+	}
+
+	pub fn into_data(self) -> Vec<f64> {
+		return self.data;
+	}
+
+	pub fn row(&self, r: usize) -> &[f64] {
+		return &self.data[r * self.cols..(r + 1) * self.cols];
+	}
+
+	pub fn row_mut(&mut self, r: usize) -> &mut [f64] {
+		return &mut self.data[r * self.cols..(r + 1) * self.cols];
+	}
+
+	pub fn to_rows(&self) -> Vec<Vec<f64>> {
+		return (0..self.rows).map(|r| self.row(r).to_vec()).collect();
+	}
+
+	pub fn transpose(&self) -> Matrix {
+		let mut t = Matrix::zeros(self.cols, self.rows);
+		for r in 0..self.rows {
+			for c in 0..self.cols {
+				t[(c, r)] = self[(r, c)];
+			}
+		}
+		return t;
+	}
+
+	pub fn matmul(&self, other: &Matrix) -> Matrix {
+		/* self * other */
+		assert_eq!(self.cols, other.rows, "Cannot multiply {}x{} by {}x{}", self.rows, self.cols, other.rows, other.cols);
+		let mut out = Matrix::zeros(self.rows, other.cols);
+		for r in 0..self.rows {
+			let out_row = &mut out.data[r * other.cols..(r + 1) * other.cols];
+			for (k, a) in self.row(r).iter().enumerate() {
+				axpy(*a, other.row(k), out_row);
+			}
+		}
+		return out;
+	}
+
+	pub fn matmul_transposed(&self, other: &Matrix) -> Matrix {
+		/* self * other^T, reading both operands along their rows */
+		assert_eq!(self.cols, other.cols, "Cannot multiply {}x{} by ({}x{})^T", self.rows, self.cols, other.rows, other.cols);
+		let mut out = Matrix::zeros(self.rows, other.rows);
+		for r in 0..self.rows {
+			for c in 0..other.rows {
+				out.data[r * other.rows + c] = dot(self.row(r), other.row(c));
+			}
+		}
+		return out;
+	}
+
+	pub fn transposed_matmul(&self, other: &Matrix) -> Matrix {
+		/* self^T * other */
+		assert_eq!(self.rows, other.rows, "Cannot multiply ({}x{})^T by {}x{}", self.rows, self.cols, other.rows, other.cols);
+		let mut out = Matrix::zeros(self.cols, other.cols);
+		for k in 0..self.rows {
+			for (r, a) in self.row(k).iter().enumerate() {
+				axpy(*a, other.row(k), &mut out.data[r * other.cols..(r + 1) * other.cols]);
+			}
+		}
+		return out;
+	}
+
+	pub fn add_row_vector(&mut self, v: &[f64]) {
+		/* Adds v to every row */
+		for r in 0..self.rows {
+			for (x, b) in self.row_mut(r).iter_mut().zip(v) {
+				*x += b;
+			}
+		}
+	}
+
+	pub fn column_sums(&self) -> Vec<f64> {
+		let mut sums = vec![0.0; self.cols];
+		for r in 0..self.rows {
+			for (s, x) in sums.iter_mut().zip(self.row(r)) {
+				*s += x;
+			}
+		}
+		return sums;
+	}
+
+	pub fn row_sums(&self) -> Vec<f64> {
+		return (0..self.rows).map(|r| self.row(r).iter().sum()).collect();
+	}
+
+	pub fn map(&self, f: impl Fn(f64) -> f64) -> Matrix {
+		/* Applies f to every element */
+		return Self { rows: self.rows, cols: self.cols, data: self.data.iter().map(|x| f(*x)).collect() };
+	}
+
+	pub fn zip_map(&self, other: &Matrix, f: impl Fn(f64, f64) -> f64) -> Matrix {
+		/* Combines two equally shaped matrices element by element */
+		assert_eq!((self.rows, self.cols), (other.rows, other.cols), "Matrix shapes differ");
+		let data = self.data.iter().zip(&other.data).map(|(a, b)| f(*a, *b)).collect();
+		return Self { rows: self.rows, cols: self.cols, data };
+	}
+
+	pub fn reshape(mut self, rows: usize, cols: usize) -> Matrix {
+		assert_eq!(rows * cols, self.data.len(), "Cannot reshape {}x{} into {}x{}", self.rows, self.cols, rows, cols);
+		self.rows = rows;
+		self.cols = cols;
+		return self;
+	}
+
+	pub fn add_assign(&mut self, other: &Matrix) {
+		assert_eq!((self.rows, self.cols), (other.rows, other.cols), "Matrix shapes differ");
+		for (a, b) in self.data.iter_mut().zip(&other.data) {
+			*a += b;
+		}
+	}
+
+	pub fn scale(&mut self, factor: f64) {
+		for x in self.data.iter_mut() {
+			*x *= factor;
+		}
+	}
+
+	pub fn map_rows(&self, f: impl Fn(&[f64]) -> Vec<f64>) -> Matrix {
+		/* Applies a row to row function, all results must have the same length */
+		let rows: Vec<Vec<f64>> = (0..self.rows).map(|r| f(self.row(r))).collect();
+		if rows.is_empty() {
+			return Matrix::zeros(0, 0);
+		}
+		return Matrix::from_rows(&rows);
+	}
+}
+
+impl Index<(usize, usize)> for Matrix {
+	type Output = f64;
+
+	fn index(&self, (r, c): (usize, usize)) -> &f64 {
+		return &self.data[r * self.cols + c];
+	}
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+	fn index_mut(&mut self, (r, c): (usize, usize)) -> &mut f64 {
+		return &mut self.data[r * self.cols + c];
+	}
+}
+
+fn axpy(a: f64, x: &[f64], y: &mut [f64]) {
+	/* y += a * x */
+	for (yi, xi) in y.iter_mut().zip(x) {
+		*yi += a * xi;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_products() {
+		let a = Matrix::from_rows(&[vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+		let b = Matrix::from_rows(&[vec![1.0, 0.0], vec![0.0, 1.0], vec![2.0, -1.0]]);
+		let expected = Matrix::from_rows(&[vec![7.0, -1.0], vec![16.0, -1.0]]);
+		assert_eq!(a.matmul(&b), expected);
+		assert_eq!(a.matmul_transposed(&b.transpose()), expected);
+		assert_eq!(a.transpose().transposed_matmul(&b), expected);
+	}
+
+	#[test]
+	fn test_rows() {
+		let mut m = Matrix::zeros(2, 3);
+		m.add_row_vector(&[1.0, 2.0, 3.0]);
+		m[(1, 2)] = 0.0;
+		assert_eq!(m.row(0), &[1.0, 2.0, 3.0]);
+		assert_eq!(m.column_sums(), vec![2.0, 4.0, 3.0]);
+		let doubled = m.map_rows(|r| r.iter().map(|x| 2.0 * x).collect());
+		assert_eq!(doubled.row(1), &[2.0, 4.0, 0.0]);
+	}
+
+	#[test]
+	fn test_deserialize() {
+		let m: Matrix = serde_json::from_str(r#"{"rows":2,"cols":2,"data":[1.0,2.0,3.0,4.0]}"#).unwrap();
+		assert_eq!(m[(1, 0)], 3.0);
+		let error = serde_json::from_str::<Matrix>(r#"{"rows":2,"cols":3,"data":[1.0,2.0,3.0,4.0]}"#).unwrap_err();
+		assert!(error.to_string().contains("does not match 2x3"));
+	}
+}
@@ -1,27 +1,65 @@
-/**
+/*!
  * nnet Module
  * Implements NeuralNetwork publicly available.
  */
 
 mod utils;
-mod neuron;
-mod layer;
+pub mod tape;
+pub mod matrix;
+pub mod autodiff;
+pub mod layer;
+pub mod dense;
+pub mod dropout;
+pub mod embedding;
+pub mod normalization;
+pub mod reshape;
+pub mod conv;
+pub mod pooling;
+pub mod recurrent;
+pub mod time_distributed;
+pub mod vocabulary;
+pub mod regularization;
+pub mod activation;
+pub mod initializer;
+pub mod loss;
+pub mod optimizer;
+pub mod schedule;
+pub mod training;
+pub mod callback;
+pub mod builder;
+pub mod error;
 pub mod network;
+pub mod summary;
+pub mod gradient_check;
+pub mod quantize;
+pub mod autoencoder;
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use activation::Activation;
+	use initializer::Initializer;
+	use loss::LossFunction;
+	use optimizer::{Adam, Sgd};
+	use regularization::Regularization;
+	use schedule::Schedule;
+	use training::Dataset;
+	use error::NetworkError;
 	use crate::persist::json::JsonPersist;
 	use crate::utils::compare;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
 	use std::path::Path;
 	use std::fs;
+	use std::sync::Arc;
+	use std::thread;
 
 	#[test]
 	fn test_nnet() {
 		// 2 input, 1 hidden layer of 4, 1 output layer of 3 neurons
-		let mut nn = network::NeuralNetwork::new(&[2, 4, 3]);
+		let nn = network::NeuralNetwork::new(&[2, 4, 3]);
 		let input = vec![0.5,-0.2];
-		let output = nn.predict(input);
+		let output = nn.predict(input).unwrap();
 		println!("Prediction: {:?}", output);
 	}
 
@@ -39,14 +77,745 @@ mod tests {
 		let nn2 = network::NeuralNetwork::load_from_file(&path).expect("Error loading file");
 		
 		for (layer1, layer2) in nn1.layers.iter().zip(nn2.layers.iter()) {
-			for (n1, n2) in layer1.neurons.iter().zip(layer2.neurons.iter()) {
-				for (w1, w2) in n1.weights.iter().zip(n2.weights.iter()) {
-					assert!(compare::approx_eq(*w1, *w2, 1e-10), "Weights differ: {} != {}", w1, w2);
-				}
-				assert!(compare::approx_eq(n1.bias, n2.bias, 1e-10), "Biases differ: {} != {}", n1.bias, n2.bias);
+			let (layer1, layer2) = (layer1.as_dense().unwrap(), layer2.as_dense().unwrap());
+			for (w1, w2) in layer1.weights.data().iter().zip(layer2.weights.data().iter()) {
+				assert!(compare::approx_eq(*w1, *w2, 1e-10), "Weights differ: {} != {}", w1, w2);
+			}
+			for (b1, b2) in layer1.biases.iter().zip(layer2.biases.iter()) {
+				assert!(compare::approx_eq(*b1, *b2, 1e-10), "Biases differ: {} != {}", b1, b2);
 			}
 		}
 		// delete file
 		fs::remove_file(&path).expect("Failed to delete test file.");
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_linear_regression() {
+		// A single identity neuron learns y = 2x + 1
+		let mut nn = network::NeuralNetwork::with_activations(&[1, 1], &[Activation::Identity]);
+		for _ in 0..2000 {
+			for x in [-1.0, -0.5, 0.0, 0.5, 1.0] {
+				nn.train(vec![x], vec![2.0 * x + 1.0], 0.05).unwrap();
+			}
+		}
+		let output = nn.predict(vec![0.25]).unwrap();
+		assert!(compare::approx_eq(output[0], 1.5, 1e-6), "Prediction {} != 1.5", output[0]);
+	}
+
+	#[test]
+	fn test_save_load_activations() {
+		let path = "nn_activations.json".to_string();
+		let activations = [Activation::Relu, Activation::LeakyRelu(0.1), Activation::Identity];
+		let nn1 = network::NeuralNetwork::with_activations(&[2, 4, 4, 1], &activations);
+		nn1.save_to_file(&path, false).expect("Error saving file");
+		let nn2 = network::NeuralNetwork::load_from_file(&path).expect("Error loading file");
+		for (layer, activation) in nn2.layers.iter().zip(activations) {
+			assert_eq!(layer.activation(), Some(activation));
+		}
+		fs::remove_file(&path).expect("Failed to delete test file.");
+	}
+
+	#[test]
+	fn test_classifier() {
+		let mut nn = network::NeuralNetwork::classifier(&[2, 6, 3]);
+		assert_eq!(nn.loss, LossFunction::CategoricalCrossEntropy);
+		let data = [
+			(vec![1.0, 0.0], vec![1.0, 0.0, 0.0]),
+			(vec![0.0, 1.0], vec![0.0, 1.0, 0.0]),
+			(vec![1.0, 1.0], vec![0.0, 0.0, 1.0]),
+		];
+		for _ in 0..1000 {
+			for (input, target) in &data {
+				nn.train(input.clone(), target.clone(), 0.5).unwrap();
+			}
+		}
+		for (input, target) in &data {
+			let output = nn.predict(input.clone()).unwrap();
+			assert!(compare::approx_eq(output.iter().sum::<f64>(), 1.0, 1e-10));
+			let class = target.iter().position(|t| *t == 1.0).unwrap();
+			assert!(output[class] > 0.9, "Expected class {} for {:?}, got {:?}", class, input, output);
+		}
+	}
+
+	#[test]
+	fn test_binary_classifier() {
+		let mut nn = network::NeuralNetwork::classifier(&[2, 3, 1]);
+		assert_eq!(nn.loss, LossFunction::BinaryCrossEntropy);
+		let data = [
+			(vec![0.0, 0.0], vec![0.0]),
+			(vec![0.0, 1.0], vec![0.0]),
+			(vec![1.0, 0.0], vec![0.0]),
+			(vec![1.0, 1.0], vec![1.0]),
+		];
+		for _ in 0..2000 {
+			for (input, target) in &data {
+				nn.train(input.clone(), target.clone(), 0.5).unwrap();
+			}
+		}
+		for (input, target) in &data {
+			let output = nn.predict(input.clone()).unwrap();
+			assert!((output[0] - target[0]).abs() < 0.2, "{:?} -> {:?}", input, output);
+		}
+	}
+
+	#[test]
+	fn test_train_returns_loss() {
+		let mut nn = network::NeuralNetwork::with_activations(&[1, 1], &[Activation::Identity])
+			.with_loss(LossFunction::Huber(1.0));
+		assert_eq!(nn.loss, LossFunction::Huber(1.0));
+		let first = nn.train(vec![1.0], vec![10.0], 0.1).unwrap();
+		let mut last = first;
+		for _ in 0..500 {
+			last = nn.train(vec![1.0], vec![10.0], 0.1).unwrap();
+		}
+		assert!(last < first, "Loss did not decrease: {} -> {}", first, last);
+		assert!(last < 1e-6, "Loss did not converge: {}", last);
+	}
+
+	#[test]
+	fn test_fit() {
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = (0..20).map(|i| {
+			let x = i as f64 / 10.0 - 1.0;
+			(vec![x], vec![3.0 * x - 0.5])
+		}).collect();
+		let validation = vec![(vec![0.25], vec![0.25]), (vec![-0.75], vec![-2.75])];
+		let dataset = Dataset::new(samples).with_validation(validation).with_seed(42);
+		let mut nn = network::NeuralNetwork::with_activations(&[1, 1], &[Activation::Identity])
+			.with_learning_rate(0.2);
+		let history = nn.fit(&dataset, 200, 4, true).unwrap();
+		assert_eq!(history.epochs.len(), 200);
+		let losses = history.losses();
+		assert!(losses[199] < losses[0], "Loss did not decrease: {} -> {}", losses[0], losses[199]);
+		let validation_loss = history.last().unwrap().validation_loss.unwrap();
+		assert!(validation_loss < 1e-6, "Validation loss did not converge: {}", validation_loss);
+	}
+
+	#[test]
+	fn test_resume_from_checkpoint() {
+		let path = "nn_checkpoint.json".to_string();
+		let data = [
+			(vec![0.0, 1.0], vec![1.0]),
+			(vec![1.0, 0.0], vec![0.0]),
+			(vec![1.0, 1.0], vec![1.0]),
+		];
+		let mut nn1 = network::NeuralNetwork::new(&[2, 3, 1]).with_optimizer(Adam::default());
+		for (input, target) in &data {
+			nn1.train(input.clone(), target.clone(), 0.05).unwrap();
+		}
+		nn1.save_to_file(&path, false).expect("Error saving file");
+		let mut nn2 = network::NeuralNetwork::load_from_file(&path).expect("Error loading file");
+		fs::remove_file(&path).expect("Failed to delete test file.");
+		assert_eq!(nn2.optimizer, nn1.optimizer);
+
+		for _ in 0..10 {
+			for (input, target) in &data {
+				nn1.train(input.clone(), target.clone(), 0.05).unwrap();
+				nn2.train(input.clone(), target.clone(), 0.05).unwrap();
+			}
+		}
+		for (layer1, layer2) in nn1.layers.iter().zip(nn2.layers.iter()) {
+			let (layer1, layer2) = (layer1.as_dense().unwrap(), layer2.as_dense().unwrap());
+			assert_eq!(layer1.weights, layer2.weights);
+			assert_eq!(layer1.biases, layer2.biases);
+		}
+	}
+
+	#[test]
+	fn test_fit_with_momentum() {
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = (0..10).map(|i| {
+			let x = i as f64 / 5.0 - 1.0;
+			(vec![x], vec![-x + 0.5])
+		}).collect();
+		let dataset = Dataset::new(samples).with_seed(1);
+		let mut nn = network::NeuralNetwork::with_activations(&[1, 1], &[Activation::Identity])
+			.with_optimizer(Sgd::nesterov(0.9))
+			.with_learning_rate(0.05);
+		let history = nn.fit(&dataset, 100, 5, true).unwrap();
+		assert!(history.last().unwrap().loss < 1e-6, "Loss did not converge: {:?}", history.last());
+	}
+
+	#[test]
+	fn test_fit_with_schedule() {
+		let samples = vec![(vec![1.0], vec![2.0]), (vec![-1.0], vec![0.0])];
+		let dataset = Dataset::new(samples).with_seed(3);
+		let mut nn = network::NeuralNetwork::with_activations(&[1, 1], &[Activation::Identity])
+			.with_learning_rate(0.1)
+			.with_schedule(Schedule::StepDecay { step_size: 2, gamma: 0.5 });
+		let history = nn.fit(&dataset, 5, 2, false).unwrap();
+		assert_eq!(history.learning_rates(), vec![0.1, 0.1, 0.05, 0.05, 0.025]);
+	}
+
+	#[test]
+	fn test_seeded_builder() {
+		let build = || network::NeuralNetwork::builder(&[3, 5, 2])
+			.activation(Activation::Relu)
+			.output_activation(Activation::Identity)
+			.initializer(Initializer::HeNormal)
+			.seed(1234)
+			.build();
+		let mut nn1 = build();
+		let mut nn2 = build();
+		for (layer1, layer2) in nn1.layers.iter().zip(nn2.layers.iter()) {
+			let (layer1, layer2) = (layer1.as_dense().unwrap(), layer2.as_dense().unwrap());
+			assert_eq!(layer1.activation, layer2.activation);
+			assert_eq!(layer1.weights, layer2.weights);
+			assert_eq!(layer1.biases, layer2.biases);
+		}
+		assert_eq!(nn1.layers[0].activation(), Some(Activation::Relu));
+		assert_eq!(nn1.layers[1].activation(), Some(Activation::Identity));
+
+		// Seeded networks and seeded shuffling make whole training runs reproducible
+		let samples = vec![(vec![0.1, 0.2, 0.3], vec![1.0, 0.0]), (vec![0.3, 0.2, 0.1], vec![0.0, 1.0])];
+		let dataset = Dataset::new(samples).with_seed(99);
+		assert_eq!(nn1.fit(&dataset, 5, 1, true).unwrap(), nn2.fit(&dataset, 5, 1, true).unwrap());
+	}
+
+	#[test]
+	fn test_introspection() {
+		let nn = network::NeuralNetwork::builder(&[3, 5, 4, 2])
+			.output_activation(Activation::Softmax)
+			.build();
+		assert_eq!(nn.input_size(), 3);
+		assert_eq!(nn.output_size(), 2);
+		assert_eq!(nn.hidden_layers_size(), 2);
+
+		let summary = nn.summary();
+		assert_eq!(summary.layers.len(), 3);
+		assert_eq!(summary.layers[0].parameters, 3 * 5 + 5);
+		assert_eq!(summary.layers[2].activation, Some(Activation::Softmax));
+		assert_eq!((summary.layers[1].input_size, summary.layers[1].output_size), (5, 4));
+		assert_eq!(summary.total_parameters, 20 + 24 + 10);
+		assert!(summary.to_string().contains("Total parameters: 54"));
+	}
+
+	#[test]
+	fn test_dimension_validation() {
+		let mut nn = network::NeuralNetwork::new(&[2, 3, 1]);
+		assert_eq!(nn.predict(vec![1.0]), Err(NetworkError::InputSize { expected: 2, found: 1 }));
+		assert_eq!(nn.train(vec![1.0, 2.0], vec![1.0, 0.0], 0.1), Err(NetworkError::TargetSize { expected: 1, found: 2 }));
+		let dataset = Dataset::new(vec![(vec![1.0, 2.0], vec![1.0]), (vec![1.0, 2.0, 3.0], vec![1.0])]);
+		assert!(nn.fit(&dataset, 1, 1, false).is_err());
+
+		let empty = network::NeuralNetwork::new(&[2]);
+		assert_eq!(empty.predict(vec![1.0, 2.0]), Err(NetworkError::EmptyNetwork));
+	}
+
+	#[test]
+	fn test_shared_inference() {
+		let nn = Arc::new(network::NeuralNetwork::builder(&[2, 4, 1]).seed(5).build());
+		let expected = nn.predict(vec![0.3, -0.7]).unwrap();
+		let handles: Vec<_> = (0..4).map(|_| {
+			let nn = Arc::clone(&nn);
+			thread::spawn(move || nn.predict(vec![0.3, -0.7]).unwrap())
+		}).collect();
+		for handle in handles {
+			assert_eq!(handle.join().unwrap(), expected);
+		}
+	}
+
+	#[test]
+	fn test_training_caches_not_persisted() {
+		let mut nn = network::NeuralNetwork::new(&[2, 2, 1]);
+		nn.train(vec![0.5, 0.5], vec![1.0], 0.1).unwrap();
+		let json = serde_json::to_string(&nn).unwrap();
+		assert!(!json.contains("last_"), "Training caches were serialized: {}", json);
+
+		// Files written in the per-neuron layout, with training caches, still load
+		let legacy = r#"{"layers":[{"neurons":[
+			{"weights":[0.5,-0.5],"bias":0.1,"last_input":[1.0,2.0],"last_z":0.3}
+		],"last_output":[0.2]}]}"#;
+		let nn: network::NeuralNetwork = serde_json::from_str(legacy).unwrap();
+		let output = nn.predict(vec![1.0, 1.0]).unwrap();
+		assert!(compare::approx_eq(output[0], 1.0 / (1.0 + (-0.1f64).exp()), 1e-12));
+	}
+
+	#[test]
+	fn test_batch_prediction() {
+		let nn = network::NeuralNetwork::builder(&[3, 8, 2])
+			.activation(Activation::Tanh)
+			.output_activation(Activation::Softmax)
+			.seed(11)
+			.build();
+		let rows = vec![vec![0.1, 0.2, 0.3], vec![-1.0, 0.5, 2.0], vec![0.0, 0.0, 0.0]];
+		let batch = nn.predict_batch(&matrix::Matrix::from_rows(&rows)).unwrap();
+		assert_eq!((batch.rows(), batch.cols()), (3, 2));
+		for (r, row) in rows.into_iter().enumerate() {
+			let single = nn.predict(row).unwrap();
+			for (a, b) in single.iter().zip(batch.row(r)) {
+				assert!(compare::approx_eq(*a, *b, 1e-12));
+			}
+		}
+	}
+
+	#[test]
+	fn test_legacy_layout_migration() {
+		let legacy = r#"{"layers":[
+			{"neurons":[{"weights":[1.0,2.0],"bias":0.5},{"weights":[3.0,4.0],"bias":-0.5}],"activation":"Identity"}
+		]}"#;
+		let nn: network::NeuralNetwork = serde_json::from_str(legacy).unwrap();
+		assert_eq!(nn.layers[0].as_dense().unwrap().weights, matrix::Matrix::from_rows(&[vec![1.0, 2.0], vec![3.0, 4.0]]));
+		assert_eq!(nn.layers[0].as_dense().unwrap().biases, vec![0.5, -0.5]);
+		assert_eq!(nn.predict(vec![1.0, 1.0]).unwrap(), vec![3.5, 6.5]);
+
+		// Re-saving writes the matrix layout, which loads back to the same network
+		let json = serde_json::to_string(&nn).unwrap();
+		assert!(!json.contains("neurons"));
+		let reloaded: network::NeuralNetwork = serde_json::from_str(&json).unwrap();
+		assert_eq!(reloaded.layers[0].as_dense().unwrap().weights, nn.layers[0].as_dense().unwrap().weights);
+
+		let ragged = r#"{"layers":[{"neurons":[{"weights":[1.0],"bias":0.0},{"weights":[1.0,2.0],"bias":0.0}]}]}"#;
+		assert!(serde_json::from_str::<network::NeuralNetwork>(ragged).is_err());
+	}
+
+	#[test]
+	fn test_dropout_network() {
+		let path = "nn_dropout.json".to_string();
+		let nn = network::NeuralNetwork::builder(&[2, 8, 1])
+			.dropout(0, 0.5)
+			.seed(3)
+			.build();
+		assert_eq!(nn.layers.len(), 3);
+		assert_eq!(nn.layers[1].name(), "Dropout");
+		assert_eq!(nn.summary().total_parameters, 24 + 9);
+		// Dropout is inactive at inference
+		assert_eq!(nn.predict(vec![0.3, 0.7]).unwrap(), nn.predict(vec![0.3, 0.7]).unwrap());
+
+		nn.save_to_file(&path, false).expect("Error saving file");
+		let reloaded = network::NeuralNetwork::load_from_file(&path).expect("Error loading file");
+		assert_eq!(serde_json::to_value(&reloaded.layers).unwrap(), serde_json::to_value(&nn.layers).unwrap());
+		fs::remove_file(&path).expect("Failed to delete test file.");
+
+		// Seeded fits draw the same masks
+		let samples = vec![(vec![0.0, 1.0], vec![1.0]), (vec![1.0, 0.0], vec![0.0])];
+		let dataset = Dataset::new(samples).with_seed(5);
+		let mut nn1 = reloaded;
+		let mut nn2: network::NeuralNetwork = serde_json::from_str(&serde_json::to_string(&nn).unwrap()).unwrap();
+		nn1.fit(&dataset, 10, 1, true).unwrap();
+		nn2.fit(&dataset, 10, 1, true).unwrap();
+		assert_eq!(serde_json::to_value(&nn1.layers).unwrap(), serde_json::to_value(&nn2.layers).unwrap());
+	}
+
+	#[test]
+	fn test_weight_regularization() {
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = (0..20).map(|i| {
+			let x = i as f64 / 10.0 - 1.0;
+			(vec![x, -x], vec![2.0 * x])
+		}).collect();
+		let dataset = Dataset::new(samples.clone()).with_seed(1);
+		let build = |reg: Regularization| network::NeuralNetwork::builder(&[2, 1])
+			.activations(&[Activation::Identity])
+			.layer_regularization(0, reg)
+			.seed(2)
+			.build();
+
+		let mut plain = build(Regularization::default());
+		let mut decayed = build(Regularization::l2(0.1));
+		let plain_history = plain.fit(&dataset, 50, 4, true).unwrap();
+		let decayed_history = decayed.fit(&dataset, 50, 4, true).unwrap();
+		let norm = |nn: &network::NeuralNetwork| nn.layers[0].as_dense().unwrap().weights.data().iter().map(|w| w * w).sum::<f64>();
+		assert!(norm(&decayed) < norm(&plain));
+
+		// The reported loss includes the penalty, evaluate() does not
+		let reported = decayed_history.last().unwrap().loss;
+		let data_loss = decayed.evaluate(&samples).unwrap();
+		assert!(reported > data_loss);
+		assert!(plain_history.last().unwrap().loss < reported);
+
+		let mut constrained = build(Regularization::max_norm(0.5));
+		constrained.fit(&dataset, 20, 4, true).unwrap();
+		assert!(norm(&constrained).sqrt() <= 0.5 + 1e-12);
+	}
+
+	#[test]
+	fn test_normalization_layers() {
+		let path = "nn_normalization.json".to_string();
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = (0..40).map(|i| {
+			let x = i as f64 / 4.0;
+			(vec![x, 10.0 - x], vec![if x > 5.0 { 1.0 } else { 0.0 }])
+		}).collect();
+		let dataset = Dataset::new(samples.clone()).with_seed(7);
+		let mut nn = network::NeuralNetwork::builder(&[2, 6, 6, 1])
+			.activation(Activation::Relu)
+			.initializer(Initializer::HeUniform)
+			.batch_norm(0)
+			.layer_norm(1)
+			.loss(LossFunction::BinaryCrossEntropy)
+			.learning_rate(0.05)
+			.seed(4)
+			.build();
+		let names: Vec<&str> = nn.layers.iter().map(|l| l.name()).collect();
+		assert_eq!(names, ["Dense", "BatchNorm", "Dense", "LayerNorm", "Dense"]);
+
+		let before = nn.evaluate(&samples).unwrap();
+		nn.fit(&dataset, 100, 8, true).unwrap();
+		assert!(nn.evaluate(&samples).unwrap() < before);
+
+		// Running statistics have moved away from their initial values and are persisted
+		let bn = nn.layers[1].downcast_ref::<normalization::BatchNorm>().unwrap();
+		assert!(bn.running_mean.iter().any(|m| *m != 0.0));
+		nn.save_to_file(&path, false).expect("Error saving file");
+		let reloaded = network::NeuralNetwork::load_from_file(&path).expect("Error loading file");
+		assert_eq!(serde_json::to_value(&reloaded.layers).unwrap(), serde_json::to_value(&nn.layers).unwrap());
+		assert_eq!(reloaded.predict(vec![1.0, 9.0]).unwrap(), nn.predict(vec![1.0, 9.0]).unwrap());
+		fs::remove_file(&path).expect("Failed to delete test file.");
+	}
+
+	#[derive(serde::Serialize, serde::Deserialize, Clone)]
+	struct Scale {
+		size: usize,
+		factor: f64,
+	}
+
+	impl layer::Layer for Scale {
+		fn name(&self) -> &'static str {
+			return "Scale";
+		}
+
+		fn input_size(&self) -> usize {
+			return self.size;
+		}
+
+		fn output_size(&self) -> usize {
+			return self.size;
+		}
+
+		fn forward(&self, inputs: &matrix::Matrix) -> matrix::Matrix {
+			return inputs.map_rows(|row| row.iter().map(|x| x * self.factor).collect());
+		}
+
+		fn backward(&self, _trace: &tape::LayerTrace, grad: &matrix::Matrix, _gradient: &mut [Vec<f64>]) -> matrix::Matrix {
+			return self.forward(grad);
+		}
+	}
+
+	#[test]
+	fn test_heterogeneous_layers() {
+		use layer::Layer;
+		let mut rng = rand::thread_rng();
+		let layers: Vec<Box<dyn Layer>> = vec![
+			Box::new(reshape::Reshape::flatten(&[2, 2])),
+			Box::new(dense::Dense::new(3, 4, Activation::Identity, Initializer::XavierUniform, &mut rng)),
+			Box::new(Scale { size: 3, factor: 0.5 }),
+			Box::new(activation::ActivationLayer::new(3, Activation::Softmax)),
+		];
+		let mut nn = network::NeuralNetwork::from_layers(layers).unwrap().with_loss(LossFunction::CategoricalCrossEntropy);
+		let samples = vec![(vec![1.0, 0.0, 0.0, 1.0], vec![1.0, 0.0, 0.0]), (vec![0.0, 1.0, 1.0, 0.0], vec![0.0, 0.0, 1.0])];
+		let before = nn.evaluate(&samples).unwrap();
+		nn.fit(&Dataset::new(samples.clone()), 200, 2, false).unwrap();
+		assert!(nn.evaluate(&samples).unwrap() < before);
+		let summary = nn.summary();
+		assert_eq!(summary.layers[2].name, "Scale");
+		assert_eq!(summary.total_parameters, 15);
+
+		// Custom layers only load once registered
+		let json = serde_json::to_string(&nn).unwrap();
+		assert!(serde_json::from_str::<network::NeuralNetwork>(&json).is_err());
+		layer::register_layer::<Scale>("Scale");
+		let reloaded: network::NeuralNetwork = serde_json::from_str(&json).unwrap();
+		assert_eq!(serde_json::to_string(&reloaded).unwrap(), json);
+		assert_eq!(reloaded.predict(vec![1.0, 0.0, 0.0, 1.0]).unwrap(), nn.predict(vec![1.0, 0.0, 0.0, 1.0]).unwrap());
+
+		let mismatched: Vec<Box<dyn Layer>> = vec![
+			Box::new(dense::Dense::new(3, 4, Activation::Relu, Initializer::HeUniform, &mut rng)),
+			Box::new(dropout::Dropout::new(4, 0.5)),
+		];
+		assert_eq!(
+			network::NeuralNetwork::from_layers(mismatched).err(),
+			Some(NetworkError::LayerSize { layer: 1, expected: 3, found: 4 })
+		);
+	}
+
+	#[test]
+	fn test_convolutional_network() {
+		use layer::Layer;
+		let path = "nn_conv.json".to_string();
+		// 6x6 images holding a horizontal or a vertical bar
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = (0..12).map(|i| {
+			let (line, vertical) = (i % 6, i >= 6);
+			let image = (0..36).map(|p| if (if vertical { p % 6 } else { p / 6 }) == line { 1.0 } else { 0.0 }).collect();
+			(image, if vertical { vec![0.0, 1.0] } else { vec![1.0, 0.0] })
+		}).collect();
+
+		let mut rng = StdRng::seed_from_u64(8);
+		let conv = conv::Conv2D::new(1, 4, [6, 6], [3, 3], Activation::Relu, Initializer::HeUniform, &mut rng).with_padding([1, 1]);
+		let layers: Vec<Box<dyn Layer>> = vec![
+			Box::new(conv),
+			Box::new(pooling::Pool2D::max(4, [6, 6], [2, 2])),
+			Box::new(reshape::Reshape::flatten(&[4, 3, 3])),
+			Box::new(dense::Dense::new(2, 36, Activation::Softmax, Initializer::XavierUniform, &mut rng)),
+		];
+		let mut nn = network::NeuralNetwork::from_layers(layers).unwrap()
+			.with_loss(LossFunction::CategoricalCrossEntropy)
+			.with_optimizer(Adam::default())
+			.with_learning_rate(0.01);
+		nn.fit(&Dataset::new(samples.clone()).with_seed(9), 60, 4, true).unwrap();
+		for (image, target) in &samples {
+			let output = nn.predict(image.clone()).unwrap();
+			assert_eq!(output[1] > output[0], target[1] > target[0]);
+		}
+
+		nn.save_to_file(&path, false).expect("Error saving file");
+		let reloaded = network::NeuralNetwork::load_from_file(&path).expect("Error loading file");
+		assert_eq!(reloaded.predict(samples[3].0.clone()).unwrap(), nn.predict(samples[3].0.clone()).unwrap());
+		fs::remove_file(&path).expect("Failed to delete test file.");
+	}
+
+	#[test]
+	fn test_embedding_network() {
+		use layer::Layer;
+		let path = "nn_embedding.json".to_string();
+		// Predicts the next char of a corpus from the two chars before it
+		let corpus = "abcdefabcdefabcdef";
+		let vocabulary = vocabulary::Vocabulary::from_text(corpus);
+		let ids = vocabulary.ids(corpus);
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = ids.windows(3).map(|w| {
+			let mut target = vec![0.0; vocabulary.len()];
+			target[w[2] as usize] = 1.0;
+			(w[..2].to_vec(), target)
+		}).collect();
+
+		let mut rng = StdRng::seed_from_u64(10);
+		let layers: Vec<Box<dyn Layer>> = vec![
+			Box::new(embedding::Embedding::new(vocabulary.len(), 4, 2, &mut rng)),
+			Box::new(dense::Dense::new(vocabulary.len(), 8, Activation::Softmax, Initializer::XavierUniform, &mut rng)),
+		];
+		let mut nn = network::NeuralNetwork::from_layers(layers).unwrap()
+			.with_loss(LossFunction::CategoricalCrossEntropy)
+			.with_optimizer(Adam::default())
+			.with_learning_rate(0.05);
+		let before = nn.layers[0].downcast_ref::<embedding::Embedding>().unwrap().vectors.clone();
+		nn.fit(&Dataset::new(samples.clone()).with_seed(11), 100, 4, true).unwrap();
+		for (input, target) in &samples {
+			let output = nn.predict(input.clone()).unwrap();
+			assert_eq!(vocabulary.decode(&output), vocabulary.decode(target));
+		}
+
+		// The vectors trained through backpropagation and can be queried by token
+		let embedding = nn.layers[0].downcast_ref::<embedding::Embedding>().unwrap();
+		assert_ne!(embedding.vectors, before);
+		let id = vocabulary.id('a').unwrap();
+		let nearest = embedding.nearest(id, 3);
+		assert_eq!(nearest.len(), 3);
+		assert!(nearest.iter().all(|(other, similarity)| *other != id && (-1.0..=1.0).contains(similarity)));
+		assert!(nearest.windows(2).all(|w| w[0].1 >= w[1].1));
+
+		// Values that are not token ids are rejected before the lookup
+		let size = vocabulary.len();
+		assert_eq!(nn.predict(vec![0.0, size as f64]), Err(NetworkError::InvalidToken { value: size as f64, vocabulary: size }));
+		assert_eq!(nn.predict(vec![-1.0, 0.0]), Err(NetworkError::InvalidToken { value: -1.0, vocabulary: size }));
+		assert!(nn.predict_batch(&matrix::Matrix::from_rows(&[vec![0.0, 1.0], vec![0.5, 1.0]])).is_err());
+		let invalid = Dataset::new(vec![(vec![0.0, 99.0], samples[0].1.clone())]);
+		assert!(nn.fit(&invalid, 1, 1, false).is_err());
+
+		nn.save_to_file(&path, false).expect("Error saving file");
+		let reloaded = network::NeuralNetwork::load_from_file(&path).expect("Error loading file");
+		assert_eq!(reloaded.layers[0].name(), embedding::Embedding::NAME);
+		assert_eq!(reloaded.predict(samples[0].0.clone()).unwrap(), nn.predict(samples[0].0.clone()).unwrap());
+		fs::remove_file(&path).expect("Failed to delete test file.");
+	}
+
+	#[test]
+	fn test_training_callbacks() {
+		use callback::{Callback, Control, CsvLogger, EarlyStopping, ModelCheckpoint, Monitor};
+		use training::{EpochRecord, History};
+
+		#[derive(Default)]
+		struct Counter {
+			events: Vec<String>,
+			batches: usize,
+		}
+
+		impl Callback for Counter {
+			fn on_epoch_begin(&mut self, epoch: usize, _network: &network::NeuralNetwork) {
+				self.events.push(format!("begin {}", epoch));
+			}
+
+			fn on_batch_end(&mut self, _epoch: usize, _batch: usize, loss: f64) {
+				assert!(loss.is_finite());
+				self.batches += 1;
+			}
+
+			fn on_epoch_end(&mut self, record: &EpochRecord, _network: &network::NeuralNetwork) -> Result<Control, NetworkError> {
+				self.events.push(format!("end {}", record.epoch));
+				return Ok(if record.epoch == 2 { Control::Stop } else { Control::Continue });
+			}
+
+			fn on_train_end(&mut self, history: &History, _network: &mut network::NeuralNetwork) -> Result<(), NetworkError> {
+				self.events.push(format!("trained {}", history.epochs.len()));
+				return Ok(());
+			}
+		}
+
+		let samples = vec![(vec![0.0], vec![0.0]), (vec![1.0], vec![1.0]), (vec![0.5], vec![0.5])];
+		let mut nn = network::NeuralNetwork::builder(&[1, 4, 1]).seed(12).build();
+		let mut counter = Counter::default();
+		let history = nn.fit_with_callbacks(&Dataset::new(samples.clone()), 10, 2, false, &mut [&mut counter]).unwrap();
+		assert_eq!(history.epochs.len(), 3);
+		assert_eq!(counter.batches, 6);
+		assert_eq!(counter.events, vec!["begin 0", "end 0", "begin 1", "end 1", "begin 2", "end 2", "trained 3"]);
+
+		// Validation targets contradict the training ones, so the validation loss soon stops
+		// improving, training stops patience epochs later and the best weights are restored
+		let validation = vec![(vec![0.0], vec![1.0]), (vec![1.0], vec![0.0])];
+		let dataset = Dataset::new(samples.clone()).with_validation(validation.clone()).with_seed(13);
+		let mut nn = network::NeuralNetwork::builder(&[1, 4, 1]).seed(12).learning_rate(0.5).build();
+		let mut stopping = EarlyStopping::new(3).restore_best();
+		let (log_path, checkpoint_path) = ("nn_training_log.csv", "nn_checkpoint_{epoch}.json");
+		let mut logger = CsvLogger::new(log_path);
+		let mut checkpoint = ModelCheckpoint::new(checkpoint_path).best_only(Monitor::ValidationLoss);
+		let history = nn.fit_with_callbacks(&dataset, 200, 3, true, &mut [&mut stopping, &mut logger, &mut checkpoint]).unwrap();
+		let best = stopping.best_epoch().unwrap();
+		assert_eq!(stopping.stopped_epoch(), Some(best + 3));
+		assert_eq!(history.epochs.len(), best + 4);
+		assert!((nn.evaluate(&validation).unwrap() - history.epochs[best].validation_loss.unwrap()).abs() < 1e-12);
+
+		let log = fs::read_to_string(log_path).expect("Error reading log");
+		let lines: Vec<&str> = log.lines().collect();
+		assert_eq!(lines[0], "epoch,loss,validation_loss,learning_rate");
+		assert_eq!(lines.len(), history.epochs.len() + 1);
+		assert!(lines[1].starts_with("0,"));
+		fs::remove_file(log_path).expect("Failed to delete test file.");
+
+		// Checkpoints were saved on improvements only, the last one holds the restored weights
+		let saved = network::NeuralNetwork::load_from_file(&checkpoint.path_for(best)).expect("Error loading checkpoint");
+		assert_eq!(saved.predict(vec![0.3]).unwrap(), nn.predict(vec![0.3]).unwrap());
+		for epoch in 0..history.epochs.len() {
+			let path = checkpoint.path_for(epoch);
+			assert!(epoch <= best || !Path::new(&path).exists());
+			if Path::new(&path).exists() {
+				fs::remove_file(&path).expect("Failed to delete test file.");
+			}
+		}
+	}
+
+	#[test]
+	fn test_data_parallel_training() {
+		use rand::Rng;
+		let mut rng = StdRng::seed_from_u64(14);
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = (0..64).map(|_| {
+			let (x, y): (f64, f64) = (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+			(vec![x, y], vec![if x * y > 0.0 { 1.0 } else { 0.0 }])
+		}).collect();
+		let weights = |nn: &network::NeuralNetwork| -> Vec<f64> {
+			return nn.layers.iter().flat_map(|l| l.parameters().concat()).collect();
+		};
+
+		// Without dropout or batch normalization the shards add up to the gradient of the whole batch
+		let mut single = network::NeuralNetwork::builder(&[2, 8, 1]).seed(15).build();
+		let mut parallel = single.clone().with_threads(3);
+		for batch in samples.chunks(16) {
+			let expected = single.train_batch(batch, 0.5).unwrap();
+			let loss = parallel.train_batch(batch, 0.5).unwrap();
+			assert!((loss - expected).abs() < 1e-12);
+		}
+		for (a, b) in weights(&single).iter().zip(weights(&parallel)) {
+			assert!((a - b).abs() < 1e-12);
+		}
+
+		// With dropout every shard draws its own masks, reproducibly for a seed and thread count
+		let train = |threads: usize| -> network::NeuralNetwork {
+			let mut nn = network::NeuralNetwork::builder(&[2, 16, 1]).seed(16).dropout(0, 0.2)
+				.activation(Activation::Tanh)
+				.optimizer(Adam::default())
+				.learning_rate(0.02)
+				.threads(threads)
+				.build();
+			nn.fit(&Dataset::new(samples.clone()).with_seed(17), 150, 16, true).unwrap();
+			return nn;
+		};
+		let first = train(4);
+		assert_eq!(weights(&first), weights(&train(4)));
+		assert_ne!(weights(&first), weights(&train(2)));
+		let correct = samples.iter().filter(|(input, target)| (first.predict(input.clone()).unwrap()[0] > 0.5) == (target[0] == 1.0)).count();
+		assert!(correct >= 56, "{} of 64", correct);
+
+		let json = serde_json::to_string(&first).unwrap();
+		let loaded: network::NeuralNetwork = serde_json::from_str(&json).unwrap();
+		assert_eq!(loaded.threads, 4);
+	}
+
+	#[test]
+	fn test_parallel_batch_norm_statistics() {
+		use layer::Layer;
+		use rand::Rng;
+		let mut rng = StdRng::seed_from_u64(18);
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = (0..40).map(|_| {
+			let x: Vec<f64> = vec![rng.gen_range(0.0..4.0), rng.gen_range(-1.0..1.0), rng.gen_range(-3.0..5.0)];
+			let y = vec![x[0] - x[2]];
+			(x, y)
+		}).collect();
+		let layers: Vec<Box<dyn Layer>> = vec![
+			Box::new(normalization::BatchNorm::new(3)),
+			Box::new(dense::Dense::new(1, 3, Activation::Identity, Initializer::XavierUniform, &mut rng)),
+		];
+		let network = network::NeuralNetwork::from_layers(layers).unwrap();
+
+		// Uneven shards, the running statistics still follow the whole batch once per step
+		let statistics = |threads: usize| -> (Vec<f64>, Vec<f64>) {
+			let mut nn = network.clone().with_threads(threads);
+			for batch in samples.chunks(10) {
+				nn.train_batch(batch, 0.1).unwrap();
+			}
+			let bn = nn.layers[0].downcast_ref::<normalization::BatchNorm>().unwrap();
+			return (bn.running_mean.clone(), bn.running_variance.clone());
+		};
+		let (mean, variance) = statistics(1);
+		for threads in [3, 4] {
+			let (parallel_mean, parallel_variance) = statistics(threads);
+			for (a, b) in mean.iter().chain(&variance).zip(parallel_mean.iter().chain(&parallel_variance)) {
+				assert!((a - b).abs() < 1e-12, "{} threads: {} != {}", threads, a, b);
+			}
+		}
+	}
+
+	#[test]
+	fn test_quantized_network() {
+		use rand::Rng;
+		use quantize::{Granularity, QuantizedNetwork};
+		let path = "nn_quantized.json".to_string();
+		// Three gaussian blobs
+		let mut rng = StdRng::seed_from_u64(18);
+		let centers = [(-1.0, -1.0), (1.0, -1.0), (0.0, 1.0)];
+		let samples: Vec<(Vec<f64>, Vec<f64>)> = (0..150).map(|i| {
+			let (x, y) = centers[i % 3];
+			let mut target = vec![0.0; 3];
+			target[i % 3] = 1.0;
+			(vec![x + rng.gen_range(-0.6..0.6), y + rng.gen_range(-0.6..0.6)], target)
+		}).collect();
+		let (training, validation) = samples.split_at(100);
+
+		let mut nn = network::NeuralNetwork::builder(&[2, 64, 64, 3]).seed(19)
+			.activation(Activation::Relu)
+			.initializer(Initializer::HeUniform)
+			.output_activation(Activation::Softmax)
+			.loss(LossFunction::CategoricalCrossEntropy)
+			.optimizer(Adam::default())
+			.learning_rate(0.005)
+			.build();
+		nn.fit(&Dataset::new(training.to_vec()).with_seed(20), 30, 10, true).unwrap();
+
+		let size = serde_json::to_string(&nn).unwrap().len();
+		for granularity in [Granularity::PerLayer, Granularity::PerChannel] {
+			let quantized = nn.quantize(granularity);
+			let report = quantized.compare(&nn, validation).unwrap();
+			assert!(report.accuracy > 0.9, "{:?}", report);
+			assert!(report.accuracy_drop() <= 0.04, "{:?}", report);
+			assert!(report.agreement >= 0.95, "{:?}", report);
+			assert!(report.max_output_error > 0.0 && report.mean_output_error < 0.05, "{:?}", report);
+			assert!((report.quantized_loss - report.loss).abs() < 0.1, "{:?}", report);
+
+			let quantized_size = serde_json::to_string(&quantized).unwrap().len();
+			assert!(quantized_size * 7 < size, "{} vs {} bytes", quantized_size, size);
+		}
+
+		let quantized = nn.quantize(Granularity::PerChannel);
+		quantized.save_to_file(&path, false).expect("Error saving file");
+		let reloaded = QuantizedNetwork::load_from_file(&path).expect("Error loading file");
+		assert_eq!(reloaded.granularity, Granularity::PerChannel);
+		for (input, _) in validation {
+			assert_eq!(reloaded.predict(input.clone()).unwrap(), quantized.predict(input.clone()).unwrap());
+		}
+		fs::remove_file(&path).expect("Failed to delete test file.");
+		assert_eq!(quantized.predict(vec![1.0]), Err(NetworkError::InputSize { expected: 2, found: 1 }));
+	}
+}
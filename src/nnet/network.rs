@@ -1,80 +1,497 @@
-use rand::Rng;
+use std::thread;
+
+use rand::{RngCore, SeedableRng};
+use rand::rngs::StdRng;
 use serde::{Serialize, Deserialize};
 
 use crate::persist::json::JsonPersist;
-use super::utils::sigmoid_derivative;
+use super::activation::Activation;
+use super::builder::NetworkBuilder;
+use super::callback::{Callback, Control};
+use super::error::NetworkError;
 use super::layer::Layer;
+use super::loss::{Loss, LossFunction};
+use super::matrix::Matrix;
+use super::optimizer::{Optimizer, OptimizerKind};
+use super::quantize::{Granularity, QuantizedNetwork};
+use super::schedule::Schedule;
+use super::summary::{LayerSummary, Summary};
+use super::tape::{LayerTrace, Tape};
+use super::training::{Dataset, EpochRecord, History, Sample};
+
+pub(crate) const DEFAULT_LEARNING_RATE: f64 = 0.1;
+
+// Summed loss and parameter gradients of part of a batch, and its traces
+type ShardGradients = (f64, Vec<Vec<Vec<f64>>>, Vec<Tape>);
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct NeuralNetwork {
-	pub layers: Vec<Layer>,
+	pub layers: Vec<Box<dyn Layer>>,
+	#[serde(default)]
+	pub loss: LossFunction,
+	#[serde(default = "default_learning_rate")]
+	pub learning_rate: f64,
+	#[serde(default)]
+	pub optimizer: OptimizerKind,
+	#[serde(default)]
+	pub schedule: Schedule,
+	// Worker threads sharing the gradient computation of every training batch
+	#[serde(default = "default_threads")]
+	pub threads: usize,
+	// Rescales the gradients of an update whose overall norm exceeds it
+	#[serde(default)]
+	pub clip_norm: Option<f64>,
+}
+
+fn default_learning_rate() -> f64 {
+	return DEFAULT_LEARNING_RATE;
+}
+
+fn default_threads() -> usize {
+	return 1;
 }
 
 impl JsonPersist for NeuralNetwork {}
 
 impl NeuralNetwork {
 	pub fn new(sizes: &[usize]) -> Self {
-		let mut layers = Vec::new();
-		let mut rng = rand::thread_rng();
-		for w in sizes.windows(2) {
-			layers.push(Layer::new(w[1], w[0], || rng.gen_range(-1.0..1.0)));
+		/* Builds a network of sigmoid layers */
+		return NetworkBuilder::new(sizes).build();
+	}
+
+	pub fn from_layers(layers: Vec<Box<dyn Layer>>) -> Result<Self, NetworkError> {
+		/* Stacks arbitrary layers, each must accept as many values as the previous one outputs */
+		for (idx, pair) in layers.windows(2).enumerate() {
+			if pair[1].output_size_for(pair[0].output_size()).is_none() {
+				return Err(NetworkError::LayerSize { layer: idx + 1, expected: pair[0].output_size(), found: pair[1].input_size() });
+			}
+		}
+		return Ok(Self {
+			layers,
+			loss: LossFunction::default(),
+			learning_rate: DEFAULT_LEARNING_RATE,
+			optimizer: OptimizerKind::default(),
+			schedule: Schedule::default(),
+			threads: default_threads(),
+			clip_norm: None,
+		});
+	}
+
+	pub fn builder(sizes: &[usize]) -> NetworkBuilder {
+		/* Configures a network before building it, see NetworkBuilder */
+		return NetworkBuilder::new(sizes);
+	}
+
+	pub fn with_activations(sizes: &[usize], activations: &[Activation]) -> Self {
+		/* Builds a network where activations[i] is used by the layer between sizes[i] and sizes[i+1] */
+		return NetworkBuilder::new(sizes).activations(activations).build();
+	}
+
+	pub fn with_loss(mut self, loss: LossFunction) -> Self {
+		/* Sets the loss minimized by train() */
+		self.loss = loss;
+		return self;
+	}
+
+	pub fn with_learning_rate(mut self, learning_rate: f64) -> Self {
+		/* Sets the base learning rate used by fit() */
+		self.learning_rate = learning_rate;
+		return self;
+	}
+
+	pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+		/* Sets how fit() varies the learning rate across epochs */
+		self.schedule = schedule;
+		return self;
+	}
+
+	pub fn with_optimizer(mut self, optimizer: impl Into<OptimizerKind>) -> Self {
+		/* Sets the update rule used by training, plain SGD by default */
+		self.optimizer = optimizer.into();
+		return self;
+	}
+
+	pub fn with_threads(mut self, threads: usize) -> Self {
+		/* Splits every training batch across threads workers, see train_batch() */
+		self.threads = threads.max(1);
+		return self;
+	}
+
+	pub fn with_clip_norm(mut self, max_norm: f64) -> Self {
+		/* Rescales the gradients of every update to an overall norm of at most max_norm */
+		self.clip_norm = Some(max_norm);
+		return self;
+	}
+
+	pub fn classifier(sizes: &[usize]) -> Self {
+		/* Builds a sigmoid network with a softmax output trained on categorical cross-entropy,
+		or with a single sigmoid output trained on binary cross-entropy. */
+		let builder = NetworkBuilder::new(sizes);
+		if sizes.len() > 1 && sizes.last().is_some_and(|n| *n > 1) {
+			return builder
+				.output_activation(Activation::Softmax)
+				.loss(LossFunction::CategoricalCrossEntropy)
+				.build();
+		}
+		return builder.loss(LossFunction::BinaryCrossEntropy).build();
+	}
+
+	pub fn predict(&self, inputs: Vec<f64>) -> Result<Vec<f64>, NetworkError> {
+		self.check_input(&inputs)?;
+		return Ok(self.forward(Matrix::row_vector(inputs)).into_data());
+	}
+
+	pub fn predict_batch(&self, inputs: &Matrix) -> Result<Matrix, NetworkError> {
+		/* Predicts every row of inputs in one batched pass */
+		self.output_size_for(inputs.cols())?;
+		for r in 0..inputs.rows() {
+			self.layers[0].check_input(inputs.row(r))?;
+		}
+		return Ok(self.forward(inputs.clone()));
+	}
+
+	pub fn train(&mut self, input: Vec<f64>, target: Vec<f64>, learning_rate: f64) -> Result<f64, NetworkError> {
+		/* Single gradient descent step on the network's loss, returns the loss of the sample */
+		let loss = self.loss;
+		return self.train_with_loss(input, target, learning_rate, &loss);
+	}
+
+	pub fn train_with_loss(&mut self, input: Vec<f64>, target: Vec<f64>, learning_rate: f64, loss: &dyn Loss) -> Result<f64, NetworkError> {
+		/* Single gradient descent step on a custom loss, returns the loss of the sample */
+		self.check_sample(&input, &target)?;
+		let tape = self.forward_traced(Matrix::row_vector(input), &mut rand::thread_rng());
+		let mut gradients = self.zero_gradients();
+		let value = self.accumulate_gradients(&tape, &Matrix::row_vector(target), loss, &mut gradients);
+		let penalty = self.apply_gradients(gradients, learning_rate);
+		self.update_statistics(&[tape]);
+		return Ok(value + penalty);
+	}
+
+	pub fn train_batch(&mut self, batch: &[Sample], learning_rate: f64) -> Result<f64, NetworkError> {
+		/* Single gradient descent step on the gradient averaged over the batch,
+		returns the mean loss of the batch including the regularization penalty.
+		With several threads, the batch is split into one contiguous shard per thread whose
+		gradients are summed in shard order before the update, so that training is deterministic
+		for a given seed and thread count. Batch normalization then normalizes every shard on
+		its own statistics, while its running statistics are updated once from the statistics
+		of the whole batch. */
+		return self.train_batch_with_rng(batch, learning_rate, &mut rand::thread_rng());
+	}
+
+	fn train_batch_with_rng(&mut self, batch: &[Sample], learning_rate: f64, rng: &mut dyn RngCore) -> Result<f64, NetworkError> {
+		for (input, target) in batch {
+			self.check_sample(input, target)?;
+		}
+		if batch.is_empty() {
+			return Ok(0.0);
+		}
+		let shard_size = batch.len().div_ceil(self.threads.max(1));
+		let (total, mut gradients, tapes) = if shard_size == batch.len() {
+			self.shard_gradients(batch, rng)
+		} else {
+			self.parallel_gradients(batch, shard_size, rng)
+		};
+		let n = batch.len() as f64;
+		for g in gradients.iter_mut().flatten().flatten() {
+			*g /= n;
+		}
+		let penalty = self.apply_gradients(gradients, learning_rate);
+		self.update_statistics(&tapes);
+		return Ok(total / n + penalty);
+	}
+
+	fn shard_gradients(&self, shard: &[Sample], rng: &mut dyn RngCore) -> ShardGradients {
+		/* One traced pass per run of equally long samples, see same_width() */
+		let mut total = 0.0;
+		let mut gradients = self.zero_gradients();
+		let mut tapes = Vec::new();
+		for group in shard.chunk_by(same_width) {
+			let inputs: Vec<Vec<f64>> = group.iter().map(|(i, _)| i.clone()).collect();
+			let targets: Vec<Vec<f64>> = group.iter().map(|(_, t)| t.clone()).collect();
+			let tape = self.forward_traced(Matrix::from_rows(&inputs), rng);
+			total += self.accumulate_gradients(&tape, &Matrix::from_rows(&targets), &self.loss, &mut gradients);
+			tapes.push(tape);
+		}
+		return (total, gradients, tapes);
+	}
+
+	fn parallel_gradients(&self, batch: &[Sample], shard_size: usize, rng: &mut dyn RngCore) -> (f64, Vec<Vec<Vec<f64>>>, Vec<Tape>) {
+		/* Computes the shards of a batch on their own threads, each with an rng seeded from rng,
+		and sums their losses and gradients in shard order */
+		let shards: Vec<(&[Sample], u64)> = batch.chunks(shard_size).map(|shard| (shard, rng.next_u64())).collect();
+		let results: Vec<ShardGradients> = thread::scope(|scope| {
+			let workers: Vec<_> = shards.iter()
+				.map(|(shard, seed)| scope.spawn(move || self.shard_gradients(shard, &mut StdRng::seed_from_u64(*seed))))
+				.collect();
+			workers.into_iter().map(|w| w.join().expect("Training thread panicked")).collect()
+		});
+		let mut total = 0.0;
+		let mut gradients = self.zero_gradients();
+		let mut tapes = Vec::with_capacity(results.len());
+		for (shard_total, shard_gradients, shard_tapes) in results {
+			total += shard_total;
+			for (g, s) in gradients.iter_mut().flatten().flatten().zip(shard_gradients.iter().flatten().flatten()) {
+				*g += s;
+			}
+			tapes.extend(shard_tapes);
+		}
+		return (total, gradients, tapes);
+	}
+
+	pub fn fit(&mut self, dataset: &Dataset, epochs: usize, batch_size: usize, shuffle: bool) -> Result<History, NetworkError> {
+		/* Trains for a number of epochs over the dataset in mini-batches of batch_size,
+		returns the mean training loss, validation loss and learning rate of every epoch.
+		Shuffling and dropout masks are drawn from the dataset's rng. */
+		return self.fit_with_callbacks(dataset, epochs, batch_size, shuffle, &mut []);
+	}
+
+	pub fn fit_with_callbacks(&mut self, dataset: &Dataset, epochs: usize, batch_size: usize, shuffle: bool, callbacks: &mut [&mut dyn Callback]) -> Result<History, NetworkError> {
+		/* fit(), notifying the callbacks as training progresses. Training ends early after
+		an epoch for which any callback returns Control::Stop. */
+		for (input, target) in dataset.samples.iter().chain(dataset.validation.iter().flatten()) {
+			self.check_sample(input, target)?;
+		}
+		let mut history = History::default();
+		let mut rng = dataset.rng();
+		for callback in callbacks.iter_mut() {
+			callback.on_train_begin(self)?;
+		}
+		for epoch in 0..epochs {
+			for callback in callbacks.iter_mut() {
+				callback.on_epoch_begin(epoch, self);
+			}
+			let monitored = history.last().map(|e| e.validation_loss.unwrap_or(e.loss));
+			let learning_rate = self.schedule.learning_rate(epoch, self.learning_rate, monitored);
+			let mut total = 0.0;
+			for (batch, indices) in dataset.batches(&mut rng, batch_size, shuffle).into_iter().enumerate() {
+				for callback in callbacks.iter_mut() {
+					callback.on_batch_begin(epoch, batch);
+				}
+				let samples: Vec<Sample> = indices.iter().map(|i| dataset.samples[*i].clone()).collect();
+				let loss = self.train_batch_with_rng(&samples, learning_rate, &mut rng)?;
+				total += loss * samples.len() as f64;
+				for callback in callbacks.iter_mut() {
+					callback.on_batch_end(epoch, batch, loss);
+				}
+			}
+			let loss = total / dataset.len().max(1) as f64;
+			let validation_loss = match &dataset.validation {
+				Some(v) => Some(self.evaluate(v)?),
+				None => None,
+			};
+			let record = EpochRecord { epoch, loss, validation_loss, learning_rate };
+			let mut stop = false;
+			for callback in callbacks.iter_mut() {
+				stop |= callback.on_epoch_end(&record, self)? == Control::Stop;
+			}
+			history.epochs.push(record);
+			if stop {
+				break;
+			}
+		}
+		for callback in callbacks.iter_mut() {
+			callback.on_train_end(&history, self)?;
+		}
+		return Ok(history);
+	}
+
+	pub fn quantize(&self, granularity: Granularity) -> QuantizedNetwork {
+		/* Fp8 inference copy of the network, see QuantizedNetwork */
+		return QuantizedNetwork::new(self, granularity);
+	}
+
+	pub fn evaluate(&self, samples: &[Sample]) -> Result<f64, NetworkError> {
+		/* Mean loss over the samples, without training or regularization penalty */
+		for (input, target) in samples {
+			self.check_sample(input, target)?;
+		}
+		if samples.is_empty() {
+			return Ok(0.0);
+		}
+		let mut total = 0.0;
+		for group in samples.chunk_by(same_width) {
+			let inputs: Vec<Vec<f64>> = group.iter().map(|(i, _)| i.clone()).collect();
+			let output = self.forward(Matrix::from_rows(&inputs));
+			total += group.iter().enumerate().map(|(r, (_, target))| self.loss.value(output.row(r), target)).sum::<f64>();
+		}
+		return Ok(total / samples.len() as f64);
+	}
+
+	pub(crate) fn batch_gradients(&self, batch: &[Sample], rng: &mut dyn RngCore) -> (f64, Vec<Vec<Vec<f64>>>) {
+		/* Mean training loss of a batch, regularization penalty included, and its gradient
+		w.r.t. every parameter tensor, without updating anything */
+		let (total, mut gradients, _) = self.shard_gradients(batch, rng);
+		let n = batch.len() as f64;
+		for g in gradients.iter_mut().flatten().flatten() {
+			*g /= n;
+		}
+		let mut value = total / n;
+		for (layer, gradient) in self.layers.iter().zip(gradients.iter_mut()) {
+			value += layer.penalty();
+			layer.add_penalty_gradient(gradient);
+		}
+		return (value, gradients);
+	}
+
+	pub(crate) fn batch_loss(&self, batch: &[Sample], rng: &mut dyn RngCore) -> f64 {
+		/* The loss differentiated by batch_gradients() */
+		let mut total = 0.0;
+		for group in batch.chunk_by(same_width) {
+			let inputs: Vec<Vec<f64>> = group.iter().map(|(i, _)| i.clone()).collect();
+			let tape = self.forward_traced(Matrix::from_rows(&inputs), rng);
+			let output = tape.output().expect("Network has no layers");
+			total += group.iter().enumerate().map(|(r, (_, target))| self.loss.value(output.row(r), target)).sum::<f64>();
 		}
-		return Self { layers };
+		let penalty: f64 = self.layers.iter().map(|l| l.penalty()).sum();
+		return total / batch.len() as f64 + penalty;
 	}
 
-	pub fn predict(&mut self, mut inputs: Vec<f64>) -> Vec<f64> {
-		for layer in &mut self.layers {
+	fn forward(&self, mut inputs: Matrix) -> Matrix {
+		/* Batched forward pass without validating the input width */
+		for layer in &self.layers {
 			inputs = layer.forward(&inputs);
 		}
 		return inputs;
 	}
 
-	pub fn train(&mut self, input: Vec<f64>, target: Vec<f64>, learning_rate: f64) {
-		let output = self.predict(input);
+	fn forward_traced(&self, mut inputs: Matrix, rng: &mut dyn RngCore) -> Tape {
+		/* Batched training pass recording every layer's activations for backpropagation */
+		let mut tape = Tape::new();
+		for layer in &self.layers {
+			let trace = layer.trace(inputs, rng);
+			inputs = trace.output.clone();
+			tape.traces.push(trace);
+		}
+		return tape;
+	}
+
+	fn output_size_for(&self, input_size: usize) -> Result<usize, NetworkError> {
+		/* Number of values output for a sample of input_size values, which every layer must accept */
+		if self.layers.is_empty() {
+			return Err(NetworkError::EmptyNetwork);
+		}
+		let mut size = input_size;
+		for layer in &self.layers {
+			size = layer.output_size_for(size).ok_or(NetworkError::InputSize { expected: self.input_size(), found: input_size })?;
+		}
+		return Ok(size);
+	}
+
+	fn check_input(&self, input: &[f64]) -> Result<(), NetworkError> {
+		self.output_size_for(input.len())?;
+		return self.layers[0].check_input(input);
+	}
+
+	pub(crate) fn check_sample(&self, input: &[f64], target: &[f64]) -> Result<(), NetworkError> {
+		let expected = self.output_size_for(input.len())?;
+		self.layers[0].check_input(input)?;
+		if target.len() != expected {
+			return Err(NetworkError::TargetSize { expected, found: target.len() });
+		}
+		return Ok(());
+	}
+
+	fn zero_gradients(&self) -> Vec<Vec<Vec<f64>>> {
+		return self.layers.iter().map(|l| l.zero_gradient()).collect();
+	}
 
-        let mut deltas: Vec<Vec<f64>> = Vec::new();
+	fn accumulate_gradients(&self, tape: &Tape, targets: &Matrix, loss: &dyn Loss, gradients: &mut [Vec<Vec<f64>>]) -> f64 {
+		/* Backpropagates a traced batch, one sample per row, and adds its parameter gradients.
+		Returns the summed loss of the batch. */
+		let output = tape.output().expect("Network has no layers");
+		let last = self.layers.len() - 1;
+		let output_trace = &tape.traces[last];
 
-        for (i, layer) in self.layers.iter().rev().enumerate() {
-            let idx = self.layers.len() - 1 - i;
-            let mut layer_deltas = Vec::new();
+		// The fused output delta skips the output activation, so it only applies to a dense output layer
+		let dense_output = self.layers[last].as_dense();
+		let mut fused = false;
+		let mut total = 0.0;
+		let rows: Vec<Vec<f64>> = (0..output.rows()).map(|r| {
+			let (o, t) = (output.row(r), targets.row(r));
+			total += loss.value(o, t);
+			match dense_output.and_then(|l| loss.output_delta(l.activation, o, t)) {
+				Some(delta) => { fused = true; delta },
+				None => loss.gradient(o, t),
+			}
+		}).collect();
 
-            if i == 0 {
-                for (o, t) in output.iter().zip(&target) {
-                    let z = layer.neurons[layer_deltas.len()].last_z;
-                    let d = (o - t) * sigmoid_derivative(z);
-                    layer_deltas.push(d);
-                }
-            } else {
-                let next_layer = &self.layers[idx + 1];
-                for (j, neuron) in layer.neurons.iter().enumerate() {
-                    let z = neuron.last_z;
-                    let mut sum = 0.0;
-                    for (k, next_neuron) in next_layer.neurons.iter().enumerate() {
-                        sum += next_neuron.weights[j] * deltas[0][k];
-                    }
-                    layer_deltas.push(sum * sigmoid_derivative(z));
-                }
-            }
+		let rows = Matrix::from_rows(&rows);
+		let mut grad = match dense_output {
+			Some(dense) if fused => dense.backward_deltas(output_trace, &rows, &mut gradients[last]),
+			_ => self.layers[last].backward(output_trace, &rows, &mut gradients[last]),
+		};
+		for idx in (0..last).rev() {
+			grad = self.layers[idx].backward(&tape.traces[idx], &grad, &mut gradients[idx]);
+		}
+		return total;
+	}
 
-            deltas.insert(0, layer_deltas);
-        }
+	fn apply_gradients(&mut self, mut gradients: Vec<Vec<Vec<f64>>>, learning_rate: f64) -> f64 {
+		/* Adds the regularization gradients and updates every parameter tensor as its own
+		optimizer group. Returns the regularization penalty before the update. */
+		let mut penalty = 0.0;
+		for (layer, gradient) in self.layers.iter().zip(gradients.iter_mut()) {
+			penalty += layer.penalty();
+			layer.add_penalty_gradient(gradient);
+		}
+		if let Some(max_norm) = self.clip_norm {
+			let norm = gradients.iter().flatten().flatten().map(|g| g * g).sum::<f64>().sqrt();
+			if norm > max_norm {
+				for g in gradients.iter_mut().flatten().flatten() {
+					*g *= max_norm / norm;
+				}
+			}
+		}
+		self.optimizer.step();
+		let mut group = 0;
+		for (layer, gradient) in self.layers.iter_mut().zip(&gradients) {
+			for (params, grads) in layer.parameters_mut().into_iter().zip(gradient) {
+				self.optimizer.update(group, params, grads, learning_rate);
+				group += 1;
+			}
+			layer.apply_constraints();
+		}
+		return penalty;
+	}
 
-        for (layer, layer_deltas) in self.layers.iter_mut().zip(deltas) {
-            for (neuron, delta) in layer.neurons.iter_mut().zip(layer_deltas) {
-                neuron.update_weights(delta, learning_rate);
-            }
-        }
+	fn update_statistics(&mut self, tapes: &[Tape]) {
+		/* Updates every layer once with the traces of all shards of a batch */
+		for (l, layer) in self.layers.iter_mut().enumerate() {
+			let traces: Vec<&LayerTrace> = tapes.iter().map(|t| &t.traces[l]).collect();
+			layer.update_statistics(&traces);
+		}
 	}
 
 	pub fn input_size(&self) -> usize {
-		todo!("Implement introspection in the nn input dimension")
+		/* Number of values expected by predict(), per step for networks over sequences */
+		return self.layers.first().map_or(0, |l| l.input_size());
 	}
 
 	pub fn output_size(&self) -> usize {
-		todo!("Implement introspection in the nn output dimension")
+		/* Number of values returned by predict(), per step for networks over sequences */
+		return self.layers.last().map_or(0, |l| l.output_size());
 	}
 
 	pub fn hidden_layers_size(&self) -> usize {
-		todo!("Implement introspection in the number of hiden layers")
+		/* Number of layers between the input and the output layer, dropout included */
+		return self.layers.len().saturating_sub(1);
+	}
+
+	pub fn summary(&self) -> Summary {
+		/* Shape, activation and parameter count of every layer */
+		let layers: Vec<LayerSummary> = self.layers.iter().map(|l| LayerSummary {
+			name: l.name().to_string(),
+			input_size: l.input_size(),
+			output_size: l.output_size(),
+			activation: l.activation(),
+			parameters: l.parameter_count(),
+		}).collect();
+		let total_parameters = layers.iter().map(|l| l.parameters).sum();
+		return Summary { layers, total_parameters };
 	}
 }
+
+fn same_width(a: &Sample, b: &Sample) -> bool {
+	/* Samples of different widths, such as sequences of different lengths, go through separate passes */
+	return a.0.len() == b.0.len();
+}
@@ -0,0 +1,287 @@
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+
+use super::autodiff::{Graph, Var};
+use super::layer::Layer;
+use super::matrix::Matrix;
+use super::tape::LayerTrace;
+
+const DEFAULT_EPSILON: f64 = 1e-5;
+const DEFAULT_MOMENTUM: f64 = 0.1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatchNorm {
+	/* Normalizes every feature over the batch during training and with running
+	statistics at inference, then scales by gamma and shifts by beta */
+	pub gamma: Vec<f64>,
+	pub beta: Vec<f64>,
+	pub running_mean: Vec<f64>,
+	pub running_variance: Vec<f64>,
+	// Weight of the latest batch in the running statistics
+	pub momentum: f64,
+	pub epsilon: f64,
+}
+
+impl BatchNorm {
+	pub const NAME: &'static str = "BatchNorm";
+
+	pub fn new(size: usize) -> Self {
+		return Self {
+			gamma: vec![1.0; size],
+			beta: vec![0.0; size],
+			running_mean: vec![0.0; size],
+			running_variance: vec![1.0; size],
+			momentum: DEFAULT_MOMENTUM,
+			epsilon: DEFAULT_EPSILON,
+		};
+	}
+
+	pub fn size(&self) -> usize {
+		return self.gamma.len();
+	}
+}
+
+impl Layer for BatchNorm {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.size();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.size();
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		/* Inference pass using the running statistics */
+		let inv_std: Vec<f64> = self.running_variance.iter().map(|v| 1.0 / (v + self.epsilon).sqrt()).collect();
+		let mut output = inputs.clone();
+		for r in 0..output.rows() {
+			for (j, x) in output.row_mut(r).iter_mut().enumerate() {
+				*x = self.gamma[j] * (*x - self.running_mean[j]) * inv_std[j] + self.beta[j];
+			}
+		}
+		return output;
+	}
+
+	fn trace(&self, inputs: Matrix, _rng: &mut dyn RngCore) -> LayerTrace {
+		/* Training pass normalizing with the batch statistics, which are kept
+		to update the running ones once the step is applied */
+		let n = inputs.rows().max(1) as f64;
+		let mean: Vec<f64> = inputs.column_sums().iter().map(|s| s / n).collect();
+		let mut variance = vec![0.0; self.size()];
+		for r in 0..inputs.rows() {
+			for (j, x) in inputs.row(r).iter().enumerate() {
+				variance[j] += (x - mean[j]).powi(2) / n;
+			}
+		}
+		let inv_std: Vec<f64> = variance.iter().map(|v| 1.0 / (v + self.epsilon).sqrt()).collect();
+		let mut normalized = inputs.clone();
+		for r in 0..normalized.rows() {
+			for (j, x) in normalized.row_mut(r).iter_mut().enumerate() {
+				*x = (*x - mean[j]) * inv_std[j];
+			}
+		}
+		let output = scale_shift(&normalized, &self.gamma, &self.beta);
+		let cache = vec![normalized, Matrix::row_vector(inv_std), Matrix::row_vector(mean), Matrix::row_vector(variance)];
+		return LayerTrace { input: inputs, output, cache };
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		let (normalized, inv_std) = (&trace.cache[0], trace.cache[1].data());
+		accumulate_scale_shift(normalized, grad, gradient);
+		let n = grad.rows() as f64;
+		let mut sums = vec![0.0; self.size()];
+		let mut products = vec![0.0; self.size()];
+		for r in 0..grad.rows() {
+			for (j, (g, x)) in grad.row(r).iter().zip(normalized.row(r)).enumerate() {
+				sums[j] += g;
+				products[j] += g * x;
+			}
+		}
+		let mut input_grad = Matrix::zeros(grad.rows(), grad.cols());
+		for r in 0..grad.rows() {
+			for j in 0..grad.cols() {
+				let g = grad[(r, j)];
+				input_grad[(r, j)] = self.gamma[j] * inv_std[j] / n * (n * g - sums[j] - normalized[(r, j)] * products[j]);
+			}
+		}
+		return input_grad;
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		return vec![&self.gamma, &self.beta];
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return vec![&mut self.gamma, &mut self.beta];
+	}
+
+	fn update_statistics(&mut self, traces: &[&LayerTrace]) {
+		/* Moves the running statistics towards those of a batch, combining the mean and
+		variance of its shards weighted by their number of samples */
+		let n: usize = traces.iter().map(|t| t.input.rows()).sum();
+		if n == 0 {
+			return;
+		}
+		let mut mean = vec![0.0; self.size()];
+		for trace in traces {
+			let weight = trace.input.rows() as f64 / n as f64;
+			for (m, shard_mean) in mean.iter_mut().zip(trace.cache[2].data()) {
+				*m += weight * shard_mean;
+			}
+		}
+		// Within-shard variance plus the spread of the shard means around the batch mean
+		let mut variance = vec![0.0; self.size()];
+		for trace in traces {
+			let weight = trace.input.rows() as f64 / n as f64;
+			for (j, v) in variance.iter_mut().enumerate() {
+				*v += weight * (trace.cache[3][(0, j)] + (trace.cache[2][(0, j)] - mean[j]).powi(2));
+			}
+		}
+		for j in 0..self.size() {
+			self.running_mean[j] += self.momentum * (mean[j] - self.running_mean[j]);
+			self.running_variance[j] += self.momentum * (variance[j] - self.running_variance[j]);
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LayerNorm {
+	/* Normalizes every sample over its features, identically in training and inference,
+	then scales by gamma and shifts by beta */
+	pub gamma: Vec<f64>,
+	pub beta: Vec<f64>,
+	pub epsilon: f64,
+}
+
+impl LayerNorm {
+	pub const NAME: &'static str = "LayerNorm";
+
+	pub fn new(size: usize) -> Self {
+		return Self { gamma: vec![1.0; size], beta: vec![0.0; size], epsilon: DEFAULT_EPSILON };
+	}
+
+	pub fn size(&self) -> usize {
+		return self.gamma.len();
+	}
+}
+
+impl Layer for LayerNorm {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.size();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.size();
+	}
+
+	fn build_graph(&self, graph: &mut Graph, input: Var, parameters: &[Var]) -> Option<Var> {
+		/* (x - mean) / sqrt(variance + epsilon) over every row, then gamma * x + beta */
+		let mean = graph.mean_rows(input);
+		let centered = graph.sub_col(input, mean);
+		let squared = graph.square(centered);
+		let variance = graph.mean_rows(squared);
+		let shifted = graph.add_scalar(variance, self.epsilon);
+		let std = graph.sqrt(shifted);
+		let normalized = graph.div_col(centered, std);
+		let scaled = graph.mul_row(normalized, parameters[0]);
+		return Some(graph.add_row(scaled, parameters[1]));
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		return vec![&self.gamma, &self.beta];
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return vec![&mut self.gamma, &mut self.beta];
+	}
+}
+
+fn scale_shift(normalized: &Matrix, gamma: &[f64], beta: &[f64]) -> Matrix {
+	return normalized.map_rows(|row| row.iter().zip(gamma).zip(beta).map(|((x, g), b)| g * x + b).collect());
+}
+
+fn accumulate_scale_shift(normalized: &Matrix, grad: &Matrix, gradient: &mut [Vec<f64>]) {
+	/* Adds the gamma and beta gradients */
+	for r in 0..grad.rows() {
+		for (j, (g, x)) in grad.row(r).iter().zip(normalized.row(r)).enumerate() {
+			gradient[0][j] += g * x;
+			gradient[1][j] += g;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::compare::approx_eq;
+
+	fn numeric_input_gradient(f: impl Fn(&Matrix) -> f64, inputs: &Matrix) -> Matrix {
+		let h = 1e-6;
+		let mut numeric = Matrix::zeros(inputs.rows(), inputs.cols());
+		for r in 0..inputs.rows() {
+			for c in 0..inputs.cols() {
+				let (mut plus, mut minus) = (inputs.clone(), inputs.clone());
+				plus[(r, c)] += h;
+				minus[(r, c)] -= h;
+				numeric[(r, c)] = (f(&plus) - f(&minus)) / (2.0 * h);
+			}
+		}
+		return numeric;
+	}
+
+	fn weighted_sum(output: &Matrix, weights: &Matrix) -> f64 {
+		return output.data().iter().zip(weights.data()).map(|(o, w)| o * w).sum();
+	}
+
+	#[test]
+	fn test_batch_norm() {
+		let mut bn = BatchNorm::new(2);
+		bn.gamma = vec![1.5, 0.5];
+		bn.beta = vec![0.1, -0.2];
+		let inputs = Matrix::from_rows(&[vec![1.0, -2.0], vec![3.0, 0.5], vec![-0.5, 4.0]]);
+		let weights = Matrix::from_rows(&[vec![0.3, -1.0], vec![0.7, 0.2], vec![-0.4, 0.9]]);
+
+		let trace = bn.trace(inputs.clone(), &mut rand::thread_rng());
+		for j in 0..2 {
+			let column: Vec<f64> = (0..3).map(|r| trace.cache[0][(r, j)]).collect();
+			assert!(approx_eq(column.iter().sum::<f64>(), 0.0, 1e-12));
+		}
+		let mut gradient = vec![vec![0.0; 2], vec![0.0; 2]];
+		let analytic = bn.backward(&trace, &weights, &mut gradient);
+		let numeric = numeric_input_gradient(|x| weighted_sum(&bn.trace(x.clone(), &mut rand::thread_rng()).output, &weights), &inputs);
+		for (a, n) in analytic.data().iter().zip(numeric.data()) {
+			assert!(approx_eq(*a, *n, 1e-6), "{} != {}", a, n);
+		}
+		assert!(approx_eq(gradient[1][0], 0.3 + 0.7 - 0.4, 1e-12));
+
+		// Inference uses the running statistics, which move towards the batch ones
+		assert_eq!(bn.forward(&inputs).row(0), &[1.5 * 1.0 / (1.0 + bn.epsilon).sqrt() + 0.1, 0.5 * -2.0 / (1.0 + bn.epsilon).sqrt() - 0.2]);
+		bn.update_statistics(&[&trace]);
+		assert!(approx_eq(bn.running_mean[0], 0.1 * 3.5 / 3.0, 1e-12));
+	}
+
+	#[test]
+	fn test_layer_norm() {
+		let mut ln = LayerNorm::new(3);
+		ln.gamma = vec![1.0, 2.0, -0.5];
+		let inputs = Matrix::from_rows(&[vec![1.0, -2.0, 0.5], vec![3.0, 0.5, 1.0]]);
+		let weights = Matrix::from_rows(&[vec![0.3, -1.0, 0.2], vec![0.7, 0.2, -0.6]]);
+
+		let trace = ln.trace(inputs.clone(), &mut rand::thread_rng());
+		assert_eq!(trace.output, ln.forward(&inputs));
+		let mut gradient = vec![vec![0.0; 3], vec![0.0; 3]];
+		let analytic = ln.backward(&trace, &weights, &mut gradient);
+		let numeric = numeric_input_gradient(|x| weighted_sum(&ln.forward(x), &weights), &inputs);
+		for (a, n) in analytic.data().iter().zip(numeric.data()) {
+			assert!(approx_eq(*a, *n, 1e-6), "{} != {}", a, n);
+		}
+	}
+}
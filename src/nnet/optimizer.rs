@@ -0,0 +1,312 @@
+use serde::{Serialize, Deserialize};
+
+pub trait Optimizer {
+	/* Update rule turning gradients into parameter changes.
+	Parameters are addressed by group, a stable index of a parameter slice in the network,
+	so that per-parameter state such as velocities or moments can be kept between steps. */
+
+	fn step(&mut self) {
+		/* Called once before the updates of every training step */
+	}
+
+	fn update(&mut self, group: usize, params: &mut [f64], grads: &[f64], learning_rate: f64);
+}
+
+fn state_slot(state: &mut Vec<Vec<f64>>, group: usize, len: usize) -> &mut Vec<f64> {
+	/* Returns the zero-initialized state of a parameter group, growing the storage on first use */
+	if state.len() <= group {
+		state.resize(group + 1, vec![]);
+	}
+	if state[group].len() != len {
+		state[group] = vec![0.0; len];
+	}
+	return &mut state[group];
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Sgd {
+	pub momentum: f64,
+	pub nesterov: bool,
+	velocity: Vec<Vec<f64>>,
+}
+
+impl Sgd {
+	pub fn new(momentum: f64) -> Self {
+		return Self { momentum, nesterov: false, velocity: vec![] };
+	}
+
+	pub fn nesterov(momentum: f64) -> Self {
+		return Self { momentum, nesterov: true, velocity: vec![] };
+	}
+}
+
+impl Optimizer for Sgd {
+	fn update(&mut self, group: usize, params: &mut [f64], grads: &[f64], learning_rate: f64) {
+		if self.momentum == 0.0 {
+			for (p, g) in params.iter_mut().zip(grads) {
+				*p -= learning_rate * g;
+			}
+			return;
+		}
+		let velocity = state_slot(&mut self.velocity, group, params.len());
+		for ((p, g), v) in params.iter_mut().zip(grads).zip(velocity.iter_mut()) {
+			*v = self.momentum * *v + g;
+			let direction = if self.nesterov { g + self.momentum * *v } else { *v };
+			*p -= learning_rate * direction;
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Adagrad {
+	pub epsilon: f64,
+	sum_squares: Vec<Vec<f64>>,
+}
+
+impl Default for Adagrad {
+	fn default() -> Self {
+		return Self { epsilon: 1e-8, sum_squares: vec![] };
+	}
+}
+
+impl Optimizer for Adagrad {
+	fn update(&mut self, group: usize, params: &mut [f64], grads: &[f64], learning_rate: f64) {
+		let sum_squares = state_slot(&mut self.sum_squares, group, params.len());
+		for ((p, g), s) in params.iter_mut().zip(grads).zip(sum_squares.iter_mut()) {
+			*s += g * g;
+			*p -= learning_rate * g / (s.sqrt() + self.epsilon);
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RmsProp {
+	pub decay: f64,
+	pub epsilon: f64,
+	mean_squares: Vec<Vec<f64>>,
+}
+
+impl RmsProp {
+	pub fn new(decay: f64) -> Self {
+		return Self { decay, ..Self::default() };
+	}
+}
+
+impl Default for RmsProp {
+	fn default() -> Self {
+		return Self { decay: 0.9, epsilon: 1e-8, mean_squares: vec![] };
+	}
+}
+
+impl Optimizer for RmsProp {
+	fn update(&mut self, group: usize, params: &mut [f64], grads: &[f64], learning_rate: f64) {
+		let mean_squares = state_slot(&mut self.mean_squares, group, params.len());
+		for ((p, g), s) in params.iter_mut().zip(grads).zip(mean_squares.iter_mut()) {
+			*s = self.decay * *s + (1.0 - self.decay) * g * g;
+			*p -= learning_rate * g / (s.sqrt() + self.epsilon);
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Adam {
+	pub beta1: f64,
+	pub beta2: f64,
+	pub epsilon: f64,
+	timestep: u64,
+	first_moments: Vec<Vec<f64>>,
+	second_moments: Vec<Vec<f64>>,
+}
+
+impl Adam {
+	pub fn new(beta1: f64, beta2: f64) -> Self {
+		return Self { beta1, beta2, ..Self::default() };
+	}
+}
+
+impl Default for Adam {
+	fn default() -> Self {
+		return Self {
+			beta1: 0.9,
+			beta2: 0.999,
+			epsilon: 1e-8,
+			timestep: 0,
+			first_moments: vec![],
+			second_moments: vec![],
+		};
+	}
+}
+
+impl Optimizer for Adam {
+	fn step(&mut self) {
+		self.timestep += 1;
+	}
+
+	fn update(&mut self, group: usize, params: &mut [f64], grads: &[f64], learning_rate: f64) {
+		let t = self.timestep.max(1) as i32;
+		let correction1 = 1.0 - self.beta1.powi(t);
+		let correction2 = 1.0 - self.beta2.powi(t);
+		let (beta1, beta2, epsilon) = (self.beta1, self.beta2, self.epsilon);
+		let m = state_slot(&mut self.first_moments, group, params.len());
+		let v = state_slot(&mut self.second_moments, group, params.len());
+		for (i, (p, g)) in params.iter_mut().zip(grads).enumerate() {
+			m[i] = beta1 * m[i] + (1.0 - beta1) * g;
+			v[i] = beta2 * v[i] + (1.0 - beta2) * g * g;
+			let m_hat = m[i] / correction1;
+			let v_hat = v[i] / correction2;
+			*p -= learning_rate * m_hat / (v_hat.sqrt() + epsilon);
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AdamW {
+	pub weight_decay: f64,
+	pub adam: Adam,
+}
+
+impl AdamW {
+	pub fn new(weight_decay: f64) -> Self {
+		/* Adam with weight decay decoupled from the gradient moments */
+		return Self { weight_decay, adam: Adam::default() };
+	}
+}
+
+impl Default for AdamW {
+	fn default() -> Self {
+		return Self::new(0.01);
+	}
+}
+
+impl Optimizer for AdamW {
+	fn step(&mut self) {
+		self.adam.step();
+	}
+
+	fn update(&mut self, group: usize, params: &mut [f64], grads: &[f64], learning_rate: f64) {
+		for p in params.iter_mut() {
+			*p -= learning_rate * self.weight_decay * *p;
+		}
+		self.adam.update(group, params, grads, learning_rate);
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum OptimizerKind {
+	Sgd(Sgd),
+	Adagrad(Adagrad),
+	RmsProp(RmsProp),
+	Adam(Adam),
+	AdamW(AdamW),
+}
+
+impl Default for OptimizerKind {
+	fn default() -> Self {
+		return OptimizerKind::Sgd(Sgd::default());
+	}
+}
+
+impl Optimizer for OptimizerKind {
+	fn step(&mut self) {
+		match self {
+			OptimizerKind::Sgd(o) => o.step(),
+			OptimizerKind::Adagrad(o) => o.step(),
+			OptimizerKind::RmsProp(o) => o.step(),
+			OptimizerKind::Adam(o) => o.step(),
+			OptimizerKind::AdamW(o) => o.step(),
+		}
+	}
+
+	fn update(&mut self, group: usize, params: &mut [f64], grads: &[f64], learning_rate: f64) {
+		match self {
+			OptimizerKind::Sgd(o) => o.update(group, params, grads, learning_rate),
+			OptimizerKind::Adagrad(o) => o.update(group, params, grads, learning_rate),
+			OptimizerKind::RmsProp(o) => o.update(group, params, grads, learning_rate),
+			OptimizerKind::Adam(o) => o.update(group, params, grads, learning_rate),
+			OptimizerKind::AdamW(o) => o.update(group, params, grads, learning_rate),
+		}
+	}
+}
+
+impl From<Sgd> for OptimizerKind {
+	fn from(o: Sgd) -> Self { OptimizerKind::Sgd(o) }
+}
+
+impl From<Adagrad> for OptimizerKind {
+	fn from(o: Adagrad) -> Self { OptimizerKind::Adagrad(o) }
+}
+
+impl From<RmsProp> for OptimizerKind {
+	fn from(o: RmsProp) -> Self { OptimizerKind::RmsProp(o) }
+}
+
+impl From<Adam> for OptimizerKind {
+	fn from(o: Adam) -> Self { OptimizerKind::Adam(o) }
+}
+
+impl From<AdamW> for OptimizerKind {
+	fn from(o: AdamW) -> Self { OptimizerKind::AdamW(o) }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::compare::approx_eq;
+
+	fn minimize(optimizer: &mut dyn Optimizer, learning_rate: f64, steps: usize) -> f64 {
+		/* Minimizes (x - 3)^2 from x = 0 */
+		let mut x = [0.0];
+		for _ in 0..steps {
+			let grad = [2.0 * (x[0] - 3.0)];
+			optimizer.step();
+			optimizer.update(0, &mut x, &grad, learning_rate);
+		}
+		return x[0];
+	}
+
+	#[test]
+	fn test_convergence() {
+		let mut optimizers: Vec<(OptimizerKind, f64)> = vec![
+			(Sgd::default().into(), 0.1),
+			(Sgd::new(0.9).into(), 0.01),
+			(Sgd::nesterov(0.9).into(), 0.01),
+			(Adagrad::default().into(), 1.0),
+			(RmsProp::default().into(), 0.01),
+			(Adam::default().into(), 0.1),
+			(AdamW::new(0.0).into(), 0.1),
+		];
+		for (optimizer, learning_rate) in optimizers.iter_mut() {
+			let x = minimize(optimizer, *learning_rate, 2000);
+			assert!(approx_eq(x, 3.0, 1e-3), "{:?} converged to {}", optimizer, x);
+		}
+	}
+
+	#[test]
+	fn test_adam_first_step() {
+		// Bias correction makes the first Adam step exactly learning_rate in size
+		let mut adam = Adam::default();
+		let mut x = [1.0, 1.0];
+		adam.step();
+		adam.update(0, &mut x, &[0.5, -20.0], 0.1);
+		assert!(approx_eq(x[0], 0.9, 1e-6));
+		assert!(approx_eq(x[1], 1.1, 1e-6));
+	}
+
+	#[test]
+	fn test_adamw_decays_weights() {
+		let mut adamw = AdamW::new(0.1);
+		let mut x = [2.0];
+		adamw.step();
+		adamw.update(0, &mut x, &[0.0], 0.5);
+		assert!(approx_eq(x[0], 1.9, 1e-12));
+	}
+
+	#[test]
+	fn test_state_serialization() {
+		let mut optimizer: OptimizerKind = Adam::default().into();
+		minimize(&mut optimizer, 0.1, 5);
+		let json = serde_json::to_string(&optimizer).unwrap();
+		let restored: OptimizerKind = serde_json::from_str(&json).unwrap();
+		assert_eq!(restored, optimizer);
+	}
+}
@@ -0,0 +1,337 @@
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+
+use super::conv::Geometry;
+use super::layer::Layer;
+use super::matrix::Matrix;
+use super::tape::LayerTrace;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PoolMode {
+	Max,
+	Average,
+}
+
+fn pool(geometry: &Geometry, mode: PoolMode, inputs: &Matrix) -> (Matrix, Matrix) {
+	/* Pools every channel of every sample, returns the outputs and, for max pooling,
+	the input index each output was taken from */
+	let (oh, ow) = geometry.output_dims();
+	let window = (geometry.kernel[0] * geometry.kernel[1]) as f64;
+	let size = geometry.channels * oh * ow;
+	let mut output = Matrix::zeros(inputs.rows(), size);
+	let mut sources = Matrix::zeros(inputs.rows(), size);
+	for r in 0..inputs.rows() {
+		let sample = inputs.row(r);
+		for c in 0..geometry.channels {
+			for oy in 0..oh {
+				for ox in 0..ow {
+					let o = (c * oh + oy) * ow + ox;
+					let (mut best, mut best_index, mut total) = (f64::NEG_INFINITY, 0, 0.0);
+					for ky in 0..geometry.kernel[0] {
+						for kx in 0..geometry.kernel[1] {
+							let (y, x) = geometry.source(oy, ox, ky, kx).expect("Pooling windows lie within the input");
+							let i = (c * geometry.height + y) * geometry.width + x;
+							total += sample[i];
+							if sample[i] > best {
+								(best, best_index) = (sample[i], i);
+							}
+						}
+					}
+					output[(r, o)] = match mode {
+						PoolMode::Max => best,
+						PoolMode::Average => total / window,
+					};
+					sources[(r, o)] = best_index as f64;
+				}
+			}
+		}
+	}
+	return (output, sources);
+}
+
+fn pool_backward(geometry: &Geometry, mode: PoolMode, trace: &LayerTrace, grad: &Matrix) -> Matrix {
+	let (oh, ow) = geometry.output_dims();
+	let window = (geometry.kernel[0] * geometry.kernel[1]) as f64;
+	let mut input_grad = Matrix::zeros(grad.rows(), geometry.input_size());
+	for r in 0..grad.rows() {
+		for c in 0..geometry.channels {
+			for oy in 0..oh {
+				for ox in 0..ow {
+					let o = (c * oh + oy) * ow + ox;
+					match mode {
+						PoolMode::Max => input_grad[(r, trace.cache[0][(r, o)] as usize)] += grad[(r, o)],
+						PoolMode::Average => {
+							for ky in 0..geometry.kernel[0] {
+								for kx in 0..geometry.kernel[1] {
+									let (y, x) = geometry.source(oy, ox, ky, kx).expect("Pooling windows lie within the input");
+									input_grad[(r, (c * geometry.height + y) * geometry.width + x)] += grad[(r, o)] / window;
+								}
+							}
+						},
+					}
+				}
+			}
+		}
+	}
+	return input_grad;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "PoolRecord")]
+pub struct Pool2D {
+	pub mode: PoolMode,
+	channels: usize,
+	// Input height and width
+	height: usize,
+	width: usize,
+	size: [usize; 2],
+	stride: [usize; 2],
+}
+
+#[derive(Deserialize)]
+struct PoolRecord {
+	mode: PoolMode,
+	channels: usize,
+	height: usize,
+	width: usize,
+	size: [usize; 2],
+	stride: [usize; 2],
+}
+
+impl TryFrom<PoolRecord> for Pool2D {
+	type Error = String;
+
+	fn try_from(record: PoolRecord) -> Result<Self, String> {
+		let PoolRecord { mode, channels, height, width, size, stride } = record;
+		let layer = Pool2D { mode, channels, height, width, size, stride };
+		layer.geometry().check()?;
+		return Ok(layer);
+	}
+}
+
+impl Pool2D {
+	pub const NAME: &'static str = "Pool2D";
+
+	pub fn max(channels: usize, input: [usize; 2], size: [usize; 2]) -> Self {
+		/* Non-overlapping windows unless a stride is set with with_stride() */
+		return Self { mode: PoolMode::Max, channels, height: input[0], width: input[1], size, stride: size }.validated();
+	}
+
+	pub fn average(channels: usize, input: [usize; 2], size: [usize; 2]) -> Self {
+		return Self { mode: PoolMode::Average, ..Self::max(channels, input, size) };
+	}
+
+	pub fn with_stride(mut self, stride: [usize; 2]) -> Self {
+		self.stride = stride;
+		return self.validated();
+	}
+
+	pub fn output_shape(&self) -> [usize; 3] {
+		let (oh, ow) = self.geometry().output_dims();
+		return [self.channels, oh, ow];
+	}
+
+	pub fn channels(&self) -> usize {
+		return self.channels;
+	}
+
+	pub fn input_shape(&self) -> [usize; 2] {
+		/* Height and width of the input */
+		return [self.height, self.width];
+	}
+
+	pub fn size(&self) -> [usize; 2] {
+		return self.size;
+	}
+
+	pub fn stride(&self) -> [usize; 2] {
+		return self.stride;
+	}
+
+	fn geometry(&self) -> Geometry {
+		return Geometry {
+			channels: self.channels, height: self.height, width: self.width,
+			kernel: self.size, stride: self.stride, padding: [0, 0], dilation: [1, 1],
+		};
+	}
+
+	fn validated(self) -> Self {
+		self.geometry().validate();
+		return self;
+	}
+}
+
+impl Layer for Pool2D {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.geometry().input_size();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.output_shape().iter().product();
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		return pool(&self.geometry(), self.mode, inputs).0;
+	}
+
+	fn trace(&self, inputs: Matrix, _rng: &mut dyn RngCore) -> LayerTrace {
+		let (output, sources) = pool(&self.geometry(), self.mode, &inputs);
+		return LayerTrace { input: inputs, output, cache: vec![sources] };
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, _gradient: &mut [Vec<f64>]) -> Matrix {
+		return pool_backward(&self.geometry(), self.mode, trace, grad);
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "Pool2D", into = "Pool2D")]
+pub struct Pool1D {
+	/* A Pool2D over inputs of height 1 with windows of height 1, persisted as that Pool2D */
+	pool: Pool2D,
+}
+
+impl TryFrom<Pool2D> for Pool1D {
+	type Error = String;
+
+	fn try_from(pool: Pool2D) -> Result<Self, String> {
+		if pool.height != 1 || pool.size[0] != 1 {
+			return Err("A 1D pooling layer has inputs and windows of height 1".to_string());
+		}
+		return Ok(Self { pool });
+	}
+}
+
+impl From<Pool1D> for Pool2D {
+	fn from(layer: Pool1D) -> Self {
+		return layer.pool;
+	}
+}
+
+impl Pool1D {
+	pub const NAME: &'static str = "Pool1D";
+
+	pub fn max(channels: usize, length: usize, size: usize) -> Self {
+		/* Non-overlapping windows unless a stride is set with with_stride() */
+		return Self { pool: Pool2D::max(channels, [1, length], [1, size]) };
+	}
+
+	pub fn average(channels: usize, length: usize, size: usize) -> Self {
+		return Self { pool: Pool2D::average(channels, [1, length], [1, size]) };
+	}
+
+	pub fn with_stride(self, stride: usize) -> Self {
+		return Self { pool: self.pool.with_stride([1, stride]) };
+	}
+
+	pub fn output_shape(&self) -> [usize; 2] {
+		let [channels, _, length] = self.pool.output_shape();
+		return [channels, length];
+	}
+
+	pub fn mode(&self) -> PoolMode {
+		return self.pool.mode;
+	}
+
+	pub fn channels(&self) -> usize {
+		return self.pool.channels;
+	}
+
+	pub fn length(&self) -> usize {
+		/* Length of the input */
+		return self.pool.width;
+	}
+
+	pub fn size(&self) -> usize {
+		return self.pool.size[1];
+	}
+
+	pub fn stride(&self) -> usize {
+		return self.pool.stride[1];
+	}
+}
+
+impl Layer for Pool1D {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.pool.input_size();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.pool.output_size();
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		return self.pool.forward(inputs);
+	}
+
+	fn trace(&self, inputs: Matrix, rng: &mut dyn RngCore) -> LayerTrace {
+		return self.pool.trace(inputs, rng);
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		return self.pool.backward(trace, grad, gradient);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_pooling() {
+		let image = Matrix::row_vector(vec![
+			1.0, 2.0, 5.0, 0.0,
+			3.0, 4.0, 1.0, 1.0,
+			0.0, 0.0, 2.0, 2.0,
+			-1.0, 9.0, 2.0, 6.0,
+		]);
+		let max = Pool2D::max(1, [4, 4], [2, 2]);
+		assert_eq!(max.forward(&image).data(), &[4.0, 5.0, 9.0, 6.0]);
+		let average = Pool2D::average(1, [4, 4], [2, 2]);
+		assert_eq!(average.forward(&image).data(), &[2.5, 1.75, 2.0, 3.0]);
+		assert_eq!(Pool2D::max(1, [4, 4], [2, 2]).with_stride([1, 1]).output_shape(), [1, 3, 3]);
+
+		// Max pooling routes the gradient to the selected input only
+		let trace = max.trace(image.clone(), &mut rand::thread_rng());
+		let grad = max.backward(&trace, &Matrix::row_vector(vec![1.0, 2.0, 3.0, 4.0]), &mut []);
+		assert_eq!(grad.data(), &[0.0, 0.0, 2.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 3.0, 0.0, 4.0]);
+
+		let pool = Pool1D::average(2, 6, 3);
+		let sequence = Matrix::row_vector(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 0.0, 0.0, 3.0, 3.0, 3.0, 3.0]);
+		assert_eq!(pool.forward(&sequence).data(), &[2.0, 5.0, 1.0, 3.0]);
+		assert_eq!((pool.mode(), pool.length(), pool.size()), (PoolMode::Average, 6, 3));
+		assert_eq!(pool.with_stride(1).output_shape(), [2, 4]);
+	}
+
+	#[test]
+	fn test_deserialize_validation() {
+		let pool = Pool1D::max(2, 6, 3);
+		let json = serde_json::to_string(&pool).unwrap();
+		assert_eq!(serde_json::from_str::<Pool1D>(&json).unwrap(), pool);
+		let error = serde_json::from_str::<Pool1D>(&json.replace("\"width\":6", "\"width\":2")).unwrap_err();
+		assert!(error.to_string().contains("does not fit an input of 2 values"));
+		let square = serde_json::to_string(&Pool2D::average(1, [4, 4], [2, 2])).unwrap();
+		assert!(serde_json::from_str::<Pool2D>(&square).is_ok());
+		assert!(serde_json::from_str::<Pool1D>(&square).is_err());
+	}
+
+	#[test]
+	#[should_panic(expected = "Strides must be at least 1")]
+	fn test_zero_stride() {
+		Pool2D::max(1, [4, 4], [2, 2]).with_stride([0, 1]);
+	}
+
+	#[test]
+	#[should_panic(expected = "does not fit an input of 3 values")]
+	fn test_oversized_window() {
+		Pool1D::average(2, 3, 4);
+	}
+}
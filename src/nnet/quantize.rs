@@ -0,0 +1,370 @@
+/*
+Fp8 quantized inference.
+A QuantizedNetwork stores every weight matrix of a trained network as Fp8 values, with one
+scale factor per matrix or per row (a dense neuron, a convolution filter or an embedding
+vector). Scales map the largest magnitude onto Fp8::MAX_FINITE so no weight saturates.
+Layers name the matrices to quantize through Layer::weight_matrices(). Weights are persisted
+as hex strings of two characters each, biases and every other parameter stay f64.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error as DeError;
+use serde_json::Value;
+
+use crate::persist::json::JsonPersist;
+use crate::types::float::Fp8;
+use super::error::NetworkError;
+use super::layer::Layer;
+use super::loss::{Loss, LossFunction};
+use super::matrix::Matrix;
+use super::network::NeuralNetwork;
+use super::training::Sample;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Granularity {
+	// One scale per weight matrix
+	PerLayer,
+	// One scale per weight matrix row
+	#[default]
+	PerChannel,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QuantizedTensor {
+	pub rows: usize,
+	pub cols: usize,
+	// A single scale, or one per row
+	pub scales: Vec<f64>,
+	#[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+	pub values: Vec<Fp8>,
+}
+
+impl QuantizedTensor {
+	pub fn quantize(matrix: &Matrix, granularity: Granularity) -> Self {
+		let scale = |values: &[f64]| {
+			let max = values.iter().fold(0.0f64, |m, v| m.max(v.abs()));
+			if max > 0.0 { max / Fp8::MAX_FINITE as f64 } else { 1.0 }
+		};
+		let scales: Vec<f64> = match granularity {
+			Granularity::PerLayer => vec![scale(matrix.data())],
+			Granularity::PerChannel => (0..matrix.rows()).map(|r| scale(matrix.row(r))).collect(),
+		};
+		let values = matrix.data().iter().enumerate()
+			.map(|(i, w)| Fp8::from_f64(w / scales[Self::scale_index(&scales, i, matrix.cols())]))
+			.collect();
+		return Self { rows: matrix.rows(), cols: matrix.cols(), scales, values };
+	}
+
+	pub fn dequantize(&self) -> Matrix {
+		let data = self.values.iter().enumerate()
+			.map(|(i, v)| v.to_f64() * self.scales[Self::scale_index(&self.scales, i, self.cols)])
+			.collect();
+		return Matrix::new(self.rows, self.cols, data);
+	}
+
+	fn scale_index(scales: &[f64], index: usize, cols: usize) -> usize {
+		if scales.len() == 1 {
+			return 0;
+		}
+		return index / cols.max(1);
+	}
+}
+
+fn serialize_hex<S: Serializer>(values: &[Fp8], serializer: S) -> Result<S::Ok, S::Error> {
+	let hex: String = values.iter().map(|v| format!("{:02x}", v.to_bits())).collect();
+	return serializer.serialize_str(&hex);
+}
+
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Fp8>, D::Error> {
+	let hex = String::deserialize(deserializer)?;
+	if hex.len() % 2 != 0 || !hex.is_ascii() {
+		return Err(D::Error::custom("Fp8 values must be pairs of hex digits"));
+	}
+	return (0..hex.len()).step_by(2)
+		.map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map(Fp8::from_bits).map_err(D::Error::custom))
+		.collect();
+}
+
+#[derive(Clone, Debug)]
+struct QuantizedLayer {
+	// The layer with empty weight matrices, only cloned to be filled in
+	skeleton: Box<dyn Layer>,
+	// Weight matrices by field name, see Layer::weight_matrices()
+	tensors: BTreeMap<String, QuantizedTensor>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QuantizedLayerRecord {
+	// The persisted layer without its weight matrices
+	layer: Value,
+	tensors: BTreeMap<String, QuantizedTensor>,
+}
+
+impl QuantizedLayer {
+	fn new(layer: &dyn Layer, granularity: Granularity) -> Self {
+		let tensors = layer.weight_matrices().into_iter()
+			.map(|(name, matrix)| (name.to_string(), QuantizedTensor::quantize(matrix, granularity)))
+			.collect();
+		return Self { skeleton: Self::strip(layer.clone_box()), tensors };
+	}
+
+	fn strip(mut layer: Box<dyn Layer>) -> Box<dyn Layer> {
+		for (_, matrix) in layer.weight_matrices_mut() {
+			*matrix = Matrix::zeros(0, 0);
+		}
+		return layer;
+	}
+
+	fn dequantize(&self) -> Box<dyn Layer> {
+		/* A full precision copy of the layer */
+		let mut layer = self.skeleton.clone();
+		for (name, matrix) in layer.weight_matrices_mut() {
+			if let Some(tensor) = self.tensors.get(name) {
+				*matrix = tensor.dequantize();
+			}
+		}
+		return layer;
+	}
+}
+
+impl TryFrom<QuantizedLayerRecord> for QuantizedLayer {
+	type Error = serde_json::Error;
+
+	fn try_from(record: QuantizedLayerRecord) -> serde_json::Result<Self> {
+		/* Loads the layer with its dequantized weights, so that their shapes are validated */
+		let mut value = record.layer;
+		if let Some(fields) = value.as_object_mut() {
+			for (name, tensor) in &record.tensors {
+				fields.insert(name.clone(), serde_json::to_value(tensor.dequantize())?);
+			}
+		}
+		let layer: Box<dyn Layer> = serde_json::from_value(value)?;
+		return Ok(Self { skeleton: Self::strip(layer), tensors: record.tensors });
+	}
+}
+
+impl From<&QuantizedLayer> for QuantizedLayerRecord {
+	fn from(quantized: &QuantizedLayer) -> Self {
+		let mut layer = serde_json::to_value(quantized.skeleton.as_ref()).expect("Layers serialize to JSON");
+		if let Some(fields) = layer.as_object_mut() {
+			for name in quantized.tensors.keys() {
+				fields.remove(name);
+			}
+		}
+		return Self { layer, tensors: quantized.tensors.clone() };
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct QuantizedRecord {
+	granularity: Granularity,
+	#[serde(default)]
+	loss: LossFunction,
+	layers: Vec<QuantizedLayerRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "QuantizedRecord", into = "QuantizedRecord")]
+pub struct QuantizedNetwork {
+	/* Weights stay Fp8 in memory as well. Inference dequantizes one layer at a time, so at most
+	one layer's weights are held as f64 at once. */
+	pub granularity: Granularity,
+	loss: LossFunction,
+	layers: Vec<QuantizedLayer>,
+}
+
+impl JsonPersist for QuantizedNetwork {}
+
+impl TryFrom<QuantizedRecord> for QuantizedNetwork {
+	type Error = String;
+
+	fn try_from(record: QuantizedRecord) -> Result<Self, String> {
+		let mut layers: Vec<QuantizedLayer> = Vec::with_capacity(record.layers.len());
+		let mut previous_output = None;
+		for (idx, layer) in record.layers.into_iter().enumerate() {
+			let layer = QuantizedLayer::try_from(layer).map_err(|e| e.to_string())?;
+			let full = layer.dequantize();
+			if let Some(expected) = previous_output.filter(|e| full.output_size_for(*e).is_none()) {
+				return Err(NetworkError::LayerSize { layer: idx, expected, found: full.input_size() }.to_string());
+			}
+			previous_output = Some(full.output_size());
+			layers.push(layer);
+		}
+		return Ok(Self { granularity: record.granularity, loss: record.loss, layers });
+	}
+}
+
+impl From<QuantizedNetwork> for QuantizedRecord {
+	fn from(quantized: QuantizedNetwork) -> Self {
+		let layers = quantized.layers.iter().map(QuantizedLayerRecord::from).collect();
+		return Self { granularity: quantized.granularity, loss: quantized.loss, layers };
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuantizationReport {
+	/* How a quantized network compares with the network it was made from on validation samples.
+	Accuracy counts matching argmax outputs, or outputs on the same side of 0.5 for single outputs. */
+	pub loss: f64,
+	pub quantized_loss: f64,
+	pub accuracy: f64,
+	pub quantized_accuracy: f64,
+	// Fraction of samples for which both networks predict the same class
+	pub agreement: f64,
+	pub max_output_error: f64,
+	pub mean_output_error: f64,
+}
+
+impl QuantizationReport {
+	pub fn accuracy_drop(&self) -> f64 {
+		return self.accuracy - self.quantized_accuracy;
+	}
+}
+
+impl QuantizedNetwork {
+	pub fn new(network: &NeuralNetwork, granularity: Granularity) -> Self {
+		let layers = network.layers.iter().map(|l| QuantizedLayer::new(l.as_ref(), granularity)).collect();
+		return Self { granularity, loss: network.loss, layers };
+	}
+
+	pub fn dequantize(&self) -> NeuralNetwork {
+		/* A full precision network with the quantized weights */
+		let layers = self.layers.iter().map(|l| l.dequantize()).collect();
+		return NeuralNetwork::from_layers(layers).expect("Quantized layers keep the shapes of the network").with_loss(self.loss);
+	}
+
+	pub fn predict(&self, inputs: Vec<f64>) -> Result<Vec<f64>, NetworkError> {
+		return Ok(self.predict_batch(&Matrix::row_vector(inputs))?.into_data());
+	}
+
+	pub fn predict_batch(&self, inputs: &Matrix) -> Result<Matrix, NetworkError> {
+		/* Predicts every row of inputs, dequantizing the layers one after the other */
+		if self.layers.is_empty() {
+			return Err(NetworkError::EmptyNetwork);
+		}
+		let mut values = inputs.clone();
+		let mut expected = 0;
+		for (idx, quantized) in self.layers.iter().enumerate() {
+			let layer = quantized.dequantize();
+			if idx == 0 {
+				expected = layer.input_size();
+			}
+			if layer.output_size_for(values.cols()).is_none() {
+				return Err(NetworkError::InputSize { expected, found: inputs.cols() });
+			}
+			if idx == 0 {
+				for r in 0..values.rows() {
+					layer.check_input(values.row(r))?;
+				}
+			}
+			values = layer.forward(&values);
+		}
+		return Ok(values);
+	}
+
+	pub fn compare(&self, reference: &NeuralNetwork, validation: &[Sample]) -> Result<QuantizationReport, NetworkError> {
+		/* Measures the degradation from reference to this network on validation samples */
+		for (input, target) in validation {
+			reference.check_sample(input, target)?;
+		}
+		let inputs: Vec<Vec<f64>> = validation.iter().map(|(i, _)| i.clone()).collect();
+		let expected = reference.predict_batch(&Matrix::from_rows(&inputs))?;
+		let quantized = self.predict_batch(&Matrix::from_rows(&inputs))?;
+		let n = validation.len().max(1) as f64;
+		let mut report = QuantizationReport {
+			loss: 0.0, quantized_loss: 0.0, accuracy: 0.0, quantized_accuracy: 0.0,
+			agreement: 0.0, max_output_error: 0.0, mean_output_error: 0.0,
+		};
+		for (r, (_, target)) in validation.iter().enumerate() {
+			let (e, q) = (expected.row(r), quantized.row(r));
+			report.loss += reference.loss.value(e, target) / n;
+			report.quantized_loss += reference.loss.value(q, target) / n;
+			let class = predicted_class(target);
+			report.accuracy += (predicted_class(e) == class) as usize as f64 / n;
+			report.quantized_accuracy += (predicted_class(q) == class) as usize as f64 / n;
+			report.agreement += (predicted_class(e) == predicted_class(q)) as usize as f64 / n;
+			for (a, b) in e.iter().zip(q) {
+				report.max_output_error = report.max_output_error.max((a - b).abs());
+				report.mean_output_error += (a - b).abs() / (n * e.len().max(1) as f64);
+			}
+		}
+		return Ok(report);
+	}
+}
+
+fn predicted_class(output: &[f64]) -> usize {
+	if output.len() == 1 {
+		return (output[0] > 0.5) as usize;
+	}
+	return output.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).map_or(0, |(i, _)| i);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_tensor_quantization() {
+		let matrix = Matrix::from_rows(&[vec![1.0, -0.5, 0.25], vec![0.001, 0.002, -0.004]]);
+		let per_channel = QuantizedTensor::quantize(&matrix, Granularity::PerChannel);
+		assert_eq!(per_channel.scales.len(), 2);
+		let per_layer = QuantizedTensor::quantize(&matrix, Granularity::PerLayer);
+		assert_eq!(per_layer.scales.len(), 1);
+
+		// Row maxima are exact, small rows keep their precision with their own scale
+		let restored = per_channel.dequantize();
+		assert!((restored[(0, 0)] - 1.0).abs() < 1e-12);
+		assert!((restored[(1, 2)] + 0.004).abs() < 1e-12);
+		for (a, b) in matrix.data().iter().zip(restored.data()) {
+			assert!((a - b).abs() <= a.abs() / 16.0);
+		}
+		let coarse = per_layer.dequantize();
+		let error = |m: &Matrix| (0..3).map(|c| (m[(1, c)] - matrix[(1, c)]).abs()).sum::<f64>();
+		assert!(error(&restored) <= error(&coarse));
+
+		let json = serde_json::to_string(&per_channel).unwrap();
+		assert!(json.contains(&format!("\"{:02x}", per_channel.values[0].to_bits())));
+		assert_eq!(serde_json::from_str::<QuantizedTensor>(&json).unwrap(), per_channel);
+		assert!(serde_json::from_str::<QuantizedTensor>(r#"{"rows":1,"cols":1,"scales":[1.0],"values":"f"}"#).is_err());
+	}
+
+	#[test]
+	fn test_layer_weights() {
+		use rand::SeedableRng;
+		use rand::rngs::StdRng;
+		use crate::nnet::activation::Activation;
+		use crate::nnet::conv::Conv1D;
+		use crate::nnet::dense::Dense;
+		use crate::nnet::embedding::Embedding;
+		use crate::nnet::initializer::Initializer;
+
+		let mut rng = StdRng::seed_from_u64(3);
+		let layers: Vec<Box<dyn Layer>> = vec![
+			Box::new(Embedding::new(10, 2, 4, &mut rng)),
+			Box::new(Conv1D::new(1, 2, 8, 3, Activation::Tanh, Initializer::XavierUniform, &mut rng)),
+			Box::new(Dense::new(1, 12, Activation::Sigmoid, Initializer::XavierUniform, &mut rng)),
+		];
+		let network = NeuralNetwork::from_layers(layers).unwrap();
+		let quantized = QuantizedNetwork::new(&network, Granularity::PerChannel);
+		let names: Vec<Vec<&String>> = quantized.layers.iter().map(|l| l.tensors.keys().collect()).collect();
+		assert_eq!(names, vec![vec!["vectors"], vec!["weights"], vec!["weights"]]);
+		// Only the weight matrices are quantized, the biases keep their value
+		let dequantized = quantized.dequantize();
+		assert_eq!(dequantized.layers[2].parameters()[1], network.layers[2].parameters()[1]);
+		assert_ne!(dequantized.layers[2].parameters()[0], network.layers[2].parameters()[0]);
+
+		let input = vec![1.0, 4.0, 9.0, 0.0];
+		let output = quantized.predict(input.clone()).unwrap();
+		assert_eq!(output, dequantized.predict(input.clone()).unwrap());
+		assert!((output[0] - network.predict(input.clone()).unwrap()[0]).abs() < 0.05);
+		assert_eq!(quantized.predict(vec![1.0, 4.0, 10.0, 0.0]), Err(NetworkError::InvalidToken { value: 10.0, vocabulary: 10 }));
+
+		let json = serde_json::to_string(&quantized).unwrap();
+		let loaded: QuantizedNetwork = serde_json::from_str(&json).unwrap();
+		assert_eq!(loaded.predict(input.clone()).unwrap(), output);
+		// Dequantized weights are validated against the layer they are loaded into
+		assert!(serde_json::from_str::<QuantizedNetwork>(&json.replace("\"rows\":2,\"cols\":3", "\"rows\":1,\"cols\":6")).is_err());
+	}
+}
@@ -0,0 +1,751 @@
+/*
+Recurrent layers over sequences of vectors.
+A sample holds the vectors of its steps laid end to end, the same layout Embedding outputs,
+so Recurrent and TimeDistributed layers stack with any other layer of a NeuralNetwork and
+train through its optimizers, schedules, callbacks and threads. Every step of a batch is
+recorded on an autodiff Graph, so backpropagation through time is the graph's backward pass.
+With a truncation window the hidden state carries over from one window to the next but
+gradients stop at the boundary. RecurrentNetwork wraps such a network to take sequences
+of vectors, possibly of different lengths, and to generate text from a language model.
+*/
+
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::persist::json::JsonPersist;
+use super::activation::Activation;
+use super::autodiff::{Graph, Var};
+use super::callback::Callback;
+use super::dense::Dense;
+use super::error::NetworkError;
+use super::initializer::Initializer;
+use super::layer::Layer;
+use super::loss::LossFunction;
+use super::matrix::Matrix;
+use super::network::NeuralNetwork;
+use super::optimizer::OptimizerKind;
+use super::schedule::Schedule;
+use super::tape::LayerTrace;
+use super::time_distributed::TimeDistributed;
+use super::training::{Dataset, History, Sample};
+use super::vocabulary::Vocabulary;
+
+// Inputs of every step, and the targets of every step or of the last step only
+pub type Sequence = (Vec<Vec<f64>>, Vec<Vec<f64>>);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Cell {
+	// h' = tanh(x·Wᵀ + h·Uᵀ + b)
+	Elman,
+	// Input, forget, candidate and output gates over a separate cell state
+	Lstm,
+	// Update, reset and candidate gates
+	Gru,
+}
+
+impl Cell {
+	fn gates(&self) -> usize {
+		return match self {
+			Cell::Elman => 1,
+			Cell::Lstm => 4,
+			Cell::Gru => 3,
+		};
+	}
+
+	fn states(&self) -> usize {
+		return match self {
+			Cell::Lstm => 2,
+			_ => 1,
+		};
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum SequenceMode {
+	// Outputs the hidden state of the last step only
+	SequenceToOne,
+	// Outputs the hidden state of every step
+	#[default]
+	SequenceToSequence,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "RecurrentRecord")]
+pub struct Recurrent {
+	/* Runs a cell over samples of any number of steps of input_size() values each */
+	cell: Cell,
+	// One row per unit of every gate, the gates stacked in the order the cell applies them:
+	// i, f, g, o for LSTM and z, r, n for GRU
+	input_weights: Matrix,
+	hidden_weights: Matrix,
+	biases: Vec<f64>,
+	#[serde(default)]
+	pub mode: SequenceMode,
+	// Steps backpropagated through before the gradient is cut, the whole sequence if None
+	#[serde(default)]
+	pub truncation: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct RecurrentRecord {
+	cell: Cell,
+	input_weights: Matrix,
+	hidden_weights: Matrix,
+	biases: Vec<f64>,
+	#[serde(default)]
+	mode: SequenceMode,
+	#[serde(default)]
+	truncation: Option<usize>,
+}
+
+impl TryFrom<RecurrentRecord> for Recurrent {
+	type Error = String;
+
+	fn try_from(record: RecurrentRecord) -> Result<Self, String> {
+		let units = record.cell.gates() * record.hidden_weights.cols();
+		let (input, hidden) = (&record.input_weights, &record.hidden_weights);
+		if units == 0 || input.cols() == 0 {
+			return Err(format!("A {:?} layer needs inputs and hidden units, got {}x{} input weights", record.cell, input.rows(), input.cols()));
+		}
+		if input.rows() != units {
+			return Err(format!("Expected {}x{} input weights, got {}x{}", units, input.cols(), input.rows(), input.cols()));
+		}
+		if hidden.rows() != units {
+			return Err(format!("Expected {}x{} hidden weights, got {}x{}", units, hidden.cols(), hidden.rows(), hidden.cols()));
+		}
+		if record.biases.len() != units {
+			return Err(format!("Expected {} biases, got {}", units, record.biases.len()));
+		}
+		if record.truncation == Some(0) {
+			return Err("Truncation must be at least one step".to_string());
+		}
+		return Ok(Self {
+			cell: record.cell,
+			input_weights: record.input_weights,
+			hidden_weights: record.hidden_weights,
+			biases: record.biases,
+			mode: record.mode,
+			truncation: record.truncation,
+		});
+	}
+}
+
+struct Unrolled {
+	// Parameter variables of every gate: input weights, hidden weights and biases
+	parameters: Vec<Var>,
+	// Input and hidden state of every step
+	inputs: Vec<Var>,
+	hidden: Vec<Var>,
+}
+
+impl Recurrent {
+	pub const NAME: &'static str = "Recurrent";
+	const FORGET_GATE: usize = 1;
+
+	pub fn new(cell: Cell, input_size: usize, hidden_size: usize, rng: &mut impl Rng) -> Self {
+		/* Sequence-to-sequence without truncation, see with_mode() and with_truncation() */
+		let initializer = Initializer::XavierUniform;
+		let units = cell.gates() * hidden_size;
+		let input_weights = (0..units * input_size).map(|_| initializer.weight(input_size, hidden_size, rng)).collect();
+		let hidden_weights = (0..units * hidden_size).map(|_| initializer.weight(hidden_size, hidden_size, rng)).collect();
+		let mut biases = vec![0.0; units];
+		if cell == Cell::Lstm {
+			// An open forget gate lets gradients flow through the cell state early in training
+			let forget = Self::FORGET_GATE * hidden_size;
+			biases[forget..forget + hidden_size].fill(1.0);
+		}
+		return Self {
+			cell,
+			input_weights: Matrix::new(units, input_size, input_weights),
+			hidden_weights: Matrix::new(units, hidden_size, hidden_weights),
+			biases,
+			mode: SequenceMode::default(),
+			truncation: None,
+		};
+	}
+
+	pub fn with_mode(mut self, mode: SequenceMode) -> Self {
+		self.mode = mode;
+		return self;
+	}
+
+	pub fn with_truncation(mut self, steps: usize) -> Self {
+		/* Backpropagates through at most steps steps, see the module documentation */
+		self.truncation = Some(steps.max(1));
+		return self;
+	}
+
+	pub fn cell(&self) -> Cell {
+		return self.cell;
+	}
+
+	pub fn hidden_size(&self) -> usize {
+		return self.hidden_weights.cols();
+	}
+
+	pub fn input_weights(&self) -> &Matrix {
+		return &self.input_weights;
+	}
+
+	pub fn hidden_weights(&self) -> &Matrix {
+		return &self.hidden_weights;
+	}
+
+	pub fn biases(&self) -> &[f64] {
+		return &self.biases;
+	}
+
+	fn leaves(&self, graph: &mut Graph) -> Vec<Var> {
+		/* Input weights, hidden weights and biases of every gate */
+		let (input_size, hidden_size) = (self.input_size(), self.hidden_size());
+		let (w, u) = (hidden_size * input_size, hidden_size * hidden_size);
+		return (0..self.cell.gates()).flat_map(|g| [
+			graph.leaf(Matrix::new(hidden_size, input_size, self.input_weights.data()[g * w..(g + 1) * w].to_vec())),
+			graph.leaf(Matrix::new(hidden_size, hidden_size, self.hidden_weights.data()[g * u..(g + 1) * u].to_vec())),
+			graph.leaf(Matrix::row_vector(self.biases[g * hidden_size..(g + 1) * hidden_size].to_vec())),
+		]).collect();
+	}
+
+	fn unroll(&self, graph: &mut Graph, inputs: &Matrix) -> Unrolled {
+		/* Records every step of a batch from a zero state. At truncation boundaries the state
+		carries over as a new leaf, which stops its gradient. */
+		let size = self.input_size();
+		let parameters = self.leaves(graph);
+		let mut state: Vec<Var> = (0..self.cell.states()).map(|_| graph.leaf(Matrix::zeros(inputs.rows(), self.hidden_size()))).collect();
+		let (mut steps, mut hidden) = (Vec::new(), Vec::new());
+		for t in 0..inputs.cols() / size {
+			if t > 0 && self.truncation.is_some_and(|k| t.is_multiple_of(k)) {
+				state = state.iter().map(|s| {
+					let value = graph.value(*s).clone();
+					graph.leaf(value)
+				}).collect();
+			}
+			let x = graph.leaf(inputs.map_rows(|row| row[t * size..(t + 1) * size].to_vec()));
+			state = self.step(graph, &parameters, x, &state);
+			steps.push(x);
+			hidden.push(state[0]);
+		}
+		return Unrolled { parameters, inputs: steps, hidden };
+	}
+
+	fn step(&self, graph: &mut Graph, parameters: &[Var], input: Var, state: &[Var]) -> Vec<Var> {
+		/* Records one step of the cell, returns the next state */
+		let hidden = state[0];
+		match self.cell {
+			Cell::Elman => {
+				let z = gate_input(graph, parameters, 0, input, hidden);
+				return vec![graph.activation(z, Activation::Tanh)];
+			},
+			Cell::Lstm => {
+				let gates: Vec<Var> = (0..4).map(|g| {
+					let z = gate_input(graph, parameters, g, input, hidden);
+					let activation = if g == 2 { Activation::Tanh } else { Activation::Sigmoid };
+					graph.activation(z, activation)
+				}).collect();
+				let kept = graph.mul(gates[1], state[1]);
+				let written = graph.mul(gates[0], gates[2]);
+				let cell = graph.add(kept, written);
+				let squashed = graph.activation(cell, Activation::Tanh);
+				return vec![graph.mul(gates[3], squashed), cell];
+			},
+			Cell::Gru => {
+				let z = gate_input(graph, parameters, 0, input, hidden);
+				let update = graph.activation(z, Activation::Sigmoid);
+				let z = gate_input(graph, parameters, 1, input, hidden);
+				let reset = graph.activation(z, Activation::Sigmoid);
+				let reset_hidden = graph.mul(reset, hidden);
+				let z = gate_input(graph, parameters, 2, input, reset_hidden);
+				let candidate = graph.activation(z, Activation::Tanh);
+				// h' = n + z ⊙ (h - n)
+				let difference = graph.sub(hidden, candidate);
+				let kept = graph.mul(update, difference);
+				return vec![graph.add(candidate, kept)];
+			},
+		}
+	}
+}
+
+fn gate_input(graph: &mut Graph, parameters: &[Var], gate: usize, input: Var, hidden: Var) -> Var {
+	/* x·Wᵀ + h·Uᵀ + b of a gate */
+	let (w, u, b) = (parameters[3 * gate], parameters[3 * gate + 1], parameters[3 * gate + 2]);
+	let from_input = graph.matmul_transposed(input, w);
+	let from_hidden = graph.matmul_transposed(hidden, u);
+	let sum = graph.add(from_input, from_hidden);
+	return graph.add_row(sum, b);
+}
+
+impl Layer for Recurrent {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		/* Values of one step */
+		return self.input_weights.cols();
+	}
+
+	fn output_size(&self) -> usize {
+		/* Values of one step, the hidden size */
+		return self.hidden_size();
+	}
+
+	fn output_size_for(&self, input_size: usize) -> Option<usize> {
+		if input_size == 0 || !input_size.is_multiple_of(self.input_size()) {
+			return None;
+		}
+		return match self.mode {
+			SequenceMode::SequenceToOne => Some(self.hidden_size()),
+			SequenceMode::SequenceToSequence => Some(input_size / self.input_size() * self.hidden_size()),
+		};
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		let mut graph = Graph::new();
+		let unrolled = self.unroll(&mut graph, inputs);
+		let last = *unrolled.hidden.last().expect("Sequences have at least one step");
+		return match self.mode {
+			SequenceMode::SequenceToOne => graph.value(last).clone(),
+			SequenceMode::SequenceToSequence => {
+				let rows: Vec<Vec<f64>> = (0..inputs.rows())
+					.map(|r| unrolled.hidden.iter().flat_map(|h| graph.value(*h).row(r).to_vec()).collect())
+					.collect();
+				Matrix::from_rows(&rows)
+			},
+		};
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		/* Unrolls the traced batch again and seeds the hidden state of every output step with its gradient */
+		let mut graph = Graph::new();
+		let unrolled = self.unroll(&mut graph, &trace.input);
+		let hidden_size = self.hidden_size();
+		let seeds = match self.mode {
+			SequenceMode::SequenceToOne => vec![(*unrolled.hidden.last().expect("Sequences have at least one step"), grad.clone())],
+			SequenceMode::SequenceToSequence => unrolled.hidden.iter().enumerate()
+				.map(|(t, h)| (*h, grad.map_rows(|row| row[t * hidden_size..(t + 1) * hidden_size].to_vec())))
+				.collect(),
+		};
+		let grads = graph.backward_seeded(seeds);
+		// Gates are stacked, so the gradient of every gate goes to its own slice of the tensor
+		for (gate, vars) in unrolled.parameters.chunks(3).enumerate() {
+			for (tensor, var) in vars.iter().enumerate() {
+				if let Some(g) = grads.get(*var) {
+					let n = g.data().len();
+					for (t, d) in gradient[tensor][gate * n..(gate + 1) * n].iter_mut().zip(g.data()) {
+						*t += d;
+					}
+				}
+			}
+		}
+		let size = self.input_size();
+		let rows: Vec<Vec<f64>> = (0..trace.input.rows()).map(|r| unrolled.inputs.iter().flat_map(|x| match grads.get(*x) {
+			Some(g) => g.row(r).to_vec(),
+			None => vec![0.0; size],
+		}).collect()).collect();
+		return Matrix::from_rows(&rows);
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		return vec![self.input_weights.data(), self.hidden_weights.data(), &self.biases];
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return vec![self.input_weights.data_mut(), self.hidden_weights.data_mut(), &mut self.biases];
+	}
+
+	fn weight_matrices(&self) -> Vec<(&'static str, &Matrix)> {
+		return vec![("input_weights", &self.input_weights), ("hidden_weights", &self.hidden_weights)];
+	}
+
+	fn weight_matrices_mut(&mut self) -> Vec<(&'static str, &mut Matrix)> {
+		return vec![("input_weights", &mut self.input_weights), ("hidden_weights", &mut self.hidden_weights)];
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "RecurrentNetworkRecord")]
+pub struct RecurrentNetwork {
+	/* Recurrent layers under a dense output head applied to every output step. Sequences are
+	laid end to end into the samples of the wrapped network, which does all the training. */
+	network: NeuralNetwork,
+}
+
+#[derive(Deserialize)]
+struct RecurrentNetworkRecord {
+	network: NeuralNetwork,
+}
+
+impl TryFrom<RecurrentNetworkRecord> for RecurrentNetwork {
+	type Error = String;
+
+	fn try_from(record: RecurrentNetworkRecord) -> Result<Self, String> {
+		let layers = &record.network.layers;
+		let head = layers.last().is_some_and(|l| l.downcast_ref::<TimeDistributed>().is_some());
+		if !head || layers[..layers.len() - 1].iter().any(|l| l.downcast_ref::<Recurrent>().is_none()) {
+			return Err("A recurrent network needs Recurrent layers under a TimeDistributed head".to_string());
+		}
+		return Ok(Self { network: record.network });
+	}
+}
+
+impl JsonPersist for RecurrentNetwork {}
+
+impl RecurrentNetwork {
+	pub fn new(cell: Cell, sizes: &[usize], rng: &mut impl Rng) -> Self {
+		/* sizes holds the input size, the hidden size of every recurrent layer and the output size.
+		The output head is linear and trained on mean squared error by default. */
+		assert!(sizes.len() >= 3, "A recurrent network needs an input size, at least one hidden size and an output size");
+		let hidden = &sizes[..sizes.len() - 1];
+		let mut layers: Vec<Box<dyn Layer>> = hidden.windows(2)
+			.map(|w| Box::new(Recurrent::new(cell, w[0], w[1], rng)) as Box<dyn Layer>)
+			.collect();
+		let output = Dense::new(sizes[sizes.len() - 1], hidden[hidden.len() - 1], Activation::Identity, Initializer::XavierUniform, rng);
+		layers.push(Box::new(TimeDistributed::new(output)));
+		let network = NeuralNetwork::from_layers(layers).expect("Consecutive sizes fit together").with_loss(LossFunction::MeanSquaredError);
+		return Self { network };
+	}
+
+	pub fn language_model(cell: Cell, vocabulary_size: usize, hidden_sizes: &[usize], rng: &mut impl Rng) -> Self {
+		/* Predicts a distribution over the next token at every step, with a softmax output
+		trained on categorical cross-entropy */
+		let sizes: Vec<usize> = [vocabulary_size].iter().chain(hidden_sizes).chain([vocabulary_size].iter()).copied().collect();
+		return Self::new(cell, &sizes, rng)
+			.with_output_activation(Activation::Softmax)
+			.with_loss(LossFunction::CategoricalCrossEntropy);
+	}
+
+	pub fn with_mode(mut self, mode: SequenceMode) -> Self {
+		/* Whether the head sees every step of the last recurrent layer or only its last one */
+		if let Some(layer) = self.recurrent_layers().pop() {
+			layer.mode = mode;
+		}
+		return self;
+	}
+
+	pub fn with_truncation(mut self, steps: usize) -> Self {
+		/* Backpropagates through at most steps steps of every recurrent layer */
+		for layer in self.recurrent_layers() {
+			layer.truncation = Some(steps.max(1));
+		}
+		return self;
+	}
+
+	pub fn with_output_activation(mut self, activation: Activation) -> Self {
+		let head = self.network.layers.last_mut().and_then(|l| l.downcast_mut::<TimeDistributed>());
+		if let Some(dense) = head.and_then(|h| h.layer.downcast_mut::<Dense>()) {
+			dense.activation = activation;
+		}
+		return self;
+	}
+
+	pub fn with_loss(mut self, loss: LossFunction) -> Self {
+		self.network = self.network.with_loss(loss);
+		return self;
+	}
+
+	pub fn with_learning_rate(mut self, learning_rate: f64) -> Self {
+		self.network = self.network.with_learning_rate(learning_rate);
+		return self;
+	}
+
+	pub fn with_optimizer(mut self, optimizer: impl Into<OptimizerKind>) -> Self {
+		self.network = self.network.with_optimizer(optimizer);
+		return self;
+	}
+
+	pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+		self.network = self.network.with_schedule(schedule);
+		return self;
+	}
+
+	pub fn with_threads(mut self, threads: usize) -> Self {
+		self.network = self.network.with_threads(threads);
+		return self;
+	}
+
+	pub fn with_clip_norm(mut self, max_norm: f64) -> Self {
+		self.network = self.network.with_clip_norm(max_norm);
+		return self;
+	}
+
+	pub fn network(&self) -> &NeuralNetwork {
+		/* The recurrent layers and the head as one network over sequences laid end to end */
+		return &self.network;
+	}
+
+	pub fn mode(&self) -> SequenceMode {
+		return self.network.layers.iter().rev()
+			.find_map(|l| l.downcast_ref::<Recurrent>())
+			.map_or(SequenceMode::default(), |l| l.mode);
+	}
+
+	pub fn input_size(&self) -> usize {
+		return self.network.input_size();
+	}
+
+	pub fn output_size(&self) -> usize {
+		return self.network.output_size();
+	}
+
+	pub fn parameter_count(&self) -> usize {
+		return self.network.summary().total_parameters;
+	}
+
+	pub fn samples(&self, sequences: &[Sequence]) -> Result<Vec<Sample>, NetworkError> {
+		/* The samples of the wrapped network, one per sequence */
+		return sequences.iter().map(|(inputs, targets)| {
+			self.check_sequence(inputs, targets)?;
+			Ok((inputs.concat(), targets.concat()))
+		}).collect();
+	}
+
+	pub fn dataset(&self, sequences: &[Sequence]) -> Result<Dataset, NetworkError> {
+		/* Training sequences for fit(). Sequences of equal length next to each other share a
+		forward pass, so a dataset sorted by length trains fastest without shuffling. */
+		return Ok(Dataset::new(self.samples(sequences)?));
+	}
+
+	pub fn fit(&mut self, dataset: &Dataset, epochs: usize, batch_size: usize, shuffle: bool) -> Result<History, NetworkError> {
+		/* See NeuralNetwork::fit(), build the dataset with dataset() */
+		return self.network.fit(dataset, epochs, batch_size, shuffle);
+	}
+
+	pub fn fit_with_callbacks(&mut self, dataset: &Dataset, epochs: usize, batch_size: usize, shuffle: bool, callbacks: &mut [&mut dyn Callback]) -> Result<History, NetworkError> {
+		return self.network.fit_with_callbacks(dataset, epochs, batch_size, shuffle, callbacks);
+	}
+
+	pub fn evaluate(&self, sequences: &[Sequence]) -> Result<f64, NetworkError> {
+		/* Mean loss over the sequences, without training */
+		return self.network.evaluate(&self.samples(sequences)?);
+	}
+
+	pub fn predict_sequence(&self, inputs: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, NetworkError> {
+		/* Output of every step, or of the last one only for a sequence-to-one model */
+		self.check_inputs(inputs)?;
+		let output = self.network.predict(inputs.concat())?;
+		return Ok(output.chunks(self.output_size()).map(|o| o.to_vec()).collect());
+	}
+
+	pub fn predict(&self, inputs: &[Vec<f64>]) -> Result<Vec<f64>, NetworkError> {
+		/* Output of the last step */
+		let mut outputs = self.predict_sequence(inputs)?;
+		return Ok(outputs.pop().expect("Sequences are not empty"));
+	}
+
+	pub fn generate(&self, vocabulary: &Vocabulary, prompt: &str, length: usize) -> Result<String, NetworkError> {
+		/* Extends the prompt by length chars of a language model, feeding back the most likely
+		char at every step. Chars of the prompt outside the vocabulary are skipped. */
+		if vocabulary.len() != self.output_size() {
+			return Err(NetworkError::TargetSize { expected: self.output_size(), found: vocabulary.len() });
+		}
+		let mut inputs = vocabulary.encode(prompt);
+		let mut text = prompt.to_string();
+		for _ in 0..length {
+			let next = vocabulary.decode(&self.predict(&inputs)?).expect("The vocabulary is not empty");
+			text.push(next);
+			inputs.push(vocabulary.one_hot(next).expect("Decoded chars are in the vocabulary"));
+		}
+		return Ok(text);
+	}
+
+	fn recurrent_layers(&mut self) -> Vec<&mut Recurrent> {
+		return self.network.layers.iter_mut().filter_map(|l| l.downcast_mut::<Recurrent>()).collect();
+	}
+
+	fn check_inputs(&self, inputs: &[Vec<f64>]) -> Result<(), NetworkError> {
+		if inputs.is_empty() {
+			return Err(NetworkError::EmptySequence);
+		}
+		for input in inputs {
+			if input.len() != self.input_size() {
+				return Err(NetworkError::InputSize { expected: self.input_size(), found: input.len() });
+			}
+		}
+		return Ok(());
+	}
+
+	fn check_sequence(&self, inputs: &[Vec<f64>], targets: &[Vec<f64>]) -> Result<(), NetworkError> {
+		self.check_inputs(inputs)?;
+		let expected = match self.mode() {
+			SequenceMode::SequenceToOne => 1,
+			SequenceMode::SequenceToSequence => inputs.len(),
+		};
+		if targets.len() != expected {
+			return Err(NetworkError::TargetCount { expected, found: targets.len() });
+		}
+		for target in targets {
+			if target.len() != self.output_size() {
+				return Err(NetworkError::TargetSize { expected: self.output_size(), found: target.len() });
+			}
+		}
+		return Ok(());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use crate::nnet::embedding::Embedding;
+	use crate::nnet::gradient_check::gradient_check_batch;
+	use crate::nnet::optimizer::Adam;
+
+	const CELLS: [Cell; 3] = [Cell::Elman, Cell::Lstm, Cell::Gru];
+
+	#[test]
+	fn test_backpropagation_through_time() {
+		// Sequences of 4, 4 and 3 steps, the last one in a pass of its own
+		let sequence = |offset: f64, steps: usize| -> Sequence {
+			let inputs = (0..steps).map(|t| vec![(t as f64 + offset).sin(), (t as f64 * offset).cos()]).collect();
+			let targets = (0..steps).map(|t| vec![0.1 * t as f64 - offset, offset]).collect();
+			(inputs, targets)
+		};
+		let sequences = vec![sequence(0.5, 4), sequence(-0.3, 4), sequence(0.8, 3)];
+		for cell in CELLS {
+			for mode in [SequenceMode::SequenceToSequence, SequenceMode::SequenceToOne] {
+				let mut rng = StdRng::seed_from_u64(1);
+				let network = RecurrentNetwork::new(cell, &[2, 3, 3, 2], &mut rng).with_mode(mode);
+				let sequences: Vec<Sequence> = sequences.iter().map(|(inputs, targets)| match mode {
+					SequenceMode::SequenceToOne => (inputs.clone(), targets[..1].to_vec()),
+					SequenceMode::SequenceToSequence => (inputs.clone(), targets.clone()),
+				}).collect();
+				let check = gradient_check_batch(network.network(), &network.samples(&sequences).unwrap(), 1e-5).unwrap();
+				assert_eq!(check.parameters.len(), network.parameter_count());
+				assert!(check.max_relative_error < 1e-5, "{:?} {:?} {:?}", cell, mode, check.worst());
+			}
+		}
+	}
+
+	#[test]
+	fn test_truncation() {
+		let mut rng = StdRng::seed_from_u64(2);
+		let layer = Recurrent::new(Cell::Lstm, 1, 4, &mut rng).with_mode(SequenceMode::SequenceToOne);
+		let truncated = layer.clone().with_truncation(3);
+		let inputs = Matrix::row_vector((0..6).map(|t| t as f64 / 6.0).collect());
+		assert_eq!(truncated.forward(&inputs), layer.forward(&inputs));
+
+		// The output of the last step only reaches back to the start of its window
+		let trace = LayerTrace { output: layer.forward(&inputs), input: inputs, cache: Vec::new() };
+		let grad = Matrix::row_vector(vec![1.0; 4]);
+		let (mut full, mut cut) = (layer.zero_gradient(), layer.zero_gradient());
+		let full_inputs = layer.backward(&trace, &grad, &mut full);
+		let cut_inputs = truncated.backward(&trace, &grad, &mut cut);
+		assert!(full_inputs.data().iter().all(|g| *g != 0.0));
+		assert_eq!(&cut_inputs.data()[..3], &[0.0; 3]);
+		assert_eq!(&cut_inputs.data()[3..], &full_inputs.data()[3..]);
+		assert_ne!(cut, full);
+	}
+
+	#[test]
+	fn test_character_language_model() {
+		let corpus = "the cat sat on the mat. ".repeat(4);
+		let vocabulary = Vocabulary::from_text(&corpus);
+		let sequences = vocabulary.next_char_sequences(&corpus, 12);
+		for cell in CELLS {
+			let mut rng = StdRng::seed_from_u64(3);
+			let mut network = RecurrentNetwork::language_model(cell, vocabulary.len(), &[24], &mut rng)
+				.with_optimizer(Adam::default())
+				.with_learning_rate(0.02)
+				.with_truncation(6)
+				.with_clip_norm(5.0);
+			let dataset = network.dataset(&sequences).unwrap();
+			let history = network.fit(&dataset, 60, 1, false).unwrap();
+			let losses = history.losses();
+			assert!(losses[losses.len() - 1] < losses[0] / 5.0, "{:?} {:?}", cell, losses);
+			assert_eq!(network.generate(&vocabulary, "the c", 8).unwrap(), "the cat sat o", "{:?}", cell);
+		}
+	}
+
+	#[test]
+	fn test_variable_length_sequence_to_one() {
+		// Whether a 1 appears anywhere in sequences of 2 to 6 steps
+		let mut rng = StdRng::seed_from_u64(4);
+		let sequences: Vec<Sequence> = (0..40).map(|_| {
+			let length = rng.gen_range(2..7);
+			let inputs: Vec<Vec<f64>> = (0..length).map(|_| vec![if rng.gen_bool(0.15) { 1.0 } else { 0.0 }]).collect();
+			let seen = inputs.iter().any(|x| x[0] == 1.0);
+			(inputs, vec![vec![if seen { 1.0 } else { 0.0 }]])
+		}).collect();
+
+		let mut network = RecurrentNetwork::new(Cell::Gru, &[1, 8, 1], &mut rng)
+			.with_mode(SequenceMode::SequenceToOne)
+			.with_output_activation(Activation::Sigmoid)
+			.with_loss(LossFunction::BinaryCrossEntropy)
+			.with_optimizer(Adam::default())
+			.with_learning_rate(0.02);
+		let dataset = network.dataset(&sequences).unwrap().with_seed(5);
+		network.fit(&dataset, 80, 4, true).unwrap();
+		for (inputs, targets) in &sequences {
+			let output = network.predict(inputs).unwrap();
+			assert_eq!(output[0] > 0.5, targets[0][0] == 1.0, "{:?}", inputs);
+		}
+	}
+
+	#[test]
+	fn test_seeded_fit() {
+		let mut rng = StdRng::seed_from_u64(6);
+		let sequences: Vec<Sequence> = (0..6).map(|i| {
+			let inputs: Vec<Vec<f64>> = (0..4).map(|t| vec![((i + t) % 3) as f64, 1.0]).collect();
+			(inputs, vec![vec![i as f64 / 6.0]])
+		}).collect();
+		let network = RecurrentNetwork::new(Cell::Lstm, &[2, 4, 1], &mut rng).with_mode(SequenceMode::SequenceToOne);
+		let dataset = network.dataset(&sequences).unwrap().with_seed(7);
+		let (mut first, mut second) = (network.clone().with_threads(2), network.clone().with_threads(2));
+		let history = first.fit(&dataset, 5, 2, true).unwrap();
+		assert_eq!(second.fit(&dataset, 5, 2, true).unwrap(), history);
+		for (inputs, _) in &sequences {
+			assert_eq!(first.predict(inputs).unwrap(), second.predict(inputs).unwrap());
+		}
+	}
+
+	#[test]
+	fn test_embedding_stack() {
+		// Token sequences go through an embedding into a sequence-to-one layer
+		let mut rng = StdRng::seed_from_u64(8);
+		let layers: Vec<Box<dyn Layer>> = vec![
+			Box::new(Embedding::from_vectors(Matrix::new(5, 3, (0..15).map(|_| rng.gen_range(-1.0..1.0)).collect()), 4)),
+			Box::new(Recurrent::new(Cell::Gru, 3, 4, &mut rng).with_mode(SequenceMode::SequenceToOne)),
+			Box::new(Dense::new(2, 4, Activation::Softmax, Initializer::XavierUniform, &mut rng)),
+		];
+		let network = NeuralNetwork::from_layers(layers).unwrap().with_loss(LossFunction::CategoricalCrossEntropy);
+		let batch = vec![(vec![0.0, 1.0, 4.0, 2.0], vec![1.0, 0.0]), (vec![3.0, 3.0, 0.0, 1.0], vec![0.0, 1.0])];
+		let check = gradient_check_batch(&network, &batch, 1e-5).unwrap();
+		assert!(check.max_relative_error < 1e-5, "{:?}", check.worst());
+
+		let json = serde_json::to_string(&network).unwrap();
+		let loaded: NeuralNetwork = serde_json::from_str(&json).unwrap();
+		assert_eq!(loaded.predict(batch[0].0.clone()).unwrap(), network.predict(batch[0].0.clone()).unwrap());
+		assert_eq!(network.predict(vec![0.0, 1.0]), Err(NetworkError::InputSize { expected: 4, found: 2 }));
+
+		let mismatched: Vec<Box<dyn Layer>> = vec![
+			Box::new(Embedding::new(5, 3, 4, &mut rng)),
+			Box::new(Recurrent::new(Cell::Elman, 5, 4, &mut rng)),
+		];
+		assert_eq!(NeuralNetwork::from_layers(mismatched).err(), Some(NetworkError::LayerSize { layer: 1, expected: 12, found: 5 }));
+	}
+
+	#[test]
+	fn test_errors_and_persistence() {
+		let mut rng = StdRng::seed_from_u64(5);
+		let network = RecurrentNetwork::new(Cell::Lstm, &[2, 3, 1], &mut rng).with_truncation(2);
+		assert_eq!(network.predict(&[]), Err(NetworkError::EmptySequence));
+		assert_eq!(network.predict(&[vec![1.0]]), Err(NetworkError::InputSize { expected: 2, found: 1 }));
+		let inputs = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0]];
+		assert_eq!(network.dataset(&[(inputs.clone(), vec![vec![1.0]])]).err(), Some(NetworkError::TargetCount { expected: 3, found: 1 }));
+
+		let path = "test_recurrent_network.json";
+		network.save_to_file(path, false).unwrap();
+		let loaded = RecurrentNetwork::load_from_file(path).unwrap();
+		std::fs::remove_file(path).unwrap();
+		assert_eq!(loaded.predict_sequence(&inputs).unwrap(), network.predict_sequence(&inputs).unwrap());
+		let layer = loaded.network().layers[0].downcast_ref::<Recurrent>().unwrap();
+		assert_eq!(layer.truncation, Some(2));
+		assert_eq!(layer, network.network().layers[0].downcast_ref::<Recurrent>().unwrap());
+
+		let json = r#"{"cell": "Gru", "input_weights": {"rows": 3, "cols": 1, "data": [0, 0, 0]}, "hidden_weights": {"rows": 3, "cols": 2, "data": [0, 0, 0, 0, 0, 0]}, "biases": [0, 0, 0]}"#;
+		assert!(serde_json::from_str::<Recurrent>(json).unwrap_err().to_string().contains("Expected 6x1 input weights, got 3x1"));
+		let json = serde_json::to_string(&network.network().layers[0]).unwrap();
+		assert!(serde_json::from_str::<RecurrentNetwork>(&format!(r#"{{"network": {{"layers": [{}]}}}}"#, json)).is_err());
+	}
+}
@@ -0,0 +1,89 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Regularization {
+	/* Weight penalties added to the training loss and a constraint applied after every update.
+	Biases are left unregularized. */
+	pub l1: f64,
+	pub l2: f64,
+	// Upper bound on the L2 norm of every neuron's incoming weights
+	pub max_norm: Option<f64>,
+}
+
+impl Regularization {
+	pub fn l1(l1: f64) -> Self {
+		return Self { l1, ..Self::default() };
+	}
+
+	pub fn l2(l2: f64) -> Self {
+		return Self { l2, ..Self::default() };
+	}
+
+	pub fn max_norm(max_norm: f64) -> Self {
+		return Self { max_norm: Some(max_norm), ..Self::default() };
+	}
+
+	pub fn is_none(&self) -> bool {
+		return self.l1 == 0.0 && self.l2 == 0.0 && self.max_norm.is_none();
+	}
+
+	pub fn penalty(&self, weights: &[f64]) -> f64 {
+		/* l1 * sum(|w|) + l2 * sum(w^2) */
+		if self.l1 == 0.0 && self.l2 == 0.0 {
+			return 0.0;
+		}
+		return weights.iter().map(|w| self.l1 * w.abs() + self.l2 * w * w).sum();
+	}
+
+	pub fn add_gradient(&self, weights: &[f64], gradient: &mut [f64]) {
+		/* Adds the derivative of penalty() to the weight gradient */
+		if self.l1 == 0.0 && self.l2 == 0.0 {
+			return;
+		}
+		for (g, w) in gradient.iter_mut().zip(weights) {
+			let sign = if *w > 0.0 { 1.0 } else if *w < 0.0 { -1.0 } else { 0.0 };
+			*g += self.l1 * sign + 2.0 * self.l2 * w;
+		}
+	}
+
+	pub fn constrain(&self, row: &mut [f64]) {
+		/* Rescales a neuron's incoming weights onto the max-norm ball */
+		if let Some(max_norm) = self.max_norm {
+			let norm = row.iter().map(|w| w * w).sum::<f64>().sqrt();
+			if norm > max_norm {
+				for w in row.iter_mut() {
+					*w *= max_norm / norm;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::compare::approx_eq;
+
+	#[test]
+	fn test_penalty() {
+		let weights = [1.0, -2.0, 0.0];
+		let reg = Regularization { l1: 0.1, l2: 0.01, max_norm: None };
+		assert!(approx_eq(reg.penalty(&weights), 0.3 + 0.05, 1e-12));
+		let mut gradient = [0.0; 3];
+		reg.add_gradient(&weights, &mut gradient);
+		assert!(approx_eq(gradient[0], 0.1 + 0.02, 1e-12));
+		assert!(approx_eq(gradient[1], -0.1 - 0.04, 1e-12));
+		assert_eq!(gradient[2], 0.0);
+	}
+
+	#[test]
+	fn test_max_norm() {
+		let mut row = [3.0, 4.0];
+		Regularization::max_norm(1.0).constrain(&mut row);
+		assert!(approx_eq(row[0], 0.6, 1e-12));
+		assert!(approx_eq(row[1], 0.8, 1e-12));
+		let mut small = [0.3, 0.4];
+		Regularization::max_norm(1.0).constrain(&mut small);
+		assert_eq!(small, [0.3, 0.4]);
+	}
+}
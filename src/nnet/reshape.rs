@@ -0,0 +1,49 @@
+use serde::{Serialize, Deserialize};
+
+use super::layer::Layer;
+use super::matrix::Matrix;
+use super::tape::LayerTrace;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Reshape {
+	/* Reinterprets every sample's values with another shape. Samples are stored flat and
+	row-major, so only the shapes change while the values pass through untouched. */
+	pub input_shape: Vec<usize>,
+	pub output_shape: Vec<usize>,
+}
+
+impl Reshape {
+	pub const NAME: &'static str = "Reshape";
+
+	pub fn new(input_shape: &[usize], output_shape: &[usize]) -> Self {
+		let (from, to) = (input_shape.iter().product::<usize>(), output_shape.iter().product::<usize>());
+		assert_eq!(from, to, "Cannot reshape {:?} into {:?}", input_shape, output_shape);
+		return Self { input_shape: input_shape.to_vec(), output_shape: output_shape.to_vec() };
+	}
+
+	pub fn flatten(input_shape: &[usize]) -> Self {
+		return Self::new(input_shape, &[input_shape.iter().product()]);
+	}
+}
+
+impl Layer for Reshape {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.input_shape.iter().product();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.output_shape.iter().product();
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		return inputs.clone();
+	}
+
+	fn backward(&self, _trace: &LayerTrace, grad: &Matrix, _gradient: &mut [Vec<f64>]) -> Matrix {
+		return grad.clone();
+	}
+}
@@ -0,0 +1,156 @@
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub enum Schedule {
+	#[default]
+	Constant,
+	// Multiplies the rate by gamma every step_size epochs
+	StepDecay { step_size: usize, gamma: f64 },
+	// Multiplies the rate by gamma every epoch
+	ExponentialDecay { gamma: f64 },
+	// Anneals from the base rate to min_rate over period epochs, then restarts
+	// with the period multiplied by period_mult
+	CosineAnnealing { period: usize, period_mult: usize, min_rate: f64 },
+	// Ramps linearly up to the base rate over epochs, then follows the inner schedule
+	LinearWarmup { epochs: usize, then: Box<Schedule> },
+	// Multiplies the rate by factor once the monitored loss has not improved
+	// by more than threshold for patience epochs
+	ReduceOnPlateau { factor: f64, patience: usize, threshold: f64, min_rate: f64, state: PlateauState },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlateauState {
+	best: f64,
+	wait: usize,
+	scale: f64,
+}
+
+impl Default for PlateauState {
+	fn default() -> Self {
+		return Self { best: f64::INFINITY, wait: 0, scale: 1.0 };
+	}
+}
+
+impl Schedule {
+	pub fn reduce_on_plateau(factor: f64, patience: usize) -> Self {
+		return Schedule::ReduceOnPlateau {
+			factor,
+			patience,
+			threshold: 1e-4,
+			min_rate: 0.0,
+			state: PlateauState::default(),
+		};
+	}
+
+	pub fn learning_rate(&mut self, epoch: usize, base_rate: f64, last_loss: Option<f64>) -> f64 {
+		/* Returns the learning rate of a (0-based) epoch.
+		last_loss is the monitored loss of the previous epoch, validation loss when available,
+		and only used by ReduceOnPlateau. */
+		match self {
+			Schedule::Constant => base_rate,
+			Schedule::StepDecay { step_size, gamma } => {
+				base_rate * gamma.powi((epoch / (*step_size).max(1)) as i32)
+			},
+			Schedule::ExponentialDecay { gamma } => base_rate * gamma.powi(epoch as i32),
+			Schedule::CosineAnnealing { period, period_mult, min_rate } => {
+				let (t, length) = cosine_cycle_position(epoch, *period, *period_mult);
+				let progress = t as f64 / length as f64;
+				*min_rate + 0.5 * (base_rate - *min_rate) * (1.0 + (PI * progress).cos())
+			},
+			Schedule::LinearWarmup { epochs, then } => {
+				if epoch < *epochs {
+					base_rate * (epoch + 1) as f64 / *epochs as f64
+				} else {
+					then.learning_rate(epoch - *epochs, base_rate, last_loss)
+				}
+			},
+			Schedule::ReduceOnPlateau { factor, patience, threshold, min_rate, state } => {
+				if let Some(loss) = last_loss {
+					if loss < state.best - *threshold {
+						state.best = loss;
+						state.wait = 0;
+					} else {
+						state.wait += 1;
+						if state.wait >= *patience {
+							state.scale *= *factor;
+							state.wait = 0;
+						}
+					}
+				}
+				(base_rate * state.scale).max(*min_rate)
+			},
+		}
+	}
+}
+
+fn cosine_cycle_position(epoch: usize, period: usize, period_mult: usize) -> (usize, usize) {
+	/* Returns the epoch offset within its restart cycle and the length of that cycle */
+	let mut t = epoch;
+	let mut length = period.max(1);
+	while t >= length {
+		t -= length;
+		length *= period_mult.max(1);
+	}
+	return (t, length);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::compare::approx_eq;
+
+	fn rates(schedule: &mut Schedule, epochs: usize) -> Vec<f64> {
+		return (0..epochs).map(|e| schedule.learning_rate(e, 1.0, None)).collect();
+	}
+
+	#[test]
+	fn test_decay() {
+		let mut step = Schedule::StepDecay { step_size: 2, gamma: 0.5 };
+		assert_eq!(rates(&mut step, 5), vec![1.0, 1.0, 0.5, 0.5, 0.25]);
+		let mut exponential = Schedule::ExponentialDecay { gamma: 0.5 };
+		assert_eq!(rates(&mut exponential, 3), vec![1.0, 0.5, 0.25]);
+	}
+
+	#[test]
+	fn test_cosine_annealing() {
+		let mut cosine = Schedule::CosineAnnealing { period: 4, period_mult: 2, min_rate: 0.0 };
+		let r = rates(&mut cosine, 13);
+		assert!(approx_eq(r[0], 1.0, 1e-12));
+		assert!(approx_eq(r[2], 0.5, 1e-12));
+		// First restart after 4 epochs, second after another 8
+		assert!(approx_eq(r[4], 1.0, 1e-12));
+		assert!(approx_eq(r[8], 0.5, 1e-12));
+		assert!(approx_eq(r[12], 1.0, 1e-12));
+	}
+
+	#[test]
+	fn test_linear_warmup() {
+		let mut warmup = Schedule::LinearWarmup {
+			epochs: 4,
+			then: Box::new(Schedule::ExponentialDecay { gamma: 0.5 }),
+		};
+		assert_eq!(rates(&mut warmup, 6), vec![0.25, 0.5, 0.75, 1.0, 1.0, 0.5]);
+	}
+
+	#[test]
+	fn test_reduce_on_plateau() {
+		let mut plateau = Schedule::reduce_on_plateau(0.1, 2);
+		// Epoch 2 is told of the improvement in epoch 1, epochs 3 and 4 of the two that follow without one
+		let losses = [None, Some(1.0), Some(0.5), Some(0.5), Some(0.5), Some(0.5), Some(0.4)];
+		let r: Vec<f64> = losses.iter().enumerate()
+			.map(|(e, l)| plateau.learning_rate(e, 1.0, *l))
+			.collect();
+		assert!(approx_eq(r[3], 1.0, 1e-12));
+		// The rate drops in the epoch after patience epochs without improvement
+		assert!(approx_eq(r[4], 0.1, 1e-12));
+		assert!(approx_eq(r[5], 0.1, 1e-12));
+		assert!(approx_eq(r[6], 0.1, 1e-12));
+
+		let mut impatient = Schedule::reduce_on_plateau(0.5, 1);
+		let r: Vec<f64> = [None, Some(1.0), Some(1.0), Some(1.0)].iter().enumerate()
+			.map(|(e, l)| impatient.learning_rate(e, 1.0, *l))
+			.collect();
+		assert_eq!(r, vec![1.0, 1.0, 0.5, 0.25]);
+	}
+}
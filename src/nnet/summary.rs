@@ -0,0 +1,31 @@
+use std::fmt;
+
+use super::activation::Activation;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerSummary {
+	pub name: String,
+	pub input_size: usize,
+	pub output_size: usize,
+	pub activation: Option<Activation>,
+	pub parameters: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+	pub layers: Vec<LayerSummary>,
+	pub total_parameters: usize,
+}
+
+impl fmt::Display for Summary {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		/* Prints one row per layer followed by the parameter total */
+		writeln!(f, "{:<8}{:<10}{:<14}{:<18}{:>12}", "Layer", "Type", "Shape", "Activation", "Parameters")?;
+		for (i, layer) in self.layers.iter().enumerate() {
+			let shape = format!("{} -> {}", layer.input_size, layer.output_size);
+			let activation = layer.activation.map_or("-".to_string(), |a| format!("{:?}", a));
+			writeln!(f, "{:<8}{:<10}{:<14}{:<18}{:>12}", i, layer.name, shape, activation, layer.parameters)?;
+		}
+		write!(f, "Total parameters: {}", self.total_parameters)
+	}
+}
@@ -0,0 +1,36 @@
+/*
+Activations recorded by a training forward pass and consumed by backpropagation.
+Kept apart from the network so that inference only needs &self and nothing
+transient ends up in persisted models.
+*/
+
+use super::matrix::Matrix;
+
+pub struct LayerTrace {
+	// One row per sample of the traced batch
+	pub input: Matrix,
+	pub output: Matrix,
+	// Whatever else the layer needs for its backward pass, e.g. weighted inputs or a dropout mask
+	pub cache: Vec<Matrix>,
+}
+
+pub struct Tape {
+	pub traces: Vec<LayerTrace>,
+}
+
+impl Tape {
+	pub fn new() -> Self {
+		return Self { traces: Vec::new() };
+	}
+
+	pub fn output(&self) -> Option<&Matrix> {
+		/* Output of the last recorded layer */
+		return self.traces.last().map(|t| &t.output);
+	}
+}
+
+impl Default for Tape {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,148 @@
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+
+use super::activation::Activation;
+use super::error::NetworkError;
+use super::layer::Layer;
+use super::matrix::Matrix;
+use super::tape::LayerTrace;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TimeDistributed {
+	/* Applies a layer to every step of sequences laid end to end, such as the outputs of a
+	sequence-to-sequence Recurrent layer, with the same parameters at every step. Any number
+	of steps is accepted. */
+	pub layer: Box<dyn Layer>,
+}
+
+impl TimeDistributed {
+	pub const NAME: &'static str = "TimeDistributed";
+
+	pub fn new(layer: impl Layer + 'static) -> Self {
+		return Self { layer: Box::new(layer) };
+	}
+
+	fn steps(&self, values: &Matrix) -> Matrix {
+		/* One row per step of every sample */
+		let size = self.layer.input_size();
+		return values.clone().reshape(values.rows() * values.cols() / size, size);
+	}
+
+	fn step_trace(&self, trace: &LayerTrace) -> LayerTrace {
+		/* The trace of the wrapped layer, whose cache is kept as is */
+		let output = trace.output.clone().reshape(trace.output.data().len() / self.layer.output_size(), self.layer.output_size());
+		return LayerTrace { input: self.steps(&trace.input), output, cache: trace.cache.clone() };
+	}
+}
+
+impl Layer for TimeDistributed {
+	fn name(&self) -> &'static str {
+		return Self::NAME;
+	}
+
+	fn input_size(&self) -> usize {
+		return self.layer.input_size();
+	}
+
+	fn output_size(&self) -> usize {
+		return self.layer.output_size();
+	}
+
+	fn output_size_for(&self, input_size: usize) -> Option<usize> {
+		if input_size == 0 || !input_size.is_multiple_of(self.input_size()) {
+			return None;
+		}
+		return Some(input_size / self.input_size() * self.output_size());
+	}
+
+	fn activation(&self) -> Option<Activation> {
+		return self.layer.activation();
+	}
+
+	fn check_input(&self, input: &[f64]) -> Result<(), NetworkError> {
+		for step in input.chunks(self.input_size()) {
+			self.layer.check_input(step)?;
+		}
+		return Ok(());
+	}
+
+	fn forward(&self, inputs: &Matrix) -> Matrix {
+		let outputs = self.layer.forward(&self.steps(inputs));
+		let cols = outputs.data().len() / inputs.rows().max(1);
+		return outputs.reshape(inputs.rows(), cols);
+	}
+
+	fn trace(&self, inputs: Matrix, rng: &mut dyn RngCore) -> LayerTrace {
+		let trace = self.layer.trace(self.steps(&inputs), rng);
+		let cols = trace.output.data().len() / inputs.rows().max(1);
+		return LayerTrace { output: trace.output.reshape(inputs.rows(), cols), input: inputs, cache: trace.cache };
+	}
+
+	fn backward(&self, trace: &LayerTrace, grad: &Matrix, gradient: &mut [Vec<f64>]) -> Matrix {
+		let grad = grad.clone().reshape(grad.data().len() / self.output_size(), self.output_size());
+		let input_grad = self.layer.backward(&self.step_trace(trace), &grad, gradient);
+		return input_grad.reshape(trace.input.rows(), trace.input.cols());
+	}
+
+	fn parameters(&self) -> Vec<&[f64]> {
+		return self.layer.parameters();
+	}
+
+	fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+		return self.layer.parameters_mut();
+	}
+
+	fn penalty(&self) -> f64 {
+		return self.layer.penalty();
+	}
+
+	fn add_penalty_gradient(&self, gradient: &mut [Vec<f64>]) {
+		self.layer.add_penalty_gradient(gradient);
+	}
+
+	fn apply_constraints(&mut self) {
+		self.layer.apply_constraints();
+	}
+
+	fn update_statistics(&mut self, traces: &[&LayerTrace]) {
+		let traces: Vec<LayerTrace> = traces.iter().map(|t| self.step_trace(t)).collect();
+		self.layer.update_statistics(&traces.iter().collect::<Vec<_>>());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::nnet::dense::Dense;
+
+	#[test]
+	fn test_steps_share_the_layer() {
+		let dense = Dense {
+			weights: Matrix::from_rows(&[vec![1.0, 2.0], vec![0.0, -1.0], vec![1.0, 1.0]]),
+			biases: vec![0.5, 0.0, -1.0],
+			activation: Activation::Identity,
+			regularization: Default::default(),
+		};
+		let layer = TimeDistributed::new(dense.clone());
+		assert_eq!(layer.output_size_for(6), Some(9));
+		assert_eq!(layer.output_size_for(5), None);
+		assert_eq!(layer.output_size_for(0), None);
+
+		let inputs = Matrix::from_rows(&[vec![1.0, 0.0, 0.0, 1.0, 2.0, 2.0], vec![-1.0, 1.0, 3.0, 0.0, 0.0, 0.0]]);
+		let outputs = layer.forward(&inputs);
+		for r in 0..2 {
+			for step in 0..3 {
+				let expected = dense.forward(&Matrix::row_vector(inputs.row(r)[2 * step..2 * step + 2].to_vec()));
+				assert_eq!(&outputs.row(r)[3 * step..3 * step + 3], expected.data());
+			}
+		}
+
+		// Every step adds to the gradient of the shared parameters
+		let trace = layer.trace(inputs.clone(), &mut rand::thread_rng());
+		let grad = Matrix::new(2, 9, vec![1.0; 18]);
+		let mut gradient = layer.zero_gradient();
+		let input_grad = layer.backward(&trace, &grad, &mut gradient);
+		assert_eq!(gradient[1], vec![6.0; 3]);
+		assert_eq!(input_grad.row(0), &[2.0, 2.0, 2.0, 2.0, 2.0, 2.0]);
+	}
+}
@@ -0,0 +1,108 @@
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+pub type Sample = (Vec<f64>, Vec<f64>);
+
+pub struct Dataset {
+	pub samples: Vec<Sample>,
+	pub validation: Option<Vec<Sample>>,
+	seed: Option<u64>,
+}
+
+impl Dataset {
+	pub fn new(samples: Vec<Sample>) -> Self {
+		return Self { samples, validation: None, seed: None };
+	}
+
+	pub fn with_validation(mut self, validation: Vec<Sample>) -> Self {
+		/* Held-out samples evaluated after every epoch */
+		self.validation = Some(validation);
+		return self;
+	}
+
+	pub fn with_seed(mut self, seed: u64) -> Self {
+		/* Seeds the per-epoch shuffling so that training runs are reproducible */
+		self.seed = Some(seed);
+		return self;
+	}
+
+	pub fn len(&self) -> usize {
+		return self.samples.len();
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.samples.is_empty();
+	}
+
+	pub(crate) fn rng(&self) -> StdRng {
+		return match self.seed {
+			Some(seed) => StdRng::seed_from_u64(seed),
+			None => StdRng::from_entropy(),
+		};
+	}
+
+	pub(crate) fn batches(&self, rng: &mut StdRng, batch_size: usize, shuffle: bool) -> Vec<Vec<usize>> {
+		/* Splits the sample indices into batches of batch_size, the last one possibly smaller */
+		let mut indices: Vec<usize> = (0..self.samples.len()).collect();
+		if shuffle {
+			indices.shuffle(rng);
+		}
+		return indices.chunks(batch_size.max(1)).map(|c| c.to_vec()).collect();
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpochRecord {
+	pub epoch: usize,
+	pub loss: f64,
+	pub validation_loss: Option<f64>,
+	pub learning_rate: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct History {
+	pub epochs: Vec<EpochRecord>,
+}
+
+impl History {
+	pub fn losses(&self) -> Vec<f64> {
+		return self.epochs.iter().map(|e| e.loss).collect();
+	}
+
+	pub fn validation_losses(&self) -> Vec<f64> {
+		return self.epochs.iter().filter_map(|e| e.validation_loss).collect();
+	}
+
+	pub fn learning_rates(&self) -> Vec<f64> {
+		return self.epochs.iter().map(|e| e.learning_rate).collect();
+	}
+
+	pub fn last(&self) -> Option<&EpochRecord> {
+		return self.epochs.last();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_batches() {
+		let samples = (0..10).map(|i| (vec![i as f64], vec![0.0])).collect();
+		let dataset = Dataset::new(samples).with_seed(7);
+		let mut rng = dataset.rng();
+		let batches = dataset.batches(&mut rng, 4, false);
+		assert_eq!(batches, vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+
+		let shuffled = dataset.batches(&mut rng, 4, true);
+		let mut indices: Vec<usize> = shuffled.concat();
+		indices.sort();
+		assert_eq!(indices, (0..10).collect::<Vec<usize>>());
+
+		// The same seed reproduces the same order
+		let mut rng2 = dataset.rng();
+		dataset.batches(&mut rng2, 4, false);
+		assert_eq!(dataset.batches(&mut rng2, 4, true), shuffled);
+	}
+}
@@ -13,3 +13,17 @@ pub fn dot(v1: &[f64], v2: &[f64]) -> f64 {
 	return v1.iter().zip(v2.iter()).map(|(a,b)| a * b).sum();
 }
 
+pub fn log_sum_exp(v: &[f64]) -> f64 {
+	/* Numerically stable ln(sum(exp(v))), shifting by the maximum before exponentiating */
+	let max = v.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+	if !max.is_finite() {
+		return max;
+	}
+	return max + v.iter().map(|x| (x - max).exp()).sum::<f64>().ln();
+}
+
+pub fn softmax(v: &[f64]) -> Vec<f64> {
+	/* Normalizes v into a probability distribution */
+	let lse = log_sum_exp(v);
+	return v.iter().map(|x| (x - lse).exp()).collect();
+}
@@ -0,0 +1,99 @@
+use serde::{Serialize, Deserialize};
+
+use crate::persist::json::JsonPersist;
+use super::recurrent::Sequence;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Vocabulary {
+	/* Characters of a corpus, each identified by its token id, its index in sorted order.
+	Turns text into one-hot sequences for character level models and predictions back into text. */
+	symbols: Vec<char>,
+}
+
+impl JsonPersist for Vocabulary {}
+
+impl Vocabulary {
+	pub fn from_text(text: &str) -> Self {
+		let mut symbols: Vec<char> = text.chars().collect();
+		symbols.sort_unstable();
+		symbols.dedup();
+		return Self { symbols };
+	}
+
+	pub fn len(&self) -> usize {
+		return self.symbols.len();
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.symbols.is_empty();
+	}
+
+	pub fn id(&self, symbol: char) -> Option<usize> {
+		return self.symbols.binary_search(&symbol).ok();
+	}
+
+	pub fn symbol(&self, id: usize) -> Option<char> {
+		return self.symbols.get(id).copied();
+	}
+
+	pub fn one_hot(&self, symbol: char) -> Option<Vec<f64>> {
+		let id = self.id(symbol)?;
+		let mut encoded = vec![0.0; self.len()];
+		encoded[id] = 1.0;
+		return Some(encoded);
+	}
+
+	pub fn encode(&self, text: &str) -> Vec<Vec<f64>> {
+		/* One-hot vector of every char of text, chars outside the vocabulary are skipped */
+		return text.chars().filter_map(|c| self.one_hot(c)).collect();
+	}
+
+	pub fn ids(&self, text: &str) -> Vec<f64> {
+		/* Token id of every char of text, as the inputs of an Embedding layer.
+		Chars outside the vocabulary are skipped. */
+		return text.chars().filter_map(|c| self.id(c)).map(|id| id as f64).collect();
+	}
+
+	pub fn decode(&self, output: &[f64]) -> Option<char> {
+		/* The most likely char of a distribution over the vocabulary */
+		let best = output.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+		return self.symbol(best.0);
+	}
+
+	pub fn next_char_sequences(&self, text: &str, length: usize) -> Vec<Sequence> {
+		/* Splits text into sequences of up to length chars, each step targeting the char that follows it */
+		let encoded = self.encode(text);
+		if encoded.len() < 2 {
+			return Vec::new();
+		}
+		let steps = encoded.len() - 1;
+		return (0..steps).step_by(length.max(1)).map(|start| {
+			let end = (start + length.max(1)).min(steps);
+			(encoded[start..end].to_vec(), encoded[start + 1..end + 1].to_vec())
+		}).collect();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_vocabulary() {
+		let vocabulary = Vocabulary::from_text("banana");
+		assert_eq!(vocabulary.len(), 3);
+		assert_eq!(vocabulary.id('n'), Some(2));
+		assert_eq!(vocabulary.id('x'), None);
+		assert_eq!(vocabulary.one_hot('b'), Some(vec![0.0, 1.0, 0.0]));
+		assert_eq!(vocabulary.encode("bxa").len(), 2);
+		assert_eq!(vocabulary.ids("nab"), vec![2.0, 0.0, 1.0]);
+		assert_eq!(vocabulary.decode(&[0.1, 0.2, 0.7]), Some('n'));
+
+		let sequences = vocabulary.next_char_sequences("banana", 2);
+		assert_eq!(sequences.len(), 3);
+		assert_eq!(sequences[0].0, vocabulary.encode("ba"));
+		assert_eq!(sequences[0].1, vocabulary.encode("an"));
+		assert_eq!(sequences[2].0, vocabulary.encode("n"));
+		assert_eq!(sequences[2].1, vocabulary.encode("a"));
+	}
+}
@@ -6,7 +6,7 @@ pub trait JsonPersist: Serialize + DeserializeOwned + Sized {
 	/*
 	Json persistance of struct fields.
 
-	Note persisting f64 to json may entail small rounding differences.
+	f64 values round-trip exactly (serde_json float_roundtrip), so saved state can be resumed bit for bit.
 	*/
 	fn save_to_file(&self, path: &str, pretty:bool) -> Result<()> {
 		let file = File::create(path)?;
@@ -5,8 +5,12 @@ impl Fp8 {
     const EXP_BITS: u8 = 4;
     const MANT_BITS: u8 = 3;
     const EXP_BIAS: i8 = 7;
-    const MAX_EXP: u8 = 0x0F;
+    const MAX_EXP: u8 = (1 << Self::EXP_BITS) - 1;
     const MAX_MANT: u8 = 0x07;
+    const MIN_SUBNORMAL: f32 = 1.0 / 512.0;
+
+    /// Largest finite value, 1.875 * 2^7
+    pub const MAX_FINITE: f32 = 240.0;
 
     /// Create from raw bits (e.g., from storage)
     pub fn from_bits(bits: u8) -> Self {
@@ -18,7 +22,8 @@ impl Fp8 {
         self.0
     }
 
-    /// Convert f32 → fp8
+    /// Convert f32 → fp8, rounding to the nearest representable value.
+    /// Finite values beyond the range saturate to ±MAX_FINITE.
     pub fn from_f32(x: f32) -> Self {
         if x.is_nan() {
             return Self(0b0111_1111); // Quiet NaN
@@ -31,28 +36,33 @@ impl Fp8 {
         }
 
         if x == 0.0 {
-            return Self((sign << 7)); // Zero
+            return Self(sign << 7); // Zero
         }
 
         let abs = x.abs();
-        let exp = abs.log2().floor() as i8;
-        let biased_exp = exp + Self::EXP_BIAS;
-
-        if biased_exp <= 0 {
-            // Subnormal: treat as zero
-            return Self((sign << 7)); // Optional: emulate subnormals
+        if abs >= Self::MAX_FINITE {
+            return Self((sign << 7) | ((Self::MAX_EXP - 1) << 3) | Self::MAX_MANT);
         }
 
-        if biased_exp >= 0x0F {
-            // Clamp to max finite value
-            let bits = (sign << 7) | (Self::MAX_EXP << 3) | Self::MAX_MANT;
-            return Self(bits);
+        let mut exp = abs.log2().floor() as i32;
+        if exp + (Self::EXP_BIAS as i32) <= 0 {
+            // Subnormal: mant/8 * 2^(1 - bias), rounding up to the smallest normal
+            let mant = (abs / Self::MIN_SUBNORMAL).round() as u8;
+            return Self((sign << 7) | mant.min(1 << Self::MANT_BITS));
         }
 
-        let mant = abs / 2f32.powi(exp as i32) - 1.0;
-        let mant_scaled = (mant * 8.0).round() as u8;
+        let mut mant_scaled = ((abs / 2f32.powi(exp) - 1.0) * 8.0).round() as u8;
+        if mant_scaled > Self::MAX_MANT {
+            // Rounded up to the next power of two
+            mant_scaled = 0;
+            exp += 1;
+        }
+        let biased_exp = (exp + Self::EXP_BIAS as i32) as u8;
+        if biased_exp >= Self::MAX_EXP {
+            return Self((sign << 7) | ((Self::MAX_EXP - 1) << 3) | Self::MAX_MANT);
+        }
 
-        let bits = (sign << 7) | ((biased_exp as u8) << 3) | (mant_scaled & 0x07);
+        let bits = (sign << 7) | (biased_exp << 3) | mant_scaled;
         Self(bits)
     }
 
@@ -69,8 +79,8 @@ impl Fp8 {
                 if mant == 0 {
                     sign * 0.0
                 } else {
-                    // Subnormal (not handled properly yet — approximated)
-                    sign * (mant as f32 / 64.0)
+                    // Subnormal
+                    sign * (mant as f32 * Self::MIN_SUBNORMAL)
                 }
             }
             0x0F => {
@@ -124,3 +134,40 @@ impl From<Fp8> for f64 {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for x in [0.0, 1.0, -1.5, 0.25, 3.75, 240.0, -0.015625] {
+            assert_eq!(Fp8::from_f32(x).to_f32(), x);
+        }
+        // Every finite encoding decodes and re-encodes to itself
+        for bits in 0..=255u8 {
+            let fp = Fp8::from_bits(bits);
+            if fp.to_f32().is_finite() && fp.to_f32() != 0.0 {
+                assert_eq!(Fp8::from_f32(fp.to_f32()), fp, "{:#010b}", bits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rounding() {
+        // 3 mantissa bits keep values within 1/16 relative error
+        for i in 1..1000 {
+            let x = i as f32 * 0.173;
+            if x < Fp8::MAX_FINITE {
+                assert!((Fp8::from_f32(x).to_f32() - x).abs() <= x / 16.0, "{}", x);
+            }
+        }
+        // Mantissas rounding up carry into the exponent
+        assert_eq!(Fp8::from_f32(1.99).to_f32(), 2.0);
+        assert_eq!(Fp8::from_f32(1e6).to_f32(), Fp8::MAX_FINITE);
+        assert_eq!(Fp8::from_f32(-1e6).to_f32(), -Fp8::MAX_FINITE);
+        assert!(Fp8::from_f32(f32::INFINITY).to_f32().is_infinite());
+        assert!(Fp8::from_f32(f32::NAN).to_f32().is_nan());
+        assert_eq!(Fp8::from_f32(3.0 / 512.0).to_f32(), 3.0 / 512.0);
+        assert_eq!(Fp8::from_f32(1e-9).to_f32(), 0.0);
+    }
+}